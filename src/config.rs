@@ -1,6 +1,9 @@
+use chrono::{DateTime, Local, NaiveTime};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
+use tracing::info;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
@@ -11,6 +14,12 @@ pub struct Config {
     pub sync: SyncConfig,
     #[serde(default)]
     pub image: ImageConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub watermark: WatermarkConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -18,6 +27,134 @@ pub struct ImmichConfig {
     pub url: String,
     #[serde(flatten)]
     pub auth: AuthConfig,
+    /// Page size for album listings; 0 fetches everything in a single request
+    #[serde(default)]
+    pub album_page_size: u32,
+    /// Safety cap on the number of album pages fetched per listing
+    #[serde(default = "default_max_album_pages")]
+    pub max_album_pages: u32,
+    /// What to do when the startup API probe finds a missing endpoint
+    #[serde(default)]
+    pub api_probe: ApiProbeMode,
+    /// Timeout for JSON API calls (album listings, searches, probes)
+    #[serde(default = "default_api_timeout_secs")]
+    pub api_timeout_secs: u64,
+    /// Timeout for a whole original download, including the body
+    #[serde(default = "default_download_timeout_secs")]
+    pub download_timeout_secs: u64,
+    /// Timeout for establishing a TCP/TLS connection
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// Idle keep-alive connections kept open to the Immich server
+    #[serde(default = "default_pool_max_idle")]
+    pub pool_max_idle: usize,
+    /// Requests in flight to the Immich server at once, across all albums and both
+    /// API calls and downloads; 0 for no limit
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    /// Tries per request before a 5xx or connection error is reported
+    #[serde(default = "default_retry_attempts")]
+    pub retry_attempts: u32,
+    /// Wait before the first retry, doubled for each one after
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// Proxy URL for all Immich requests. Without it, `HTTP_PROXY`, `HTTPS_PROXY`
+    /// and `NO_PROXY` from the environment apply.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Accept any TLS certificate, for self-signed Immich instances
+    #[serde(default)]
+    pub insecure_skip_tls_verify: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiProbeMode {
+    /// Abort with an error naming the missing endpoint
+    #[default]
+    Strict,
+    /// Log the missing endpoint and continue
+    Warn,
+    /// Skip the probe entirely
+    Off,
+}
+
+impl std::str::FromStr for ApiProbeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(ApiProbeMode::Strict),
+            "warn" => Ok(ApiProbeMode::Warn),
+            "off" => Ok(ApiProbeMode::Off),
+            other => Err(anyhow::anyhow!("Unknown API probe mode: {}", other)),
+        }
+    }
+}
+
+/// Replace `field` with the value of the environment variable `name`, when set
+fn override_from_env<T>(field: &mut T, name: &str) -> anyhow::Result<()>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    if let Ok(value) = env::var(name) {
+        *field = value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid {} '{}': {}", name, value, e))?;
+    }
+    Ok(())
+}
+
+/// Like `override_from_env`, where an empty value clears the field
+fn override_option_from_env<T>(field: &mut Option<T>, name: &str) -> anyhow::Result<()>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) if value.is_empty() => *field = None,
+        Ok(value) => {
+            let parsed = value
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid {} '{}': {}", name, value, e))?;
+            *field = Some(parsed);
+        }
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// Replace a list with the comma-separated environment variable `name`, when set
+fn override_list_from_env(field: &mut Vec<String>, name: &str) {
+    if let Ok(value) = env::var(name) {
+        *field = value
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect();
+    }
+}
+
+/// `Name: value` pairs separated by `;`, as in `SERVER_RESPONSE_HEADERS`
+fn parse_header_list(value: &str, name: &str) -> anyhow::Result<HashMap<String, String>> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (header, value) = entry.split_once(':').ok_or_else(|| {
+                anyhow::anyhow!("Invalid {} entry '{}': expected `Name: value`", name, entry)
+            })?;
+            Ok((header.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+fn default_max_album_pages() -> u32 {
+    100
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -33,6 +170,12 @@ pub enum AuthConfig {
         auth_url: String,
         redirect_uri: String,
     },
+    /// Read-only access to a single shared link (`/share/<key>` in Immich)
+    #[serde(rename = "shared_link")]
+    SharedLink {
+        #[serde(rename = "shared_link_key")]
+        key: String,
+    },
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -44,6 +187,264 @@ pub struct StorageConfig {
     pub avif_dir: String,
     #[serde(default = "default_db_name")]
     pub db_name: String,
+    /// SQLite journal mode; WAL lets the server read while a sync writes
+    #[serde(default)]
+    pub db_journal_mode: JournalMode,
+    /// How long a query waits for a locked database before failing
+    #[serde(default = "default_db_busy_timeout_ms")]
+    pub db_busy_timeout_ms: u64,
+    /// Location of each AVIF inside `avif_dir`; thumbnails get a `_thumb` suffix
+    #[serde(default)]
+    pub path_template: PathTemplate,
+    /// Cap on originals plus AVIFs, enforced after each convert run; 0 = unlimited
+    #[serde(default)]
+    pub max_total_bytes: u64,
+    /// What to delete first when over `max_total_bytes`
+    #[serde(default)]
+    pub eviction_order: EvictionOrder,
+    /// Store AVIFs and thumbnails once per distinct content under `blobs/` instead of
+    /// at `path_template`; unreferenced blobs are removed by the `clean` command
+    #[serde(default)]
+    pub content_addressed: bool,
+    /// Free space to leave on the `base_path` disk; downloads that would go below it
+    /// fail instead. 0 = no check
+    #[serde(default)]
+    pub min_free_bytes: u64,
+    /// Write `{avif_dir}/{album_id}/manifest.json` after each convert run that
+    /// changed the album
+    #[serde(default)]
+    pub write_manifests: bool,
+    /// Separator splitting album names into nested folders, e.g. `" / "` for
+    /// `Trips / 2023 / Japan`. `{album_name}` then expands to one directory per level.
+    #[serde(default)]
+    pub split_album_name_on: Option<String>,
+    /// Base64 of a 256-bit key; originals are then stored AES-256-GCM encrypted
+    #[serde(default)]
+    pub encryption_key: Option<String>,
+    /// File holding `encryption_key`, used when that is unset
+    #[serde(default)]
+    pub encryption_key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionOrder {
+    /// Whole albums, least recently synced first
+    #[default]
+    OldestAlbum,
+    /// Single images, oldest `synced_at` first
+    OldestImage,
+}
+
+impl std::str::FromStr for EvictionOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "oldest_album" => Ok(EvictionOrder::OldestAlbum),
+            "oldest_image" => Ok(EvictionOrder::OldestImage),
+            other => Err(anyhow::anyhow!("Unknown eviction order: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalMode {
+    #[default]
+    Wal,
+    /// SQLite's own default, which blocks readers during writes
+    Delete,
+    Truncate,
+    Persist,
+}
+
+impl std::str::FromStr for JournalMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "wal" => Ok(JournalMode::Wal),
+            "delete" => Ok(JournalMode::Delete),
+            "truncate" => Ok(JournalMode::Truncate),
+            "persist" => Ok(JournalMode::Persist),
+            other => Err(anyhow::anyhow!("Unknown journal mode: {}", other)),
+        }
+    }
+}
+
+/// Tokens accepted in `path_template`
+const PATH_TOKENS: &[&str] = &["album_name", "album_id", "filename", "id", "yyyy"];
+
+/// Relative output path with `{token}` placeholders, e.g. `{album_name}/{filename}.avif`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PathTemplate(String);
+
+/// Values substituted into a `PathTemplate`
+pub struct PathVars<'a> {
+    pub album_name: &'a str,
+    /// `split_album_name_on`, nesting `{album_name}` when set
+    pub album_separator: Option<&'a str>,
+    pub album_id: &'a str,
+    /// Original filename without its extension
+    pub filename: &'a str,
+    pub id: &'a str,
+    pub year: Option<i32>,
+}
+
+impl PathTemplate {
+    pub fn contains_token(&self, token: &str) -> bool {
+        self.0.contains(&format!("{{{}}}", token))
+    }
+
+    /// Substitute the tokens, each sanitized into a single safe path component
+    pub fn render(&self, vars: &PathVars) -> PathBuf {
+        let mut out = String::with_capacity(self.0.len() + 64);
+        let mut rest = self.0.as_str();
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            // Validated on parse: every `{` starts a known, closed token
+            let close = rest[open..].find('}').map_or(rest.len(), |i| open + i);
+            let value = match &rest[open + 1..close] {
+                "album_name" => split_album_name(vars.album_name, vars.album_separator)
+                    .into_iter()
+                    .map(sanitize_path_component)
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                "album_id" => sanitize_path_component(vars.album_id),
+                "filename" => sanitize_path_component(vars.filename),
+                "id" => sanitize_path_component(vars.id),
+                "yyyy" => vars
+                    .year
+                    .map(|y| format!("{:04}", y))
+                    .unwrap_or_else(|| "unknown".to_string()),
+                _ => String::new(),
+            };
+            out.push_str(&value);
+            rest = &rest[(close + 1).min(rest.len())..];
+        }
+        out.push_str(rest);
+        PathBuf::from(out)
+    }
+}
+
+/// Levels of an album name split on `separator`, trimmed and without empty parts;
+/// the whole name when there is no separator or nothing is left after splitting
+pub fn split_album_name<'a>(name: &'a str, separator: Option<&str>) -> Vec<&'a str> {
+    let parts: Vec<&str> = match separator.filter(|s| !s.is_empty()) {
+        Some(separator) => name
+            .split(separator)
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .collect(),
+        None => Vec::new(),
+    };
+    if parts.is_empty() {
+        vec![name]
+    } else {
+        parts
+    }
+}
+
+/// Make a value safe as one path component on any common filesystem
+fn sanitize_path_component(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Leading dots would hide the entry (or form `..`); trailing dots and spaces
+    // are stripped by Windows
+    let trimmed = cleaned
+        .trim_start_matches(['.', ' '])
+        .trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        "_".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        PathTemplate("{album_id}/{id}.avif".to_string())
+    }
+}
+
+impl std::str::FromStr for PathTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if !s.ends_with(".avif") {
+            anyhow::bail!("Path template must end with .avif: {}", s);
+        }
+        if s.starts_with('/') || s.split(['/', '\\']).any(|part| part == "..") {
+            anyhow::bail!("Path template must stay inside the AVIF directory: {}", s);
+        }
+
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            let close = rest[open..]
+                .find('}')
+                .map(|i| open + i)
+                .ok_or_else(|| anyhow::anyhow!("Unclosed token in path template: {}", s))?;
+            let token = &rest[open + 1..close];
+            if !PATH_TOKENS.contains(&token) {
+                anyhow::bail!(
+                    "Unknown token {{{}}} in path template (expected one of {})",
+                    token,
+                    PATH_TOKENS.join(", ")
+                );
+            }
+            rest = &rest[close + 1..];
+        }
+
+        Ok(PathTemplate(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for PathTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+fn default_api_timeout_secs() -> u64 {
+    30
+}
+
+fn default_download_timeout_secs() -> u64 {
+    300
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_pool_max_idle() -> usize {
+    16
+}
+
+fn default_max_concurrent_requests() -> usize {
+    8
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
+fn default_user_agent() -> String {
+    format!("avif-generator/{}", env!("CARGO_PKG_VERSION"))
 }
 
 fn default_original_dir() -> String {
@@ -58,12 +459,101 @@ fn default_db_name() -> String {
     "db.sqlite".to_string()
 }
 
+fn default_db_busy_timeout_ms() -> u64 {
+    5000
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
     #[serde(default = "default_host")]
     pub host: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    /// Seconds between background integrity checks; 0 only checks on demand
+    #[serde(default = "default_integrity_check_interval")]
+    pub integrity_check_interval_secs: u64,
+    /// Seconds between database health checks; after repeated failures the
+    /// connection pool is recreated. 0 disables the check.
+    #[serde(default = "default_db_health_check_interval")]
+    pub db_health_check_interval_secs: u64,
+    /// Expose untouched originals at `/images/:image_id/original`
+    #[serde(default)]
+    pub serve_originals: bool,
+    /// Key required by mutating endpoints, sent as `x-api-key` or a bearer token.
+    /// Those endpoints are refused entirely when unset.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Image served instead of a JSON 404 when an image or thumbnail is unavailable
+    #[serde(default)]
+    pub not_found_image: Option<PathBuf>,
+    /// Status sent with `not_found_image`: 404, or 202 for "still converting"
+    #[serde(default = "default_not_found_status")]
+    pub not_found_status: u16,
+    /// Serve a generated placeholder for images that are not converted yet, as
+    /// `?placeholder=1` does, instead of a 404
+    #[serde(default)]
+    pub pending_placeholder: bool,
+    /// CORS origins allowed to call the API; empty allows any origin
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// PEM certificate chain; with `tls_key_path`, the server speaks HTTPS directly
+    #[serde(default)]
+    pub tls_cert_path: Option<PathBuf>,
+    /// PEM private key for `tls_cert_path`
+    #[serde(default)]
+    pub tls_key_path: Option<PathBuf>,
+    /// Extra headers on image, thumbnail and cover responses; `{id}` in a value is
+    /// replaced by the image ID
+    #[serde(default)]
+    pub response_headers: HashMap<String, String>,
+    /// Image, thumbnail, cover and original requests allowed per second and client;
+    /// 0 disables the limit
+    #[serde(default)]
+    pub rate_limit_per_second: f64,
+    /// Requests a client can make at once before `rate_limit_per_second` applies
+    #[serde(default = "default_rate_limit_burst")]
+    pub rate_limit_burst: u32,
+    /// Identify clients by the last `X-Forwarded-For` address, as set by a reverse
+    /// proxy, instead of the connection's address
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    /// Offer HTTP/2, negotiated over TLS, so browsers can multiplex many image
+    /// requests over one connection
+    #[serde(default = "default_http2")]
+    pub http2: bool,
+    /// Requests one HTTP/2 connection may have in flight at once
+    #[serde(default = "default_http2_max_concurrent_streams")]
+    pub http2_max_concurrent_streams: u32,
+    /// Seconds between HTTP/2 pings that keep idle connections open and detect dead
+    /// ones; 0 disables them
+    #[serde(default)]
+    pub http2_keep_alive_interval_secs: u64,
+    /// Seconds to wait for a ping reply before closing the connection
+    #[serde(default = "default_http2_keep_alive_timeout")]
+    pub http2_keep_alive_timeout_secs: u64,
+    /// Reuse HTTP/1.1 connections for further requests
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: bool,
+}
+
+fn default_http2() -> bool {
+    true
+}
+
+fn default_http2_max_concurrent_streams() -> u32 {
+    200
+}
+
+fn default_http2_keep_alive_timeout() -> u64 {
+    20
+}
+
+fn default_keep_alive() -> bool {
+    true
+}
+
+fn default_not_found_status() -> u16 {
+    404
 }
 
 fn default_host() -> String {
@@ -74,17 +564,229 @@ fn default_port() -> u16 {
     3000
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+fn default_integrity_check_interval() -> u64 {
+    3600
+}
+
+fn default_rate_limit_burst() -> u32 {
+    20
+}
+
+fn default_db_health_check_interval() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct SyncConfig {
     #[serde(default)]
     pub delete_removed: bool,
+    /// What happens to the files of removed images and albums
+    #[serde(default)]
+    pub removal_mode: RemovalMode,
+    /// Days files stay in the trash before a sync deletes them; 0 keeps them until
+    /// `empty-trash`
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// Delete local albums, with their images and files, once they no longer exist
+    /// in Immich; unset follows `delete_removed`, see `prune_removed_albums()`
+    #[serde(default)]
+    pub prune_removed_albums: Option<bool>,
+    /// Concurrent downloads; 0 derives it from the CPU count
     #[serde(default = "default_parallel_downloads")]
     pub parallel_downloads: usize,
+    /// Concurrent conversions; 0 uses one per CPU core
     #[serde(default = "default_parallel_conversions")]
     pub parallel_conversions: usize,
+    /// Estimated decode/encode memory allowed across parallel conversions, in MiB;
+    /// 0 limits only the number of conversions
+    #[serde(default)]
+    pub max_conversion_memory_mb: u64,
+    /// Local-time window (e.g. `"22:00-06:00"`) outside which no new conversions start
+    #[serde(default)]
+    pub conversion_active_hours: Option<ActiveHours>,
+    /// Cron expression (e.g. `"0 3 * * *"`) for syncs run by the `daemon` command
+    #[serde(default)]
+    pub schedule: Option<SyncSchedule>,
+    /// Immich's upload folder as mounted on this host; originals found there are
+    /// linked into `original_path()` instead of downloaded
+    #[serde(default)]
+    pub source_library_path: Option<PathBuf>,
+    #[serde(default)]
+    pub source_link_mode: LinkMode,
+    /// MIME types (e.g. `"image/x-adobe-dng"`) that are neither downloaded nor converted
+    #[serde(default)]
+    pub skip_mime_types: Vec<String>,
+    /// Immich user IDs whose assets are synced; empty syncs every owner's. Assets
+    /// of other owners in shared albums are treated as not in the album.
+    #[serde(default)]
+    pub owner_ids: Vec<String>,
+    /// Also download Immich's own thumbnail of each asset and make thumbnails from
+    /// it instead of the original
+    #[serde(default)]
+    pub immich_thumbnails: bool,
+    /// Convert originals as soon as they appear on disk while serving, instead of
+    /// waiting for the next conversion pass
+    #[serde(default)]
+    pub watch_originals: bool,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// How originals found under `source_library_path` are placed in `original_path()`
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkMode {
+    /// Hardlink, or copy when the library is on another filesystem
+    #[default]
+    Hardlink,
+    Symlink,
+}
+
+impl std::str::FromStr for LinkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "hardlink" => Ok(LinkMode::Hardlink),
+            "symlink" => Ok(LinkMode::Symlink),
+            other => Err(anyhow::anyhow!("Unknown link mode: {}", other)),
+        }
+    }
+}
+
+/// What `delete_removed` and `prune_removed_albums` do with the files of removed images
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalMode {
+    #[default]
+    Delete,
+    /// Move them to `trash_path()`, to be deleted after `trash_retention_days`
+    Trash,
+}
+
+impl std::str::FromStr for RemovalMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "delete" => Ok(RemovalMode::Delete),
+            "trash" => Ok(RemovalMode::Trash),
+            other => Err(anyhow::anyhow!("Unknown removal mode: {}", other)),
+        }
+    }
+}
+
+/// Upper bound for auto-detected downloads, which are I/O- rather than CPU-bound
+const MAX_AUTO_DOWNLOADS: usize = 16;
+
+impl SyncConfig {
+    /// Whether albums deleted in Immich are pruned: `prune_removed_albums`, or
+    /// `delete_removed` when that is not set, as both used to be one option
+    pub fn prune_removed_albums(&self) -> bool {
+        self.prune_removed_albums.unwrap_or(self.delete_removed)
+    }
+
+    /// Replace `0` ("auto") concurrency values with ones derived from the number of
+    /// available cores, and log what was chosen
+    fn resolve_parallelism(&mut self) {
+        let cores = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        let auto_downloads = self.parallel_downloads == 0;
+        if auto_downloads {
+            self.parallel_downloads = (cores * 2).min(MAX_AUTO_DOWNLOADS);
+        }
+        let auto_conversions = self.parallel_conversions == 0;
+        if auto_conversions {
+            self.parallel_conversions = cores;
+        }
+
+        let source = |auto: bool| if auto { " (auto)" } else { "" };
+        info!(
+            "Concurrency: {} downloads{}, {} conversions{} ({} cores available)",
+            self.parallel_downloads,
+            source(auto_downloads),
+            self.parallel_conversions,
+            source(auto_conversions),
+            cores
+        );
+    }
+}
+
+/// Daily `HH:MM-HH:MM` window in local time; may wrap past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ActiveHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl ActiveHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Time from `now` until the window next opens (zero when already inside it)
+    pub fn until_open(&self, now: NaiveTime) -> std::time::Duration {
+        if self.contains(now) {
+            return std::time::Duration::ZERO;
+        }
+        let wait = self.start - now;
+        let wait = if wait < chrono::Duration::zero() {
+            wait + chrono::Duration::days(1)
+        } else {
+            wait
+        };
+        wait.to_std().unwrap_or_default()
+    }
+}
+
+impl std::str::FromStr for ActiveHours {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("Active hours must look like HH:MM-HH:MM: {}", s))?;
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|e| anyhow::anyhow!("Invalid time {:?} in active hours: {}", t, e))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        // An empty window would never open, leaving conversion waiting forever
+        if start == end {
+            anyhow::bail!(
+                "Active hours must not start and end at the same time: {}",
+                s
+            );
+        }
+        Ok(ActiveHours { start, end })
+    }
+}
+
+impl TryFrom<String> for ActiveHours {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for ActiveHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct ImageConfig {
     #[serde(default = "default_quality")]
     pub quality: f32,
@@ -96,6 +798,384 @@ pub struct ImageConfig {
     pub max_file_size: u64,
     #[serde(default = "default_min_quality")]
     pub min_quality: f32,
+    /// Quality for thumbnails; unset uses `quality`
+    #[serde(default)]
+    pub thumbnail_quality: Option<f32>,
+    /// Size limit for thumbnails; unset uses `max_file_size`
+    #[serde(default)]
+    pub thumbnail_max_file_size: Option<u64>,
+    /// What to do when an encode is still over the size limit at `min_quality`
+    #[serde(default)]
+    pub on_size_exceeded: SizeExceededPolicy,
+    /// Center-crop thumbnails to a square before resizing
+    #[serde(default)]
+    pub thumbnail_square: bool,
+    /// Write a `{id}.avif.json` provenance sidecar next to each AVIF
+    #[serde(default)]
+    pub write_sidecar: bool,
+    /// Link the original instead of encoding when it is already under
+    /// `max_file_size` and no wider than `max_width`
+    #[serde(default)]
+    pub skip_if_smaller: bool,
+    /// Copy capture date, camera and GPS EXIF tags into the AVIF output
+    #[serde(default)]
+    pub preserve_metadata: bool,
+    /// Compact placeholder hash computed from each thumbnail
+    #[serde(default)]
+    pub placeholder_strategy: PlaceholderStrategy,
+    /// Store a tiny JPEG data URI of each image, returned as `lqip`
+    #[serde(default)]
+    pub lqip: bool,
+    /// Always write an alpha plane, even for fully opaque images
+    #[serde(default)]
+    pub force_alpha: bool,
+    /// Encode animated GIF/APNG sources as animated AVIFs instead of
+    /// keeping only the first frame
+    #[serde(default = "default_convert_animations")]
+    pub convert_animations: bool,
+    /// Encode the full-size AVIF losslessly, ignoring `quality` and `max_file_size`.
+    /// Thumbnails stay lossy.
+    #[serde(default)]
+    pub lossless: bool,
+    /// Search for the lowest quality between `min_quality` and `quality` whose
+    /// output reaches `target_ssim`, with a few trial encodes per image.
+    /// Thumbnails keep their fixed quality.
+    #[serde(default)]
+    pub auto_quality: bool,
+    /// SSIM (0-1) that `auto_quality` must reach; 1.0 is identical to the source
+    #[serde(default = "default_target_ssim")]
+    pub target_ssim: f32,
+    /// Largest `width * height` a source may declare before it is rejected without
+    /// being decoded; 0 for no limit
+    #[serde(default = "default_max_pixels")]
+    pub max_pixels: u64,
+    /// Quality by output width, replacing `quality` for full-size images
+    #[serde(default)]
+    pub quality_ladder: QualityLadder,
+    /// Extra AVIFs at other widths and qualities, served with `?variant=<name>`;
+    /// the full-size AVIF stays the implicit `full` variant
+    #[serde(default)]
+    pub variants: Variants,
+    /// Per-album settings, keyed by album ID (`[image.overrides.<album_id>]`)
+    #[serde(default)]
+    pub overrides: HashMap<String, ImageOverride>,
+}
+
+/// Subset of `ImageConfig` set for one album; unset fields inherit the global value
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageOverride {
+    pub quality: Option<f32>,
+    pub max_width: Option<u32>,
+    pub thumbnail_width: Option<u32>,
+    pub max_file_size: Option<u64>,
+    pub min_quality: Option<f32>,
+    pub thumbnail_quality: Option<f32>,
+    pub thumbnail_max_file_size: Option<u64>,
+    pub on_size_exceeded: Option<SizeExceededPolicy>,
+    pub thumbnail_square: Option<bool>,
+    pub write_sidecar: Option<bool>,
+    pub skip_if_smaller: Option<bool>,
+    pub preserve_metadata: Option<bool>,
+    pub placeholder_strategy: Option<PlaceholderStrategy>,
+    pub lqip: Option<bool>,
+    pub force_alpha: Option<bool>,
+    pub convert_animations: Option<bool>,
+    pub lossless: Option<bool>,
+    pub auto_quality: Option<bool>,
+    pub target_ssim: Option<f32>,
+    pub quality_ladder: Option<QualityLadder>,
+}
+
+impl ImageConfig {
+    /// Settings for images in `album_id`, with that album's overrides applied
+    pub fn for_album(&self, album_id: &str) -> ImageConfig {
+        let Some(o) = self.overrides.get(album_id) else {
+            return ImageConfig {
+                overrides: HashMap::new(),
+                ..self.clone()
+            };
+        };
+
+        ImageConfig {
+            quality: o.quality.unwrap_or(self.quality),
+            max_width: o.max_width.unwrap_or(self.max_width),
+            thumbnail_width: o.thumbnail_width.unwrap_or(self.thumbnail_width),
+            max_file_size: o.max_file_size.unwrap_or(self.max_file_size),
+            min_quality: o.min_quality.unwrap_or(self.min_quality),
+            thumbnail_quality: o.thumbnail_quality.or(self.thumbnail_quality),
+            thumbnail_max_file_size: o.thumbnail_max_file_size.or(self.thumbnail_max_file_size),
+            on_size_exceeded: o.on_size_exceeded.unwrap_or(self.on_size_exceeded),
+            thumbnail_square: o.thumbnail_square.unwrap_or(self.thumbnail_square),
+            write_sidecar: o.write_sidecar.unwrap_or(self.write_sidecar),
+            skip_if_smaller: o.skip_if_smaller.unwrap_or(self.skip_if_smaller),
+            preserve_metadata: o.preserve_metadata.unwrap_or(self.preserve_metadata),
+            placeholder_strategy: o.placeholder_strategy.unwrap_or(self.placeholder_strategy),
+            lqip: o.lqip.unwrap_or(self.lqip),
+            force_alpha: o.force_alpha.unwrap_or(self.force_alpha),
+            convert_animations: o.convert_animations.unwrap_or(self.convert_animations),
+            lossless: o.lossless.unwrap_or(self.lossless),
+            auto_quality: o.auto_quality.unwrap_or(self.auto_quality),
+            target_ssim: o.target_ssim.unwrap_or(self.target_ssim),
+            max_pixels: self.max_pixels,
+            // An album's own flat quality is not overruled by the global ladder
+            quality_ladder: match (&o.quality_ladder, o.quality) {
+                (Some(ladder), _) => ladder.clone(),
+                (None, Some(_)) => QualityLadder::default(),
+                (None, None) => self.quality_ladder.clone(),
+            },
+            variants: self.variants.clone(),
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Settings for a full-size image `width` pixels wide: `quality` from the
+    /// ladder step for that width, if any
+    pub fn at_width(&self, width: u32) -> ImageConfig {
+        match self.quality_ladder.quality_for(width) {
+            Some(quality) => ImageConfig {
+                quality,
+                min_quality: self.min_quality.min(quality),
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Settings for encoding thumbnails: `thumbnail_quality` and
+    /// `thumbnail_max_file_size` stand in for the main image's values
+    pub fn for_thumbnail(&self) -> ImageConfig {
+        ImageConfig {
+            quality: self.thumbnail_quality.unwrap_or(self.quality),
+            max_file_size: self.thumbnail_max_file_size.unwrap_or(self.max_file_size),
+            lossless: false,
+            auto_quality: false,
+            quality_ladder: QualityLadder::default(),
+            variants: Variants::default(),
+            overrides: HashMap::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Settings for encoding one of `variants`: its own width and flat quality,
+    /// never wider than the full-size image
+    pub fn for_variant(&self, variant: &VariantConfig) -> ImageConfig {
+        ImageConfig {
+            quality: variant.quality,
+            min_quality: self.min_quality.min(variant.quality),
+            max_width: variant.max_width.min(self.max_width),
+            lossless: false,
+            auto_quality: false,
+            quality_ladder: QualityLadder::default(),
+            variants: Variants::default(),
+            overrides: HashMap::new(),
+            ..self.clone()
+        }
+    }
+}
+
+/// One step of `image.quality_ladder`: `quality` for outputs at least `min_width`
+/// pixels wide
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QualityStep {
+    pub min_width: u32,
+    pub quality: f32,
+}
+
+/// Quality steps sorted by `min_width`. An output uses the step with the largest
+/// `min_width` not above its width; narrower than every step, or with no steps,
+/// it keeps the flat `quality`.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(try_from = "Vec<QualityStep>")]
+pub struct QualityLadder(Vec<QualityStep>);
+
+impl QualityLadder {
+    pub fn quality_for(&self, width: u32) -> Option<f32> {
+        self.0
+            .iter()
+            .rev()
+            .find(|step| step.min_width <= width)
+            .map(|step| step.quality)
+    }
+}
+
+impl TryFrom<Vec<QualityStep>> for QualityLadder {
+    type Error = anyhow::Error;
+
+    fn try_from(mut steps: Vec<QualityStep>) -> anyhow::Result<Self> {
+        for step in &steps {
+            if !(1.0..=100.0).contains(&step.quality) {
+                anyhow::bail!(
+                    "Quality ladder step for {}px has quality {}, expected 1-100",
+                    step.min_width,
+                    step.quality
+                );
+            }
+        }
+        steps.sort_by_key(|step| step.min_width);
+        if let Some(pair) = steps.windows(2).find(|w| w[0].min_width == w[1].min_width) {
+            anyhow::bail!("Quality ladder has two steps for {}px", pair[0].min_width);
+        }
+        Ok(QualityLadder(steps))
+    }
+}
+
+/// `min_width:quality` pairs separated by commas, e.g. `0:85,2000:75,3500:65`
+impl std::str::FromStr for QualityLadder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let steps = s
+            .split(',')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+            .map(|step| {
+                let (min_width, quality) = step.split_once(':').ok_or_else(|| {
+                    anyhow::anyhow!("Quality ladder step '{}' is not min_width:quality", step)
+                })?;
+                Ok(QualityStep {
+                    min_width: min_width.trim().parse()?,
+                    quality: quality.trim().parse()?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        steps.try_into()
+    }
+}
+
+/// One entry of `image.variants`: an extra AVIF no wider than `max_width`,
+/// encoded at `quality`
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct VariantConfig {
+    pub name: String,
+    pub max_width: u32,
+    pub quality: f32,
+}
+
+/// Named variants written next to each full-size AVIF as `{stem}_{name}.avif`
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(try_from = "Vec<VariantConfig>")]
+pub struct Variants(Vec<VariantConfig>);
+
+impl Variants {
+    /// The implicit variant served when none is asked for
+    pub const FULL: &'static str = "full";
+
+    pub fn iter(&self) -> std::slice::Iter<'_, VariantConfig> {
+        self.0.iter()
+    }
+}
+
+impl TryFrom<Vec<VariantConfig>> for Variants {
+    type Error = anyhow::Error;
+
+    fn try_from(variants: Vec<VariantConfig>) -> anyhow::Result<Self> {
+        for (i, variant) in variants.iter().enumerate() {
+            let valid_name = !variant.name.is_empty()
+                && variant
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid_name {
+                anyhow::bail!(
+                    "Variant name '{}' must be lowercase letters, digits or '-'",
+                    variant.name
+                );
+            }
+            // `thumb` would collide with the thumbnail's `{stem}_thumb.avif`
+            if variant.name == Self::FULL || variant.name == "thumb" {
+                anyhow::bail!("Variant name '{}' is reserved", variant.name);
+            }
+            if variant.max_width == 0 {
+                anyhow::bail!("Variant '{}' needs a max_width above 0", variant.name);
+            }
+            if !(1.0..=100.0).contains(&variant.quality) {
+                anyhow::bail!(
+                    "Variant '{}' has quality {}, expected 1-100",
+                    variant.name,
+                    variant.quality
+                );
+            }
+            if variants[..i].iter().any(|v| v.name == variant.name) {
+                anyhow::bail!("Variant '{}' is defined twice", variant.name);
+            }
+        }
+        Ok(Variants(variants))
+    }
+}
+
+/// `name:max_width:quality` triples separated by commas, e.g. `low:640:50,medium:1280:65`
+impl std::str::FromStr for Variants {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let variants = s
+            .split(',')
+            .map(str::trim)
+            .filter(|variant| !variant.is_empty())
+            .map(|variant| {
+                let mut parts = variant.split(':').map(str::trim);
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some(name), Some(max_width), Some(quality), None) => Ok(VariantConfig {
+                        name: name.to_string(),
+                        max_width: max_width.parse()?,
+                        quality: quality.parse()?,
+                    }),
+                    _ => anyhow::bail!("Variant '{}' is not name:max_width:quality", variant),
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        variants.try_into()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceholderStrategy {
+    #[default]
+    None,
+    Blurhash,
+    Thumbhash,
+}
+
+impl std::str::FromStr for PlaceholderStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(PlaceholderStrategy::None),
+            "blurhash" => Ok(PlaceholderStrategy::Blurhash),
+            "thumbhash" => Ok(PlaceholderStrategy::Thumbhash),
+            other => Err(anyhow::anyhow!("Unknown placeholder strategy: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeExceededPolicy {
+    /// Keep the output even though it is over the limit
+    #[default]
+    SaveAnyway,
+    /// Fail the conversion without writing anything
+    Skip,
+    /// Shrink the image in 10% steps until it fits, failing if it never does
+    Downscale,
+}
+
+impl std::str::FromStr for SizeExceededPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "save_anyway" => Ok(SizeExceededPolicy::SaveAnyway),
+            "skip" => Ok(SizeExceededPolicy::Skip),
+            "downscale" => Ok(SizeExceededPolicy::Downscale),
+            other => Err(anyhow::anyhow!("Unknown size exceeded policy: {}", other)),
+        }
+    }
 }
 
 impl Default for ImageConfig {
@@ -106,10 +1186,235 @@ impl Default for ImageConfig {
             thumbnail_width: default_thumbnail_width(),
             max_file_size: default_max_file_size(),
             min_quality: default_min_quality(),
+            thumbnail_quality: None,
+            thumbnail_max_file_size: None,
+            on_size_exceeded: SizeExceededPolicy::SaveAnyway,
+            thumbnail_square: false,
+            write_sidecar: false,
+            skip_if_smaller: false,
+            preserve_metadata: false,
+            placeholder_strategy: PlaceholderStrategy::None,
+            lqip: false,
+            force_alpha: false,
+            convert_animations: default_convert_animations(),
+            lossless: false,
+            auto_quality: false,
+            target_ssim: default_target_ssim(),
+            max_pixels: default_max_pixels(),
+            quality_ladder: QualityLadder::default(),
+            variants: Variants::default(),
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            delete_removed: false,
+            removal_mode: RemovalMode::Delete,
+            trash_retention_days: default_trash_retention_days(),
+            prune_removed_albums: None,
+            parallel_downloads: default_parallel_downloads(),
+            parallel_conversions: default_parallel_conversions(),
+            max_conversion_memory_mb: 0,
+            conversion_active_hours: None,
+            schedule: None,
+            source_library_path: None,
+            source_link_mode: LinkMode::Hardlink,
+            skip_mime_types: Vec::new(),
+            owner_ids: Vec::new(),
+            immich_thumbnails: false,
+            watch_originals: false,
+        }
+    }
+}
+
+/// Cron expression in local time. Standard five-field expressions get a leading
+/// seconds field, since the `cron` crate expects six or seven fields.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SyncSchedule {
+    expression: String,
+    schedule: cron::Schedule,
+}
+
+impl SyncSchedule {
+    /// First scheduled time strictly after `now`, if the expression has any left
+    pub fn next_after(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.schedule.after(&now).next()
+    }
+}
+
+impl std::str::FromStr for SyncSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let expression = s.trim().to_string();
+        let full = if expression.split_whitespace().count() == 5 {
+            format!("0 {}", expression)
+        } else {
+            expression.clone()
+        };
+        let schedule = full.parse::<cron::Schedule>().map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid sync schedule {:?}, expected \"minute hour day month weekday\": {}",
+                expression,
+                e
+            )
+        })?;
+        Ok(SyncSchedule {
+            expression,
+            schedule,
+        })
+    }
+}
+
+impl TryFrom<String> for SyncSchedule {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for SyncSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricsConfig {
+    /// StatsD `host:port` to push metrics to (requires the `statsd` feature)
+    #[serde(default)]
+    pub statsd_endpoint: Option<String>,
+    #[serde(default = "default_metrics_prefix")]
+    #[cfg_attr(not(feature = "statsd"), allow(dead_code))]
+    pub prefix: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            statsd_endpoint: None,
+            prefix: default_metrics_prefix(),
         }
     }
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NotificationsConfig {
+    /// URL POSTed a JSON summary after each sync/convert run
+    #[serde(default)]
+    pub completion_webhook_url: Option<String>,
+    /// Shared secret for the `X-Signature-256` HMAC-SHA256 header
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+}
+
+/// Logo or text composited onto converted images; off unless `image` or `text` is set
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatermarkConfig {
+    /// PNG (or any decodable image) to use as the mark, with its own transparency
+    #[serde(default)]
+    pub image: Option<PathBuf>,
+    /// Text to draw instead of an image; needs `font_path`
+    #[serde(default)]
+    pub text: Option<String>,
+    /// TrueType/OpenType font for `text`
+    #[serde(default)]
+    pub font_path: Option<PathBuf>,
+    /// Text color as `#rrggbb`
+    #[serde(default = "default_watermark_color")]
+    pub color: String,
+    #[serde(default)]
+    pub position: WatermarkPosition,
+    /// 0 (invisible) to 1 (as drawn)
+    #[serde(default = "default_watermark_opacity")]
+    pub opacity: f32,
+    /// Width of the mark as a fraction of the image width
+    #[serde(default = "default_watermark_scale")]
+    pub scale: f32,
+    /// Distance from the edges as a fraction of the image's shorter side
+    #[serde(default = "default_watermark_margin")]
+    pub margin: f32,
+    /// Also mark thumbnails
+    #[serde(default)]
+    pub thumbnails: bool,
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            image: None,
+            text: None,
+            font_path: None,
+            color: default_watermark_color(),
+            position: WatermarkPosition::default(),
+            opacity: default_watermark_opacity(),
+            scale: default_watermark_scale(),
+            margin: default_watermark_margin(),
+            thumbnails: false,
+        }
+    }
+}
+
+/// Where the watermark goes: one of the nine gravity positions
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    #[default]
+    BottomRight,
+}
+
+impl std::str::FromStr for WatermarkPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "top_left" => Ok(WatermarkPosition::TopLeft),
+            "top" => Ok(WatermarkPosition::Top),
+            "top_right" => Ok(WatermarkPosition::TopRight),
+            "left" => Ok(WatermarkPosition::Left),
+            "center" => Ok(WatermarkPosition::Center),
+            "right" => Ok(WatermarkPosition::Right),
+            "bottom_left" => Ok(WatermarkPosition::BottomLeft),
+            "bottom" => Ok(WatermarkPosition::Bottom),
+            "bottom_right" => Ok(WatermarkPosition::BottomRight),
+            other => Err(anyhow::anyhow!("Unknown watermark position: {}", other)),
+        }
+    }
+}
+
+fn default_watermark_color() -> String {
+    "#ffffff".to_string()
+}
+
+fn default_watermark_opacity() -> f32 {
+    0.5
+}
+
+fn default_watermark_scale() -> f32 {
+    0.2
+}
+
+fn default_watermark_margin() -> f32 {
+    0.03
+}
+
+fn default_metrics_prefix() -> String {
+    "avif_generator".to_string()
+}
+
 fn default_quality() -> f32 {
     80.0
 }
@@ -126,10 +1431,22 @@ fn default_max_file_size() -> u64 {
     10 * 1024 * 1024 // 10MB
 }
 
+fn default_max_pixels() -> u64 {
+    100_000_000
+}
+
 fn default_min_quality() -> f32 {
     30.0
 }
 
+fn default_target_ssim() -> f32 {
+    0.98
+}
+
+fn default_convert_animations() -> bool {
+    true
+}
+
 fn default_parallel_downloads() -> usize {
     4
 }
@@ -138,33 +1455,374 @@ fn default_parallel_conversions() -> usize {
     2
 }
 
+fn default_trash_retention_days() -> u64 {
+    30
+}
+
 impl Config {
-    pub fn load(path: &str) -> anyhow::Result<Self> {
+    /// Load a config file, then let any of the environment variables `from_env`
+    /// reads replace the matching field, e.g. to keep `IMMICH_API_KEY` out of the file
+    pub fn load_with_env_overrides(path: &str) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        let mut config: Config = toml::from_str(&content)?;
+        config.apply_env_overrides()?;
+        config.sync.resolve_parallelism();
         Ok(config)
     }
 
+    fn apply_env_overrides(&mut self) -> anyhow::Result<()> {
+        let immich = &mut self.immich;
+        override_from_env(&mut immich.url, "IMMICH_URL")?;
+        // Same precedence as `from_env`: a shared-link key wins over an API key
+        if let Some(key) = env::var("IMMICH_SHARED_LINK_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())
+        {
+            immich.auth = AuthConfig::SharedLink { key };
+        } else if let Ok(api_key) = env::var("IMMICH_API_KEY") {
+            immich.auth = AuthConfig::ApiKey { api_key };
+        }
+        override_from_env(&mut immich.album_page_size, "IMMICH_ALBUM_PAGE_SIZE")?;
+        override_from_env(&mut immich.max_album_pages, "IMMICH_MAX_ALBUM_PAGES")?;
+        override_from_env(&mut immich.api_probe, "IMMICH_API_PROBE")?;
+        override_from_env(&mut immich.api_timeout_secs, "IMMICH_API_TIMEOUT_SECS")?;
+        override_from_env(
+            &mut immich.download_timeout_secs,
+            "IMMICH_DOWNLOAD_TIMEOUT_SECS",
+        )?;
+        override_from_env(
+            &mut immich.connect_timeout_secs,
+            "IMMICH_CONNECT_TIMEOUT_SECS",
+        )?;
+        override_from_env(&mut immich.pool_max_idle, "IMMICH_POOL_MAX_IDLE")?;
+        override_from_env(
+            &mut immich.max_concurrent_requests,
+            "IMMICH_MAX_CONCURRENT_REQUESTS",
+        )?;
+        override_from_env(&mut immich.retry_attempts, "IMMICH_RETRY_ATTEMPTS")?;
+        override_from_env(&mut immich.retry_backoff_ms, "IMMICH_RETRY_BACKOFF_MS")?;
+        override_from_env(&mut immich.user_agent, "IMMICH_USER_AGENT")?;
+        override_option_from_env(&mut immich.proxy, "IMMICH_PROXY")?;
+        override_from_env(
+            &mut immich.insecure_skip_tls_verify,
+            "IMMICH_INSECURE_SKIP_TLS_VERIFY",
+        )?;
+
+        let storage = &mut self.storage;
+        override_from_env(&mut storage.base_path, "STORAGE_PATH")?;
+        override_from_env(&mut storage.original_dir, "STORAGE_ORIGINAL_DIR")?;
+        override_from_env(&mut storage.avif_dir, "STORAGE_AVIF_DIR")?;
+        override_from_env(&mut storage.db_name, "STORAGE_DB_NAME")?;
+        override_from_env(&mut storage.db_journal_mode, "STORAGE_DB_JOURNAL_MODE")?;
+        override_from_env(
+            &mut storage.db_busy_timeout_ms,
+            "STORAGE_DB_BUSY_TIMEOUT_MS",
+        )?;
+        override_from_env(&mut storage.path_template, "STORAGE_PATH_TEMPLATE")?;
+        override_from_env(&mut storage.max_total_bytes, "STORAGE_MAX_TOTAL_BYTES")?;
+        override_from_env(&mut storage.eviction_order, "STORAGE_EVICTION_ORDER")?;
+        override_from_env(&mut storage.content_addressed, "STORAGE_CONTENT_ADDRESSED")?;
+        override_from_env(&mut storage.min_free_bytes, "STORAGE_MIN_FREE_BYTES")?;
+        override_from_env(&mut storage.write_manifests, "STORAGE_WRITE_MANIFESTS")?;
+        override_option_from_env(
+            &mut storage.split_album_name_on,
+            "STORAGE_SPLIT_ALBUM_NAME_ON",
+        )?;
+        override_option_from_env(&mut storage.encryption_key, "STORAGE_ENCRYPTION_KEY")?;
+        override_option_from_env(
+            &mut storage.encryption_key_file,
+            "STORAGE_ENCRYPTION_KEY_FILE",
+        )?;
+
+        let server = &mut self.server;
+        override_from_env(&mut server.host, "SERVER_HOST")?;
+        override_from_env(&mut server.port, "SERVER_PORT")?;
+        override_from_env(
+            &mut server.integrity_check_interval_secs,
+            "SERVER_INTEGRITY_CHECK_INTERVAL",
+        )?;
+        override_from_env(
+            &mut server.db_health_check_interval_secs,
+            "SERVER_DB_HEALTH_CHECK_INTERVAL",
+        )?;
+        override_from_env(&mut server.serve_originals, "SERVER_SERVE_ORIGINALS")?;
+        override_option_from_env(&mut server.api_key, "SERVER_API_KEY")?;
+        override_option_from_env(&mut server.not_found_image, "SERVER_NOT_FOUND_IMAGE")?;
+        override_from_env(&mut server.not_found_status, "SERVER_NOT_FOUND_STATUS")?;
+        override_from_env(
+            &mut server.pending_placeholder,
+            "SERVER_PENDING_PLACEHOLDER",
+        )?;
+        override_list_from_env(&mut server.allowed_origins, "SERVER_ALLOWED_ORIGINS");
+        override_option_from_env(&mut server.tls_cert_path, "SERVER_TLS_CERT_PATH")?;
+        override_option_from_env(&mut server.tls_key_path, "SERVER_TLS_KEY_PATH")?;
+        override_from_env(
+            &mut server.rate_limit_per_second,
+            "SERVER_RATE_LIMIT_PER_SECOND",
+        )?;
+        override_from_env(&mut server.rate_limit_burst, "SERVER_RATE_LIMIT_BURST")?;
+        override_from_env(
+            &mut server.trust_forwarded_for,
+            "SERVER_TRUST_FORWARDED_FOR",
+        )?;
+        override_from_env(&mut server.http2, "SERVER_HTTP2")?;
+        override_from_env(
+            &mut server.http2_max_concurrent_streams,
+            "SERVER_HTTP2_MAX_CONCURRENT_STREAMS",
+        )?;
+        override_from_env(
+            &mut server.http2_keep_alive_interval_secs,
+            "SERVER_HTTP2_KEEP_ALIVE_INTERVAL",
+        )?;
+        override_from_env(
+            &mut server.http2_keep_alive_timeout_secs,
+            "SERVER_HTTP2_KEEP_ALIVE_TIMEOUT",
+        )?;
+        override_from_env(&mut server.keep_alive, "SERVER_KEEP_ALIVE")?;
+        if let Ok(value) = env::var("SERVER_RESPONSE_HEADERS") {
+            server.response_headers = parse_header_list(&value, "SERVER_RESPONSE_HEADERS")?;
+        }
+
+        let sync = &mut self.sync;
+        override_from_env(&mut sync.delete_removed, "SYNC_DELETE_REMOVED")?;
+        override_from_env(&mut sync.removal_mode, "SYNC_REMOVAL_MODE")?;
+        override_from_env(&mut sync.trash_retention_days, "SYNC_TRASH_RETENTION_DAYS")?;
+        override_option_from_env(&mut sync.prune_removed_albums, "SYNC_PRUNE_REMOVED_ALBUMS")?;
+        override_from_env(&mut sync.parallel_downloads, "SYNC_PARALLEL_DOWNLOADS")?;
+        override_from_env(&mut sync.parallel_conversions, "SYNC_PARALLEL_CONVERSIONS")?;
+        override_from_env(
+            &mut sync.max_conversion_memory_mb,
+            "SYNC_MAX_CONVERSION_MEMORY_MB",
+        )?;
+        override_option_from_env(
+            &mut sync.conversion_active_hours,
+            "SYNC_CONVERSION_ACTIVE_HOURS",
+        )?;
+        override_option_from_env(&mut sync.schedule, "SYNC_SCHEDULE")?;
+        override_option_from_env(&mut sync.source_library_path, "SYNC_SOURCE_LIBRARY_PATH")?;
+        override_from_env(&mut sync.source_link_mode, "SYNC_SOURCE_LINK_MODE")?;
+        override_list_from_env(&mut sync.skip_mime_types, "SYNC_SKIP_MIME_TYPES");
+        override_list_from_env(&mut sync.owner_ids, "SYNC_OWNER_IDS");
+        override_from_env(&mut sync.immich_thumbnails, "SYNC_IMMICH_THUMBNAILS")?;
+        override_from_env(&mut sync.watch_originals, "SYNC_WATCH_ORIGINALS")?;
+
+        let image = &mut self.image;
+        override_from_env(&mut image.quality, "IMAGE_QUALITY")?;
+        override_from_env(&mut image.max_width, "IMAGE_MAX_WIDTH")?;
+        override_from_env(&mut image.thumbnail_width, "IMAGE_THUMBNAIL_WIDTH")?;
+        override_from_env(&mut image.max_file_size, "IMAGE_MAX_FILE_SIZE")?;
+        override_from_env(&mut image.max_pixels, "IMAGE_MAX_PIXELS")?;
+        override_from_env(&mut image.min_quality, "IMAGE_MIN_QUALITY")?;
+        override_option_from_env(&mut image.thumbnail_quality, "IMAGE_THUMBNAIL_QUALITY")?;
+        override_option_from_env(
+            &mut image.thumbnail_max_file_size,
+            "IMAGE_THUMBNAIL_MAX_FILE_SIZE",
+        )?;
+        override_from_env(&mut image.on_size_exceeded, "IMAGE_ON_SIZE_EXCEEDED")?;
+        override_from_env(&mut image.thumbnail_square, "IMAGE_THUMBNAIL_SQUARE")?;
+        override_from_env(&mut image.write_sidecar, "IMAGE_WRITE_SIDECAR")?;
+        override_from_env(&mut image.skip_if_smaller, "IMAGE_SKIP_IF_SMALLER")?;
+        override_from_env(&mut image.preserve_metadata, "IMAGE_PRESERVE_METADATA")?;
+        override_from_env(&mut image.force_alpha, "IMAGE_FORCE_ALPHA")?;
+        override_from_env(&mut image.convert_animations, "IMAGE_CONVERT_ANIMATIONS")?;
+        override_from_env(&mut image.lossless, "IMAGE_LOSSLESS")?;
+        override_from_env(&mut image.auto_quality, "IMAGE_AUTO_QUALITY")?;
+        override_from_env(&mut image.target_ssim, "IMAGE_TARGET_SSIM")?;
+        override_from_env(&mut image.quality_ladder, "IMAGE_QUALITY_LADDER")?;
+        override_from_env(&mut image.variants, "IMAGE_VARIANTS")?;
+        override_from_env(
+            &mut image.placeholder_strategy,
+            "IMAGE_PLACEHOLDER_STRATEGY",
+        )?;
+        override_from_env(&mut image.lqip, "IMAGE_LQIP")?;
+
+        override_option_from_env(&mut self.metrics.statsd_endpoint, "METRICS_STATSD_ENDPOINT")?;
+        override_from_env(&mut self.metrics.prefix, "METRICS_PREFIX")?;
+
+        let notifications = &mut self.notifications;
+        override_option_from_env(
+            &mut notifications.completion_webhook_url,
+            "NOTIFICATIONS_COMPLETION_WEBHOOK_URL",
+        )?;
+        override_option_from_env(
+            &mut notifications.webhook_secret,
+            "NOTIFICATIONS_WEBHOOK_SECRET",
+        )?;
+
+        let watermark = &mut self.watermark;
+        override_option_from_env(&mut watermark.image, "WATERMARK_IMAGE")?;
+        override_option_from_env(&mut watermark.text, "WATERMARK_TEXT")?;
+        override_option_from_env(&mut watermark.font_path, "WATERMARK_FONT_PATH")?;
+        override_from_env(&mut watermark.color, "WATERMARK_COLOR")?;
+        override_from_env(&mut watermark.position, "WATERMARK_POSITION")?;
+        override_from_env(&mut watermark.opacity, "WATERMARK_OPACITY")?;
+        override_from_env(&mut watermark.scale, "WATERMARK_SCALE")?;
+        override_from_env(&mut watermark.margin, "WATERMARK_MARGIN")?;
+        override_from_env(&mut watermark.thumbnails, "WATERMARK_THUMBNAILS")?;
+
+        Ok(())
+    }
+
     /// Load configuration from environment variables
     pub fn from_env() -> anyhow::Result<Self> {
         let url = env::var("IMMICH_URL")
             .map_err(|_| anyhow::anyhow!("IMMICH_URL environment variable is required"))?;
 
-        let api_key = env::var("IMMICH_API_KEY")
-            .map_err(|_| anyhow::anyhow!("IMMICH_API_KEY environment variable is required"))?;
+        // A shared-link key takes precedence, so a public mirror needs no API key
+        let auth = match env::var("IMMICH_SHARED_LINK_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())
+        {
+            Some(key) => AuthConfig::SharedLink { key },
+            None => {
+                let api_key = env::var("IMMICH_API_KEY").map_err(|_| {
+                    anyhow::anyhow!(
+                        "IMMICH_API_KEY (or IMMICH_SHARED_LINK_KEY) environment variable is required"
+                    )
+                })?;
+                AuthConfig::ApiKey { api_key }
+            }
+        };
 
         let base_path = env::var("STORAGE_PATH").unwrap_or_else(|_| "./data".to_string());
+
+        let path_template = match env::var("STORAGE_PATH_TEMPLATE") {
+            Ok(template) if !template.is_empty() => template.parse()?,
+            _ => PathTemplate::default(),
+        };
+        let max_total_bytes: u64 = env::var("STORAGE_MAX_TOTAL_BYTES")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+        let eviction_order: EvictionOrder = env::var("STORAGE_EVICTION_ORDER")
+            .unwrap_or_else(|_| "oldest_album".to_string())
+            .parse()
+            .unwrap_or_default();
+        let db_journal_mode: JournalMode = env::var("STORAGE_DB_JOURNAL_MODE")
+            .unwrap_or_else(|_| "wal".to_string())
+            .parse()
+            .unwrap_or_default();
+        let db_busy_timeout_ms: u64 = env::var("STORAGE_DB_BUSY_TIMEOUT_MS")
+            .unwrap_or_else(|_| default_db_busy_timeout_ms().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_db_busy_timeout_ms());
+        let content_addressed: bool = env::var("STORAGE_CONTENT_ADDRESSED")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let min_free_bytes: u64 = env::var("STORAGE_MIN_FREE_BYTES")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+        let write_manifests: bool = env::var("STORAGE_WRITE_MANIFESTS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let split_album_name_on = env::var("STORAGE_SPLIT_ALBUM_NAME_ON")
+            .ok()
+            .filter(|s| !s.is_empty());
+        let encryption_key = env::var("STORAGE_ENCRYPTION_KEY")
+            .ok()
+            .filter(|s| !s.is_empty());
+        let encryption_key_file = env::var("STORAGE_ENCRYPTION_KEY_FILE")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
         let host = env::var("SERVER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
         let port: u16 = env::var("SERVER_PORT")
             .unwrap_or_else(|_| "3000".to_string())
             .parse()
             .unwrap_or(3000);
 
+        let integrity_check_interval_secs: u64 = env::var("SERVER_INTEGRITY_CHECK_INTERVAL")
+            .unwrap_or_else(|_| default_integrity_check_interval().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_integrity_check_interval());
+
+        let db_health_check_interval_secs: u64 = env::var("SERVER_DB_HEALTH_CHECK_INTERVAL")
+            .unwrap_or_else(|_| default_db_health_check_interval().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_db_health_check_interval());
+
+        let serve_originals: bool = env::var("SERVER_SERVE_ORIGINALS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+
+        let server_api_key = env::var("SERVER_API_KEY").ok().filter(|k| !k.is_empty());
+
+        let not_found_image = env::var("SERVER_NOT_FOUND_IMAGE")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+        let not_found_status: u16 = env::var("SERVER_NOT_FOUND_STATUS")
+            .unwrap_or_else(|_| default_not_found_status().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_not_found_status());
+        let pending_placeholder: bool = env::var("SERVER_PENDING_PLACEHOLDER")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let allowed_origins: Vec<String> = env::var("SERVER_ALLOWED_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|o| o.trim().to_string())
+            .filter(|o| !o.is_empty())
+            .collect();
+        let tls_cert_path = env::var("SERVER_TLS_CERT_PATH")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+        let tls_key_path = env::var("SERVER_TLS_KEY_PATH")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+        let rate_limit_per_second: f64 = env::var("SERVER_RATE_LIMIT_PER_SECOND")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0.0);
+        let rate_limit_burst: u32 = env::var("SERVER_RATE_LIMIT_BURST")
+            .unwrap_or_else(|_| default_rate_limit_burst().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_rate_limit_burst());
+        let trust_forwarded_for: bool = env::var("SERVER_TRUST_FORWARDED_FOR")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let http2: bool = env::var("SERVER_HTTP2")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse()
+            .unwrap_or(true);
+        let http2_max_concurrent_streams: u32 = env::var("SERVER_HTTP2_MAX_CONCURRENT_STREAMS")
+            .unwrap_or_else(|_| default_http2_max_concurrent_streams().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_http2_max_concurrent_streams());
+        let http2_keep_alive_interval_secs: u64 = env::var("SERVER_HTTP2_KEEP_ALIVE_INTERVAL")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+        let http2_keep_alive_timeout_secs: u64 = env::var("SERVER_HTTP2_KEEP_ALIVE_TIMEOUT")
+            .unwrap_or_else(|_| default_http2_keep_alive_timeout().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_http2_keep_alive_timeout());
+        let keep_alive: bool = env::var("SERVER_KEEP_ALIVE")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse()
+            .unwrap_or(true);
+        let response_headers = parse_header_list(
+            &env::var("SERVER_RESPONSE_HEADERS").unwrap_or_default(),
+            "SERVER_RESPONSE_HEADERS",
+        )?;
+
         let delete_removed: bool = env::var("SYNC_DELETE_REMOVED")
             .unwrap_or_else(|_| "false".to_string())
             .parse()
             .unwrap_or(false);
 
+        let prune_removed_albums: Option<bool> = env::var("SYNC_PRUNE_REMOVED_ALBUMS")
+            .ok()
+            .and_then(|v| v.parse().ok());
+
         let parallel_downloads: usize = env::var("SYNC_PARALLEL_DOWNLOADS")
             .unwrap_or_else(|_| "4".to_string())
             .parse()
@@ -174,6 +1832,60 @@ impl Config {
             .unwrap_or_else(|_| "2".to_string())
             .parse()
             .unwrap_or(2);
+        let max_conversion_memory_mb: u64 = env::var("SYNC_MAX_CONVERSION_MEMORY_MB")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+
+        let conversion_active_hours = env::var("SYNC_CONVERSION_ACTIVE_HOURS")
+            .ok()
+            .filter(|h| !h.is_empty())
+            .map(|h| h.parse::<ActiveHours>())
+            .transpose()?;
+
+        let schedule = env::var("SYNC_SCHEDULE")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<SyncSchedule>())
+            .transpose()?;
+
+        let source_library_path = env::var("SYNC_SOURCE_LIBRARY_PATH")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+
+        let source_link_mode: LinkMode = env::var("SYNC_SOURCE_LINK_MODE")
+            .unwrap_or_else(|_| "hardlink".to_string())
+            .parse()
+            .unwrap_or_default();
+        let skip_mime_types: Vec<String> = env::var("SYNC_SKIP_MIME_TYPES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        let removal_mode: RemovalMode = env::var("SYNC_REMOVAL_MODE")
+            .unwrap_or_else(|_| "delete".to_string())
+            .parse()
+            .unwrap_or_default();
+        let trash_retention_days: u64 = env::var("SYNC_TRASH_RETENTION_DAYS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .unwrap_or(30);
+        let owner_ids: Vec<String> = env::var("SYNC_OWNER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect();
+        let immich_thumbnails: bool = env::var("SYNC_IMMICH_THUMBNAILS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let watch_originals: bool = env::var("SYNC_WATCH_ORIGINALS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
 
         let image_quality: f32 = env::var("IMAGE_QUALITY")
             .unwrap_or_else(|_| "80.0".to_string())
@@ -195,15 +1907,202 @@ impl Config {
             .parse()
             .unwrap_or(10 * 1024 * 1024);
 
+        let image_max_pixels: u64 = env::var("IMAGE_MAX_PIXELS")
+            .unwrap_or_else(|_| default_max_pixels().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_max_pixels());
+
         let image_min_quality: f32 = env::var("IMAGE_MIN_QUALITY")
             .unwrap_or_else(|_| "30.0".to_string())
             .parse()
             .unwrap_or(30.0);
 
-        Ok(Config {
+        let image_thumbnail_quality: Option<f32> = env::var("IMAGE_THUMBNAIL_QUALITY")
+            .ok()
+            .and_then(|q| q.parse().ok());
+
+        let image_thumbnail_max_file_size: Option<u64> = env::var("IMAGE_THUMBNAIL_MAX_FILE_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok());
+
+        let image_thumbnail_square: bool = env::var("IMAGE_THUMBNAIL_SQUARE")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+
+        let image_write_sidecar: bool = env::var("IMAGE_WRITE_SIDECAR")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+
+        let album_page_size: u32 = env::var("IMMICH_ALBUM_PAGE_SIZE")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+
+        let max_album_pages: u32 = env::var("IMMICH_MAX_ALBUM_PAGES")
+            .unwrap_or_else(|_| default_max_album_pages().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_max_album_pages());
+
+        let api_timeout_secs: u64 = env::var("IMMICH_API_TIMEOUT_SECS")
+            .unwrap_or_else(|_| default_api_timeout_secs().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_api_timeout_secs());
+        let download_timeout_secs: u64 = env::var("IMMICH_DOWNLOAD_TIMEOUT_SECS")
+            .unwrap_or_else(|_| default_download_timeout_secs().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_download_timeout_secs());
+        let connect_timeout_secs: u64 = env::var("IMMICH_CONNECT_TIMEOUT_SECS")
+            .unwrap_or_else(|_| default_connect_timeout_secs().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_connect_timeout_secs());
+        let pool_max_idle: usize = env::var("IMMICH_POOL_MAX_IDLE")
+            .unwrap_or_else(|_| default_pool_max_idle().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_pool_max_idle());
+        let max_concurrent_requests: usize = env::var("IMMICH_MAX_CONCURRENT_REQUESTS")
+            .unwrap_or_else(|_| default_max_concurrent_requests().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_max_concurrent_requests());
+
+        let retry_attempts: u32 = env::var("IMMICH_RETRY_ATTEMPTS")
+            .unwrap_or_else(|_| default_retry_attempts().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_retry_attempts());
+        let retry_backoff_ms: u64 = env::var("IMMICH_RETRY_BACKOFF_MS")
+            .unwrap_or_else(|_| default_retry_backoff_ms().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_retry_backoff_ms());
+        let user_agent = env::var("IMMICH_USER_AGENT")
+            .ok()
+            .filter(|u| !u.is_empty())
+            .unwrap_or_else(default_user_agent);
+        let proxy = env::var("IMMICH_PROXY").ok().filter(|p| !p.is_empty());
+        let insecure_skip_tls_verify: bool = env::var("IMMICH_INSECURE_SKIP_TLS_VERIFY")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+
+        let image_skip_if_smaller: bool = env::var("IMAGE_SKIP_IF_SMALLER")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let image_preserve_metadata: bool = env::var("IMAGE_PRESERVE_METADATA")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let image_force_alpha: bool = env::var("IMAGE_FORCE_ALPHA")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let image_convert_animations: bool = env::var("IMAGE_CONVERT_ANIMATIONS")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse()
+            .unwrap_or(true);
+        let image_lossless: bool = env::var("IMAGE_LOSSLESS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let image_auto_quality: bool = env::var("IMAGE_AUTO_QUALITY")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let image_target_ssim: f32 = env::var("IMAGE_TARGET_SSIM")
+            .unwrap_or_else(|_| default_target_ssim().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_target_ssim());
+        let image_quality_ladder: QualityLadder = env::var("IMAGE_QUALITY_LADDER")
+            .ok()
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+        let image_variants: Variants = env::var("IMAGE_VARIANTS")
+            .ok()
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+        let image_placeholder_strategy: PlaceholderStrategy =
+            env::var("IMAGE_PLACEHOLDER_STRATEGY")
+                .unwrap_or_else(|_| "none".to_string())
+                .parse()
+                .unwrap_or_default();
+        let image_lqip: bool = env::var("IMAGE_LQIP")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+        let image_on_size_exceeded: SizeExceededPolicy = env::var("IMAGE_ON_SIZE_EXCEEDED")
+            .unwrap_or_else(|_| "save_anyway".to_string())
+            .parse()
+            .unwrap_or_default();
+
+        let api_probe: ApiProbeMode = env::var("IMMICH_API_PROBE")
+            .unwrap_or_else(|_| "strict".to_string())
+            .parse()
+            .unwrap_or_default();
+
+        let statsd_endpoint = env::var("METRICS_STATSD_ENDPOINT")
+            .ok()
+            .filter(|e| !e.is_empty());
+        let metrics_prefix =
+            env::var("METRICS_PREFIX").unwrap_or_else(|_| default_metrics_prefix());
+
+        let completion_webhook_url = env::var("NOTIFICATIONS_COMPLETION_WEBHOOK_URL")
+            .ok()
+            .filter(|u| !u.is_empty());
+        let webhook_secret = env::var("NOTIFICATIONS_WEBHOOK_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty());
+
+        let watermark_image = env::var("WATERMARK_IMAGE")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+        let watermark_text = env::var("WATERMARK_TEXT").ok().filter(|t| !t.is_empty());
+        let watermark_font_path = env::var("WATERMARK_FONT_PATH")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+        let watermark_color =
+            env::var("WATERMARK_COLOR").unwrap_or_else(|_| default_watermark_color());
+        let watermark_position: WatermarkPosition = env::var("WATERMARK_POSITION")
+            .unwrap_or_else(|_| "bottom_right".to_string())
+            .parse()
+            .unwrap_or_default();
+        let watermark_opacity: f32 = env::var("WATERMARK_OPACITY")
+            .unwrap_or_else(|_| default_watermark_opacity().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_watermark_opacity());
+        let watermark_scale: f32 = env::var("WATERMARK_SCALE")
+            .unwrap_or_else(|_| default_watermark_scale().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_watermark_scale());
+        let watermark_margin: f32 = env::var("WATERMARK_MARGIN")
+            .unwrap_or_else(|_| default_watermark_margin().to_string())
+            .parse()
+            .unwrap_or_else(|_| default_watermark_margin());
+        let watermark_thumbnails: bool = env::var("WATERMARK_THUMBNAILS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+
+        let mut config = Config {
             immich: ImmichConfig {
                 url,
-                auth: AuthConfig::ApiKey { api_key },
+                auth,
+                album_page_size,
+                max_album_pages,
+                api_probe,
+                api_timeout_secs,
+                download_timeout_secs,
+                connect_timeout_secs,
+                pool_max_idle,
+                max_concurrent_requests,
+                retry_attempts,
+                retry_backoff_ms,
+                user_agent,
+                proxy,
+                insecure_skip_tls_verify,
             },
             storage: StorageConfig {
                 base_path: PathBuf::from(base_path),
@@ -211,21 +2110,106 @@ impl Config {
                     .unwrap_or_else(|_| default_original_dir()),
                 avif_dir: env::var("STORAGE_AVIF_DIR").unwrap_or_else(|_| default_avif_dir()),
                 db_name: env::var("STORAGE_DB_NAME").unwrap_or_else(|_| default_db_name()),
+                db_journal_mode,
+                db_busy_timeout_ms,
+                path_template,
+                max_total_bytes,
+                eviction_order,
+                content_addressed,
+                min_free_bytes,
+                write_manifests,
+                split_album_name_on,
+                encryption_key,
+                encryption_key_file,
+            },
+            server: ServerConfig {
+                host,
+                port,
+                integrity_check_interval_secs,
+                db_health_check_interval_secs,
+                serve_originals,
+                api_key: server_api_key,
+                not_found_image,
+                not_found_status,
+                pending_placeholder,
+                allowed_origins,
+                response_headers,
+                rate_limit_per_second,
+                rate_limit_burst,
+                trust_forwarded_for,
+                tls_cert_path,
+                tls_key_path,
+                http2,
+                http2_max_concurrent_streams,
+                http2_keep_alive_interval_secs,
+                http2_keep_alive_timeout_secs,
+                keep_alive,
             },
-            server: ServerConfig { host, port },
             sync: SyncConfig {
                 delete_removed,
+                removal_mode,
+                trash_retention_days,
+                prune_removed_albums,
                 parallel_downloads,
                 parallel_conversions,
+                max_conversion_memory_mb,
+                conversion_active_hours,
+                schedule,
+                source_library_path,
+                source_link_mode,
+                skip_mime_types,
+                owner_ids,
+                immich_thumbnails,
+                watch_originals,
             },
             image: ImageConfig {
                 quality: image_quality,
                 max_width: image_max_width,
                 thumbnail_width: image_thumbnail_width,
                 max_file_size: image_max_file_size,
+                max_pixels: image_max_pixels,
                 min_quality: image_min_quality,
+                thumbnail_quality: image_thumbnail_quality,
+                thumbnail_max_file_size: image_thumbnail_max_file_size,
+                on_size_exceeded: image_on_size_exceeded,
+                thumbnail_square: image_thumbnail_square,
+                write_sidecar: image_write_sidecar,
+                skip_if_smaller: image_skip_if_smaller,
+                preserve_metadata: image_preserve_metadata,
+                placeholder_strategy: image_placeholder_strategy,
+                lqip: image_lqip,
+                force_alpha: image_force_alpha,
+                convert_animations: image_convert_animations,
+                lossless: image_lossless,
+                auto_quality: image_auto_quality,
+                target_ssim: image_target_ssim,
+                quality_ladder: image_quality_ladder,
+                variants: image_variants,
+                overrides: HashMap::new(),
             },
-        })
+            metrics: MetricsConfig {
+                statsd_endpoint,
+                prefix: metrics_prefix,
+            },
+            notifications: NotificationsConfig {
+                completion_webhook_url,
+                webhook_secret,
+            },
+            watermark: WatermarkConfig {
+                image: watermark_image,
+                text: watermark_text,
+                font_path: watermark_font_path,
+                color: watermark_color,
+                position: watermark_position,
+                opacity: watermark_opacity,
+                scale: watermark_scale,
+                margin: watermark_margin,
+                thumbnails: watermark_thumbnails,
+            },
+        };
+        config.sync.resolve_parallelism();
+
+        Ok(config)
     }
 
     pub fn original_path(&self) -> PathBuf {
@@ -236,6 +2220,17 @@ impl Config {
         self.storage.base_path.join(&self.storage.avif_dir)
     }
 
+    /// Files of removed images, kept in their layout under `base_path` until the
+    /// retention period ends
+    pub fn trash_path(&self) -> PathBuf {
+        self.storage.base_path.join("trash")
+    }
+
+    /// Content-addressed outputs, as `{ab}/{sha256}.{ext}`
+    pub fn blobs_path(&self) -> PathBuf {
+        self.avif_path().join("blobs")
+    }
+
     pub fn db_path(&self) -> PathBuf {
         self.storage.base_path.join(&self.storage.db_name)
     }
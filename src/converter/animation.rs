@@ -0,0 +1,327 @@
+use super::sequence::{self, Nclx, Sample, Track};
+use super::watermark;
+use crate::encryption::PlainFile;
+use anyhow::{bail, Context as _, Result};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, Frame, Frames, RgbaImage};
+use rav1e::prelude::*;
+use std::io::Read;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Browsers play GIF delays shorter than this at 100 ms, so the AVIF keeps that pace
+const MIN_FRAME_DELAY_MS: u32 = 20;
+const DEFAULT_FRAME_DELAY_MS: u32 = 100;
+
+/// BT.601 luma weights, the matrix signalled for every output
+const BT601: [f32; 3] = [0.2990, 0.5870, 0.1140];
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Gif,
+    Apng,
+}
+
+/// A multi-frame GIF or APNG, probed but not yet encoded
+pub struct Animation {
+    source: PlainFile,
+    format: Format,
+    frame_count: usize,
+    width: u32,
+    height: u32,
+    has_alpha: bool,
+}
+
+impl Animation {
+    /// Probe `source`, returning `None` for still images and formats other
+    /// than GIF and APNG. Output dimensions are scaled down to `max_width`.
+    /// Fails once the frames together hold more than `max_pixels` pixels (0 for
+    /// no limit), as every one of them is decoded again for each encode.
+    pub fn open(
+        source: &PlainFile,
+        max_width: u32,
+        force_alpha: bool,
+        max_pixels: u64,
+    ) -> Result<Option<Self>> {
+        let Some(format) = detect(source)? else {
+            return Ok(None);
+        };
+
+        // Decode everything once to count frames and look for transparency
+        let mut frame_count = 0;
+        let mut has_alpha = force_alpha;
+        let (mut width, mut height) = (0, 0);
+        let mut total_pixels = 0u64;
+        for frame in frames(source, format)? {
+            let frame = frame.context("Failed to decode animation frame")?;
+            (width, height) = frame.buffer().dimensions();
+            total_pixels += u64::from(width) * u64::from(height);
+            if max_pixels > 0 && total_pixels > max_pixels {
+                bail!(
+                    "Animation holds over {:.1} MP across its first {} frames, over the {:.1} MP limit (max_pixels)",
+                    total_pixels as f64 / 1e6,
+                    frame_count + 1,
+                    max_pixels as f64 / 1e6
+                );
+            }
+            has_alpha = has_alpha || frame.buffer().pixels().any(|p| p[3] != u8::MAX);
+            frame_count += 1;
+        }
+        if frame_count < 2 {
+            return Ok(None);
+        }
+
+        if width > max_width {
+            height = ((height as f32 * max_width as f32 / width as f32) as u32).max(1);
+            width = max_width;
+        }
+
+        Ok(Some(Self {
+            source: source.clone(),
+            format,
+            frame_count,
+            width,
+            height,
+            has_alpha,
+        }))
+    }
+
+    /// The same animation scaled to `factor` of its output size
+    pub fn scaled(&self, factor: f32) -> Self {
+        Self {
+            source: self.source.clone(),
+            format: self.format,
+            frame_count: self.frame_count,
+            width: ((self.width as f32 * factor) as u32).max(1),
+            height: ((self.height as f32 * factor) as u32).max(1),
+            has_alpha: self.has_alpha,
+        }
+    }
+
+    /// Encode every frame, watermarked like still images, as an AV1 image
+    /// sequence at `quality` (0-100), or losslessly in RGB
+    pub fn encode(&self, quality: f32, speed: u8, lossless: bool) -> Result<Vec<u8>> {
+        let mut color = Encoder::new(self, ChromaSampling::Cs444, quality, speed, lossless)?;
+        let mut alpha = self
+            .has_alpha
+            .then(|| Encoder::new(self, ChromaSampling::Cs400, quality, speed, lossless))
+            .transpose()?;
+        let mut durations = Vec::with_capacity(self.frame_count);
+
+        for frame in frames(&self.source, self.format)? {
+            let frame = frame.context("Failed to decode animation frame")?;
+            durations.push(frame_delay_ms(&frame));
+
+            let mut rgba = frame.into_buffer();
+            if rgba.width() != self.width || rgba.height() != self.height {
+                rgba =
+                    image::imageops::resize(&rgba, self.width, self.height, FilterType::Lanczos3);
+            }
+            let rgba = watermark::apply(DynamicImage::ImageRgba8(rgba)).into_rgba8();
+
+            color.send(&if lossless {
+                to_gbr(&rgba)
+            } else {
+                to_ycbcr(&rgba)
+            })?;
+            if let Some(alpha) = &mut alpha {
+                let plane: Vec<u8> = rgba.pixels().map(|p| p[3]).collect();
+                alpha.send(&[plane])?;
+            }
+        }
+
+        let color = color.finish()?;
+        let alpha = alpha.map(Encoder::finish).transpose()?;
+        let nclx = if lossless { Nclx::RGB } else { Nclx::YCBCR };
+        sequence::write(
+            self.width,
+            self.height,
+            &durations,
+            &color,
+            alpha.as_ref(),
+            nclx,
+        )
+    }
+}
+
+/// Streaming rav1e encoder for one track
+struct Encoder {
+    ctx: Context<u8>,
+    width: usize,
+    samples: Vec<Sample>,
+}
+
+impl Encoder {
+    fn new(
+        animation: &Animation,
+        chroma: ChromaSampling,
+        quality: f32,
+        speed: u8,
+        lossless: bool,
+    ) -> Result<Self> {
+        let is_color = chroma != ChromaSampling::Cs400;
+        // Quantizer 0 makes rav1e code the frame losslessly
+        let quantizer = if lossless {
+            0
+        } else {
+            quality_to_quantizer(quality).into()
+        };
+        let config = EncoderConfig {
+            width: animation.width as usize,
+            height: animation.height as usize,
+            time_base: Rational::new(1, 1000),
+            bit_depth: 8,
+            chroma_sampling: chroma,
+            pixel_range: PixelRange::Full,
+            color_description: is_color.then_some(ColorDescription {
+                color_primaries: ColorPrimaries::BT709,
+                transfer_characteristics: TransferCharacteristics::SRGB,
+                matrix_coefficients: if lossless {
+                    MatrixCoefficients::Identity
+                } else {
+                    MatrixCoefficients::BT601
+                },
+            }),
+            quantizer,
+            min_quantizer: quantizer as u8,
+            // Frames come out in input order, one packet each
+            low_latency: true,
+            speed_settings: SpeedSettings::from_preset(speed),
+            ..Default::default()
+        };
+        let ctx = Config::new()
+            .with_encoder_config(config)
+            .new_context()
+            .context("Invalid AV1 encoder configuration")?;
+
+        Ok(Self {
+            ctx,
+            width: animation.width as usize,
+            samples: Vec::with_capacity(animation.frame_count),
+        })
+    }
+
+    fn send(&mut self, planes: &[Vec<u8>]) -> Result<()> {
+        let mut frame = self.ctx.new_frame();
+        for (plane, data) in frame.planes.iter_mut().zip(planes) {
+            plane.copy_from_raw_u8(data, self.width, 1);
+        }
+        self.ctx
+            .send_frame(frame)
+            .context("Failed to queue animation frame")?;
+        self.drain()
+    }
+
+    fn finish(mut self) -> Result<Track> {
+        self.ctx.flush();
+        self.drain()?;
+        Ok(Track {
+            config: self.ctx.container_sequence_header(),
+            samples: self.samples,
+        })
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        loop {
+            match self.ctx.receive_packet() {
+                Ok(packet) => {
+                    // Samples must not carry the temporal delimiter OBU
+                    let data = match packet.data.strip_prefix(&[0x12, 0x00]) {
+                        Some(rest) => rest.to_vec(),
+                        None => packet.data,
+                    };
+                    self.samples.push(Sample {
+                        data,
+                        keyframe: packet.frame_type == FrameType::KEY,
+                    });
+                }
+                Err(EncoderStatus::Encoded) => continue,
+                Err(EncoderStatus::NeedMoreData | EncoderStatus::LimitReached) => return Ok(()),
+                Err(e) => bail!("Failed to encode animation frame: {}", e),
+            }
+        }
+    }
+}
+
+fn detect(source: &PlainFile) -> Result<Option<Format>> {
+    let mut magic = [0u8; 8];
+    if source.reader()?.read_exact(&mut magic).is_err() {
+        return Ok(None);
+    }
+
+    if magic.starts_with(b"GIF8") {
+        return Ok(Some(Format::Gif));
+    }
+    if magic == PNG_SIGNATURE {
+        let decoder = PngDecoder::new(source.reader()?)?;
+        return Ok(decoder.is_apng().then_some(Format::Apng));
+    }
+    Ok(None)
+}
+
+/// Full-canvas RGBA frames, with disposal and blending already applied
+fn frames(source: &PlainFile, format: Format) -> Result<Frames<'static>> {
+    let reader = source.reader()?;
+    Ok(match format {
+        Format::Gif => GifDecoder::new(reader)?.into_frames(),
+        Format::Apng => PngDecoder::new(reader)?.apng().into_frames(),
+    })
+}
+
+fn frame_delay_ms(frame: &Frame) -> u32 {
+    let (numer, denom) = frame.delay().numer_denom_ms();
+    let ms = (numer as f32 / denom.max(1) as f32).round() as u32;
+    if ms < MIN_FRAME_DELAY_MS {
+        DEFAULT_FRAME_DELAY_MS
+    } else {
+        ms
+    }
+}
+
+/// 8-bit full-range BT.601 Y, Cb and Cr planes
+fn to_ycbcr(rgba: &RgbaImage) -> [Vec<u8>; 3] {
+    let len = rgba.pixels().len();
+    let mut planes = [
+        Vec::with_capacity(len),
+        Vec::with_capacity(len),
+        Vec::with_capacity(len),
+    ];
+    for px in rgba.pixels() {
+        let (r, g, b) = (f32::from(px[0]), f32::from(px[1]), f32::from(px[2]));
+        let y = BT601[0] * r + BT601[1] * g + BT601[2] * b;
+        let cb = (b - y).mul_add(0.5 / (1.0 - BT601[2]), 128.0);
+        let cr = (r - y).mul_add(0.5 / (1.0 - BT601[0]), 128.0);
+        planes[0].push(y.round().clamp(0.0, 255.0) as u8);
+        planes[1].push(cb.round().clamp(0.0, 255.0) as u8);
+        planes[2].push(cr.round().clamp(0.0, 255.0) as u8);
+    }
+    planes
+}
+
+/// Planes in the G, B, R order AV1 stores RGB in with the identity matrix,
+/// which keeps lossless output bit-exact
+fn to_gbr(rgba: &RgbaImage) -> [Vec<u8>; 3] {
+    let mut planes = [Vec::new(), Vec::new(), Vec::new()];
+    for px in rgba.pixels() {
+        planes[0].push(px[1]);
+        planes[1].push(px[2]);
+        planes[2].push(px[0]);
+    }
+    planes
+}
+
+/// Same quality-to-quantizer curve ravif uses, which it does not export, so
+/// animated and still output at a given quality look alike
+pub(super) fn quality_to_quantizer(quality: f32) -> u8 {
+    let q = quality / 100.0;
+    let x = if q >= 0.85 {
+        (1.0 - q) * 3.0
+    } else if q > 0.25 {
+        1.0 - 0.125 - q * 0.5
+    } else {
+        1.0 - q
+    };
+    (x * 255.0).round() as u8
+}
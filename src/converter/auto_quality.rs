@@ -0,0 +1,156 @@
+//! `auto_quality`: the lowest quality whose output still looks like the source.
+//!
+//! ravif cannot decode, so each trial is an 8-bit, luma-only AV1 encode through
+//! rav1e at the quantizer ravif would use, and SSIM is measured on the frame rav1e
+//! reconstructs against the source luma. The real encode is 10-bit with chroma,
+//! so the trial estimates its SSIM rather than measuring it.
+
+use super::animation::quality_to_quantizer;
+use crate::config::ImageConfig;
+use anyhow::Result;
+use image::DynamicImage;
+use rav1e::prelude::{
+    ChromaSampling, Config, Context, EncoderConfig, EncoderStatus, PixelRange, SpeedSettings, Tune,
+};
+use tracing::debug;
+
+/// Trial encodes per image, including the one at the configured quality
+const MAX_TRIALS: u32 = 6;
+
+/// SSIM window size and the step between windows, in pixels
+const WINDOW: usize = 8;
+const WINDOW_STEP: usize = 4;
+
+/// Pick a quality between `min_quality` and `quality` for `img`: the lowest whose
+/// SSIM reaches `target_ssim`, to the precision the trial budget allows. Falls
+/// back to `quality` when even that misses the target.
+pub fn pick_quality(img: &DynamicImage, config: &ImageConfig, speed: u8) -> Result<f32> {
+    let width = img.width() as usize;
+    let height = img.height() as usize;
+    let source = luma(img);
+    let ssim_at = |quality: f32| -> Result<f64> {
+        let decoded = round_trip(&source, width, height, quality, speed)?;
+        let score = ssim(&source, &decoded, width, height);
+        debug!("Quality {}: SSIM {:.4}", quality, score);
+        Ok(score)
+    };
+
+    let target = f64::from(config.target_ssim);
+    if ssim_at(config.quality)? < target {
+        debug!(
+            "SSIM {} not reached at quality {}, keeping it",
+            target, config.quality
+        );
+        return Ok(config.quality);
+    }
+
+    // Bisect on whole quality steps: `high` meets the target, everything below
+    // `low` is known to miss it
+    let mut low = config.min_quality.min(config.quality).round();
+    let mut high = config.quality;
+    let mut trials = 1;
+    while trials < MAX_TRIALS && low < high {
+        let mid = ((low + high) / 2.0).floor();
+        trials += 1;
+        if ssim_at(mid)? >= target {
+            high = mid;
+        } else {
+            low = mid + 1.0;
+        }
+    }
+    Ok(high)
+}
+
+/// BT.601 luma, as ravif computes it for its YCbCr planes
+fn luma(img: &DynamicImage) -> Vec<u8> {
+    img.to_rgb8()
+        .pixels()
+        .map(|p| {
+            let y = 0.299 * f32::from(p[0]) + 0.587 * f32::from(p[1]) + 0.114 * f32::from(p[2]);
+            y.round() as u8
+        })
+        .collect()
+}
+
+/// Encode `luma` as an 8-bit monochrome still picture and return the decoded plane
+fn round_trip(
+    luma: &[u8],
+    width: usize,
+    height: usize,
+    quality: f32,
+    speed: u8,
+) -> Result<Vec<u8>> {
+    let quantizer = quality_to_quantizer(quality);
+    let config = Config::new().with_encoder_config(EncoderConfig {
+        width,
+        height,
+        bit_depth: 8,
+        chroma_sampling: ChromaSampling::Cs400,
+        pixel_range: PixelRange::Full,
+        still_picture: true,
+        quantizer: quantizer.into(),
+        min_quantizer: quantizer,
+        bitrate: 0,
+        tune: Tune::Psychovisual,
+        speed_settings: SpeedSettings::from_preset(speed),
+        ..Default::default()
+    });
+    let mut ctx: Context<u8> = config.new_context()?;
+    let mut frame = ctx.new_frame();
+    frame.planes[0].copy_from_raw_u8(luma, width, 1);
+    ctx.send_frame(frame)?;
+    ctx.flush();
+
+    loop {
+        match ctx.receive_packet() {
+            Ok(packet) => {
+                let Some(rec) = packet.rec else { continue };
+                return Ok(rec.planes[0]
+                    .rows_iter()
+                    .take(height)
+                    .flat_map(|row| &row[..width])
+                    .copied()
+                    .collect());
+            }
+            Err(EncoderStatus::Encoded) => continue,
+            Err(e) => anyhow::bail!("Trial encode failed: {}", e),
+        }
+    }
+}
+
+/// Mean SSIM over overlapping square windows; images smaller than one window
+/// are compared as a whole
+fn ssim(a: &[u8], b: &[u8], width: usize, height: usize) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let window_w = WINDOW.min(width);
+    let window_h = WINDOW.min(height);
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..=height - window_h).step_by(WINDOW_STEP) {
+        for x in (0..=width - window_w).step_by(WINDOW_STEP) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for row in y..y + window_h {
+                for i in row * width + x..row * width + x + window_w {
+                    let (pa, pb) = (f64::from(a[i]), f64::from(b[i]));
+                    sum_a += pa;
+                    sum_b += pb;
+                    sum_aa += pa * pa;
+                    sum_bb += pb * pb;
+                    sum_ab += pa * pb;
+                }
+            }
+            let n = (window_w * window_h) as f64;
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covar = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / f64::from(windows)
+}
@@ -1,97 +1,1001 @@
-use crate::config::{Config, ImageConfig};
-use crate::db::models::SyncedImage;
+use super::animation::Animation;
+use super::auto_quality;
+use super::budget::MemoryBudget;
+use super::formats::{self, UnsupportedTally};
+use super::manifest::{self, ManifestResult};
+use super::mosaic::{self, MosaicFormat};
+use super::{heif, metadata, placeholder, verify, watermark};
+use crate::config::{
+    Config, ImageConfig, PathVars, PlaceholderStrategy, QualityLadder, SizeExceededPolicy, Variants,
+};
+use crate::db::models::{Album, ConversionLogEntry, NewConversionLogEntry, SyncedImage};
+use crate::encryption::{self, PlainFile};
+use crate::failures::FailureList;
+use crate::immich::ImmichClient;
+use crate::metrics;
+use crate::progress::Progress;
+use crate::server::health::SharedPool;
+use crate::storage::{self, KeyLocks, VolumeUnavailable};
 use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Local, SubsecRound, Utc};
+use exif::{In, Tag};
 use futures::stream::{self, StreamExt};
 use image::imageops::FilterType;
 use image::DynamicImage;
-use ravif::{Encoder, Img};
-use rgb::RGBA8;
+use rav1e::prelude::PixelRange;
+use ravif::{BitDepth, ColorModel, EncodedImage, Encoder, Img, MatrixCoefficients};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rgb::{RGB8, RGBA8};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use sqlx::SqlitePool;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
 use tracing::{debug, info, warn};
-use exif::{In, Tag};
+
+/// rav1e speed preset used for every encode (0 = slowest, 10 = fastest)
+const ENCODER_SPEED: u8 = 4;
+
+/// Longest single sleep while waiting for the conversion window to open
+const MAX_WINDOW_SLEEP: Duration = Duration::from_secs(300);
+
+/// Shrink steps of 10% tried by `on_size_exceeded = "downscale"`, down to about
+/// a third of the configured size
+const MAX_DOWNSCALE_STEPS: i32 = 10;
+
+/// Encoder identifier recorded in sidecar files
+const ENCODER_VERSION: &str = "ravif 0.11";
+
+/// Directory under the AVIF path holding on-demand resizes (`GET /images/:id?w=`)
+const RESIZED_DIR: &str = "resized";
+
+/// Widths `?w=` is rounded up to, so clients cannot make a new resize per pixel
+const RESIZE_WIDTHS: &[u32] = &[160, 320, 480, 640, 800, 1024, 1280, 1600, 1920, 2560, 3840];
+
+/// `?q=` is rounded to a multiple of this
+const RESIZE_QUALITY_STEP: f32 = 10.0;
+
+/// Most cached resizes per image; once reached, other sizes get the stored AVIF
+const MAX_RESIZES_PER_IMAGE: usize = 8;
+
+/// Numbers temporary files, so concurrent resizes never write the same file
+static TEMP_FILE_SEQ: AtomicU64 = AtomicU64::new(0);
 
 pub struct AvifConverter {
-    pool: SqlitePool,
+    /// Loaded on each use, so a pool the server's health check swapped in after
+    /// reconnecting takes effect here too
+    pool: SharedPool,
     config: Config,
+    /// Dedicated pool for CPU-bound decode/encode work, so saturating it never
+    /// starves tokio's blocking pool that serves file I/O
+    workers: Arc<ThreadPool>,
+    /// Output paths handed out during this run, so concurrent conversions never
+    /// resolve to the same file before either is recorded in the database
+    claimed_paths: Arc<Mutex<HashMap<PathBuf, String>>>,
+    checksum_locks: KeyLocks,
+    /// Held while building a cached file on request, so concurrent misses of the
+    /// same file build it once
+    cache_locks: KeyLocks,
+    memory_budget: MemoryBudget,
+    progress_bar: bool,
+    /// Set by `with_force`: when the forced run started
+    force_since: Option<DateTime<Utc>>,
+    /// Set by `with_fetch_missing`: downloads originals that are not on disk
+    client: Option<ImmichClient>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ConversionResult {
     pub converted: usize,
     pub skipped: usize,
     pub failed: usize,
+    /// Originals of a type that cannot or should not be converted
+    pub unsupported: usize,
+    #[serde(flatten)]
+    pub failures: FailureList,
+}
+
+/// Totals from `convert --dry-run`
+#[derive(Debug, Serialize)]
+pub struct DryRunResult {
+    pub images: usize,
+    pub failed: usize,
+    pub source_bytes: u64,
+    pub avif_bytes: u64,
+    pub thumbnail_bytes: u64,
+}
+
+impl DryRunResult {
+    /// Full-size AVIF bytes per source byte (thumbnails excluded)
+    pub fn compression_ratio(&self) -> f64 {
+        if self.source_bytes == 0 {
+            return 0.0;
+        }
+        self.avif_bytes as f64 / self.source_bytes as f64
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResult {
+    pub checked: usize,
+    pub invalid: usize,
+}
+
+struct SizeEstimate {
+    avif_bytes: u64,
+    thumbnail_bytes: u64,
+}
+
+/// Pixels handed to the encoder; opaque images carry no alpha channel at all
+enum EncoderInput {
+    Rgb(Vec<RGB8>),
+    Rgba(Vec<RGBA8>),
+}
+
+/// Files produced by a conversion and the quality values actually used after the
+/// file-size reduction loop. A `None` quality means the original was linked as-is,
+/// or the files of an identical image were reused.
+struct ConversionOutput {
+    avif_path: PathBuf,
+    thumbnail_path: PathBuf,
+    quality: Option<f32>,
+    thumbnail_quality: Option<f32>,
+    placeholder: Option<String>,
+    dominant_color: Option<String>,
+    lqip: Option<String>,
+    /// `image.variants` written next to the AVIF, by name
+    variants: BTreeMap<String, PathBuf>,
+}
+
+/// An encode still over the size limit at `min_quality`, under a policy other than
+/// `save_anyway`
+#[derive(Debug)]
+struct SizeExceeded {
+    size: u64,
+    limit: u64,
+    quality: f32,
+}
+
+impl std::fmt::Display for SizeExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Output is {} bytes at minimum quality {}, over the {} byte limit",
+            self.size, self.quality, self.limit
+        )
+    }
+}
+
+impl std::error::Error for SizeExceeded {}
+
+/// Provenance record written next to each AVIF as `{id}.avif.json`, or per image
+/// as `{hash}.avif.{id}.json` next to a shared blob
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    id: &'a str,
+    source_filename: &'a str,
+    source_checksum: Option<&'a str>,
+    quality: Option<f32>,
+    thumbnail_quality: Option<f32>,
+    max_width: u32,
+    thumbnail_width: u32,
+    thumbnail_square: bool,
+    speed: u8,
+    encoder: &'static str,
+    generated_at: String,
 }
 
 impl AvifConverter {
     pub fn new(pool: SqlitePool, config: Config) -> Self {
-        Self { pool, config }
+        let workers = ThreadPoolBuilder::new()
+            .num_threads(config.sync.parallel_conversions.max(1))
+            .thread_name(|i| format!("avif-worker-{}", i))
+            .build()
+            .expect("Failed to create conversion worker pool");
+        let memory_budget = MemoryBudget::new(config.sync.max_conversion_memory_mb);
+
+        Self {
+            pool: Arc::new(ArcSwap::from_pointee(pool)),
+            config,
+            workers: Arc::new(workers),
+            claimed_paths: Arc::default(),
+            checksum_locks: KeyLocks::default(),
+            cache_locks: KeyLocks::default(),
+            memory_budget,
+            progress_bar: false,
+            force_since: None,
+            client: None,
+        }
+    }
+
+    /// The converter's database pool, for the server to share so its health check
+    /// reconnects both
+    pub fn shared_pool(&self) -> SharedPool {
+        self.pool.clone()
+    }
+
+    /// Current database pool; held for one query so a reconnect takes effect promptly
+    pub fn pool(&self) -> Arc<SqlitePool> {
+        self.pool.load_full()
+    }
+
+    /// Draw a progress bar instead of periodic progress logs when on a terminal
+    pub fn with_progress_bar(mut self, enabled: bool) -> Self {
+        self.progress_bar = enabled;
+        self
+    }
+
+    /// Reconvert already converted images too, replacing their output
+    pub fn with_force(mut self, enabled: bool) -> Self {
+        // `converted_at` has whole-second precision
+        self.force_since = enabled.then(|| Utc::now().trunc_subsecs(0));
+        self
+    }
+
+    /// Download missing originals from Immich before converting them, instead of
+    /// leaving those images to the next sync
+    pub fn with_fetch_missing(mut self, client: ImmichClient) -> Self {
+        self.client = Some(client);
+        self
     }
 
+    /// Images to process, optionally only those of one album: the unconverted ones,
+    /// or every downloaded image when forced. Fetching missing originals also
+    /// queues images that have none, except those the storage limit evicted while
+    /// it is still set, which the run would only evict again.
+    async fn queue(&self, album_id: Option<&str>) -> Result<Vec<SyncedImage>> {
+        let mut images = if self.client.is_some() {
+            let mut images =
+                SyncedImage::get_convertible(&self.pool(), self.force_since.is_some()).await?;
+            if self.config.storage.max_total_bytes > 0 {
+                images.retain(|image| image.evicted_at.is_none());
+            }
+            images
+        } else if self.force_since.is_some() {
+            SyncedImage::get_downloaded(&self.pool()).await?
+        } else {
+            SyncedImage::get_unconverted(&self.pool()).await?
+        };
+        // Every image listed in the album, also those first synced through another
+        if let Some(album_id) = album_id {
+            let members: HashSet<String> = SyncedImage::member_ids(&self.pool(), album_id)
+                .await?
+                .into_iter()
+                .collect();
+            images.retain(|image| members.contains(&image.id));
+        }
+        Ok(images)
+    }
+
+    /// Convert every unconverted image, then apply the storage limit
     pub async fn convert_all(&self) -> Result<ConversionResult> {
-        let unconverted = SyncedImage::get_unconverted(&self.pool).await?;
+        self.backfill_taken_at().await?;
+        let unconverted = self.queue(None).await?;
+        let result = self.convert_images(unconverted).await?;
+
+        match storage::enforce_limit(&self.pool(), &self.config).await {
+            Ok(eviction) => manifest::refresh(&self.pool(), &self.config, &eviction.albums).await,
+            Err(e) => warn!("Failed to enforce storage limit: {}", e),
+        }
+
+        Ok(result)
+    }
 
+    /// Read the capture time of images converted before it was recorded, without
+    /// converting them again
+    async fn backfill_taken_at(&self) -> Result<()> {
+        let pool = self.pool();
+        let pending = SyncedImage::get_taken_at_pending(&pool).await?;
+        if pending.is_empty() {
+            return Ok(());
+        }
+        info!(
+            "Reading capture times of {} converted images",
+            pending.len()
+        );
+        for image in &pending {
+            Self::record_taken_at(&pool, image).await?;
+        }
+        Ok(())
+    }
+
+    /// Store the EXIF capture time of the image's original, if it has one
+    async fn record_taken_at(pool: &SqlitePool, image: &SyncedImage) -> Result<()> {
+        let original = image.original_path.clone();
+        let taken_at = tokio::task::spawn_blocking(move || {
+            let source = PlainFile::open(Path::new(&original?)).ok()?;
+            metadata::capture_time(&source)
+        })
+        .await
+        .unwrap_or_default();
+        SyncedImage::set_taken_at(pool, &image.id, taken_at).await
+    }
+
+    /// Convert the given images now, e.g. as their originals appear on disk
+    pub async fn convert_new(&self, images: Vec<SyncedImage>) -> Result<ConversionResult> {
+        self.convert_images(images).await
+    }
+
+    /// Convert the unconverted images of one album only
+    pub async fn convert_album(&self, album_id: &str) -> Result<ConversionResult> {
+        let unconverted = self.queue(Some(album_id)).await?;
+        self.convert_images(unconverted).await
+    }
+
+    async fn convert_images(&self, unconverted: Vec<SyncedImage>) -> Result<ConversionResult> {
         info!("Found {} images to convert", unconverted.len());
+        if !unconverted.is_empty() {
+            storage::check_volumes(&self.config).await?;
+        }
+
+        let mut unsupported = UnsupportedTally::default();
+        let mut supported = Vec::with_capacity(unconverted.len());
+        for image in unconverted {
+            let mime_type = image.mime_type.as_deref();
+            let skip_mime_types = &self.config.sync.skip_mime_types;
+            match formats::unsupported_reason(&image.filename, mime_type, skip_mime_types) {
+                Some(reason) => {
+                    let label = formats::type_label(&image.filename, mime_type);
+                    let status = format!("{}: {}", label, reason);
+                    SyncedImage::mark_unsupported(&self.pool(), &image.id, &status).await?;
+                    unsupported.add(label, reason);
+                }
+                None => supported.push(image),
+            }
+        }
+        unsupported.log("Not converting");
+        let unconverted = supported;
+        self.claim_output_paths(&unconverted).await?;
 
         let mut result = ConversionResult {
             converted: 0,
             skipped: 0,
             failed: 0,
+            unsupported: unsupported.total(),
+            failures: FailureList::default(),
         };
 
+        let progress = &Progress::new("converted", unconverted.len(), self.progress_bar);
+        // Set once a volume goes away mid-run; the images still queued are left for
+        // the next run instead of each failing
+        let volume_lost = &AtomicBool::new(false);
+
+        // Dispatch in the queue's stable order; after a crash, the completed set is a
+        // prefix of the queue plus at most `parallel_conversions` in-flight stragglers
         let results: Vec<_> = stream::iter(unconverted)
-            .map(|image| {
-                let pool = self.pool.clone();
-                let avif_base = self.config.avif_path();
-                let image_config = self.config.image.clone();
-                async move { Self::convert_image(&pool, &image, &avif_base, &image_config).await }
+            .then(|image| async move {
+                self.wait_for_active_hours().await;
+                image
+            })
+            .map(|image| async move {
+                if volume_lost.load(Ordering::Relaxed) {
+                    return (image, Ok(false));
+                }
+                let _memory = self
+                    .memory_budget
+                    .reserve(image.original_path.as_ref().map(PathBuf::from))
+                    .await;
+                let result = self.convert_and_record(&image).await;
+                if result.as_ref().is_err_and(|e| e.is::<VolumeUnavailable>()) {
+                    volume_lost.store(true, Ordering::Relaxed);
+                }
+                progress.inc();
+                (image, result)
             })
             .buffer_unordered(self.config.sync.parallel_conversions)
             .collect()
             .await;
+        if let Some(e) = results.iter().find_map(|(_, result)| {
+            result
+                .as_ref()
+                .err()
+                .and_then(|e| e.downcast_ref::<VolumeUnavailable>())
+        }) {
+            anyhow::bail!("Conversion stopped: {}", e);
+        }
 
-        for conversion_result in results {
+        // A failed forced conversion has discarded the previous output, so failures
+        // change an album's converted set as well
+        let mut changed = Vec::new();
+        for (image, conversion_result) in results {
             match conversion_result {
                 Ok(true) => result.converted += 1,
-                Ok(false) => result.skipped += 1,
+                Ok(false) => {
+                    result.skipped += 1;
+                    continue;
+                }
                 Err(e) => {
-                    warn!("Conversion failed: {}", e);
                     result.failed += 1;
+                    result.failures.push(&image.id, &image.filename, &e);
                 }
             }
+            changed.push(image.id);
         }
 
         info!(
-            "Conversion complete: {} converted, {} skipped, {} failed",
-            result.converted, result.skipped, result.failed
+            "Conversion complete: {} converted, {} skipped, {} unsupported, {} failed",
+            result.converted, result.skipped, result.unsupported, result.failed
         );
 
+        if let Err(e) = self.refresh_manifests(&changed).await {
+            warn!("Failed to update album manifests: {}", e);
+        }
+
+        Ok(result)
+    }
+
+    /// Rewrite the manifests of every album listing one of `image_ids`
+    async fn refresh_manifests(&self, image_ids: &[String]) -> Result<()> {
+        if !self.config.storage.write_manifests {
+            return Ok(());
+        }
+        let mut album_ids = BTreeSet::new();
+        for id in image_ids {
+            album_ids.extend(SyncedImage::album_ids(&self.pool(), id).await?);
+        }
+        manifest::refresh(&self.pool(), &self.config, &album_ids).await;
+        Ok(())
+    }
+
+    /// Write `manifest.json` for one album, or for every synced album
+    pub async fn write_manifests(&self, album_id: Option<&str>) -> Result<ManifestResult> {
+        let album_ids = match album_id {
+            Some(album_id) => vec![album_id.to_string()],
+            None => Album::get_all(&self.pool())
+                .await?
+                .into_iter()
+                .map(|album| album.id)
+                .collect(),
+        };
+
+        let mut result = ManifestResult::default();
+        for album_id in &album_ids {
+            result.images += manifest::write(&self.pool(), &self.config, album_id).await?;
+            result.albums += 1;
+        }
         Ok(result)
     }
 
-    async fn convert_image(
-        pool: &SqlitePool,
+    /// Encode every unconverted image in memory to estimate output sizes, without
+    /// writing files or touching the database
+    pub async fn estimate_all(&self, album_id: Option<&str>) -> Result<DryRunResult> {
+        let unconverted = self.queue(album_id).await?;
+        info!("Estimating output size for {} images", unconverted.len());
+
+        let estimates: Vec<_> = stream::iter(unconverted)
+            .map(|image| async move {
+                let estimate = self.estimate_image(&image).await;
+                if let Err(e) = &estimate {
+                    warn!("Failed to estimate {}: {:#}", image.filename, e);
+                }
+                estimate
+            })
+            .buffer_unordered(self.config.sync.parallel_conversions)
+            .collect()
+            .await;
+
+        let mut result = DryRunResult {
+            images: 0,
+            failed: 0,
+            source_bytes: 0,
+            avif_bytes: 0,
+            thumbnail_bytes: 0,
+        };
+        for estimate in estimates {
+            match estimate {
+                Ok((source_bytes, estimate)) => {
+                    result.images += 1;
+                    result.source_bytes += source_bytes;
+                    result.avif_bytes += estimate.avif_bytes;
+                    result.thumbnail_bytes += estimate.thumbnail_bytes;
+                }
+                Err(_) => result.failed += 1,
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Check every converted AVIF and thumbnail on disk and clear the conversion of
+    /// any image whose files are missing or corrupt, so the next run regenerates it
+    pub async fn verify_all(&self) -> Result<VerifyResult> {
+        let converted: Vec<SyncedImage> = SyncedImage::get_with_files(&self.pool())
+            .await?
+            .into_iter()
+            .filter(|image| image.avif_path.is_some() || image.thumbnail_path.is_some())
+            .collect();
+        info!("Verifying {} converted images", converted.len());
+
+        let checks: Vec<_> = stream::iter(converted)
+            .map(|image| async move {
+                let paths: Vec<PathBuf> = [&image.avif_path, &image.thumbnail_path]
+                    .into_iter()
+                    .flatten()
+                    .map(PathBuf::from)
+                    .collect();
+                let outcome = tokio::task::spawn_blocking(move || {
+                    paths.iter().try_for_each(|path| verify::check_file(path))
+                })
+                .await
+                .context("Verification task panicked")
+                .and_then(|outcome| outcome);
+                (image, outcome)
+            })
+            .buffer_unordered(self.config.sync.parallel_conversions)
+            .collect()
+            .await;
+
+        let mut result = VerifyResult {
+            checked: checks.len(),
+            invalid: 0,
+        };
+        for (image, outcome) in checks {
+            if let Err(e) = outcome {
+                warn!(
+                    "Invalid output for {} ({}): {:#}",
+                    image.filename, image.id, e
+                );
+                SyncedImage::clear_conversion(&self.pool(), &image.id).await?;
+                result.invalid += 1;
+            }
+        }
+
+        info!(
+            "Verification complete: {} checked, {} invalid",
+            result.checked, result.invalid
+        );
+
+        Ok(result)
+    }
+
+    /// Returns the source size (as recorded at sync time) alongside the estimate
+    async fn estimate_image(&self, image: &SyncedImage) -> Result<(u64, SizeEstimate)> {
+        let source = image
+            .original_path
+            .as_deref()
+            .map(PathBuf::from)
+            .context("Image has no original file")?;
+        let config = self.config.image.for_album(&image.album_id);
+
+        let worker_source = source.clone();
+        let estimate = self
+            .run_on_worker("Conversion", move || {
+                PlainFile::open(&worker_source)
+                    .and_then(|source| Self::estimate_sizes(&source, &config))
+            })
+            .await?;
+
+        let source_bytes = match image.file_size {
+            Some(size) => size as u64,
+            None => tokio::fs::metadata(&source).await?.len(),
+        };
+        Ok((source_bytes, estimate))
+    }
+
+    /// Hold back the next dispatch until `conversion_active_hours` opens. In-flight
+    /// conversions are unaffected and finish normally.
+    async fn wait_for_active_hours(&self) {
+        let Some(hours) = self.config.sync.conversion_active_hours else {
+            return;
+        };
+
+        let mut logged = false;
+        loop {
+            let wait = hours.until_open(Local::now().time());
+            if wait.is_zero() {
+                if logged {
+                    info!("Conversion window {} open, resuming", hours);
+                }
+                return;
+            }
+            if !logged {
+                info!(
+                    "Outside conversion window {}, pausing for {}m",
+                    hours,
+                    wait.as_secs() / 60
+                );
+                logged = true;
+            }
+            // Re-check periodically so clock changes (DST, NTP) are picked up
+            tokio::time::sleep(wait.min(MAX_WINDOW_SLEEP)).await;
+        }
+    }
+
+    /// Discard an image's existing output and convert it again with the current settings
+    pub async fn reconvert(&self, image: &SyncedImage) -> Result<SyncedImage> {
+        self.discard_output(image).await?;
+        let memory = self
+            .memory_budget
+            .reserve(image.original_path.as_ref().map(PathBuf::from))
+            .await;
+        let converted = self.convert_and_record(image).await?;
+        drop(memory);
+        // The old output is gone either way
+        if let Err(e) = self
+            .refresh_manifests(std::slice::from_ref(&image.id))
+            .await
+        {
+            warn!("Failed to update album manifests: {}", e);
+        }
+        if !converted {
+            return Err(anyhow::anyhow!(
+                "Original for {} is not available",
+                image.id
+            ));
+        }
+
+        SyncedImage::get_by_id(&self.pool(), &image.id)
+            .await?
+            .context("Image disappeared during reconversion")
+    }
+
+    /// The stored AVIF of the image's `name` variant. Returns `None` when the
+    /// full-size AVIF stands in for it: for `full`, and for images without variant
+    /// files, such as animations, originals linked as-is and images converted
+    /// before the variant was configured.
+    pub fn variant(&self, image: &SyncedImage, name: &str) -> Result<Option<PathBuf>> {
+        if name == Variants::FULL {
+            return Ok(None);
+        }
+        if !self.config.image.variants.iter().any(|v| v.name == name) {
+            anyhow::bail!("Unknown variant '{}'", name);
+        }
+        Ok(image
+            .variant_paths()
+            .remove(name)
+            .map(PathBuf::from)
+            .filter(|path| path.exists()))
+    }
+
+    /// PNG to show while the image has no AVIF yet, from whatever an earlier
+    /// conversion left: see `placeholder::pending_image`
+    pub async fn pending_placeholder(&self, image: &SyncedImage) -> Result<Vec<u8>> {
+        let strategy = self
+            .config
+            .image
+            .for_album(&image.album_id)
+            .placeholder_strategy;
+        let blurhash = image
+            .placeholder
+            .clone()
+            .filter(|_| strategy == PlaceholderStrategy::Blurhash);
+        let dominant_color = image.dominant_color.clone();
+        let original = image.original_path.clone().map(PathBuf::from);
+
+        tokio::task::spawn_blocking(move || {
+            let aspect = original.as_deref().and_then(Self::original_aspect);
+            placeholder::pending_image(blurhash.as_deref(), dominant_color.as_deref(), aspect)
+        })
+        .await?
+    }
+
+    /// Width / height of an original as displayed, from its header alone.
+    /// Encrypted originals are not decrypted for this.
+    fn original_aspect(path: &Path) -> Option<f32> {
+        if encryption::is_encrypted(path).ok()? {
+            return None;
+        }
+        let source = PlainFile::open(path).ok()?;
+        let (width, height) = formats::source_reader(&source)
+            .ok()?
+            .into_dimensions()
+            .ok()?;
+        // Orientations 5-8 turn the image by 90 degrees
+        let (width, height) = match Self::read_exif_orientation(&source) {
+            Some(5..=8) => (height, width),
+            _ => (width, height),
+        };
+        (height > 0).then(|| width as f32 / height as f32)
+    }
+
+    /// A `width`-pixel-wide AVIF of the image, optionally at another `quality`,
+    /// encoded from the original and cached as `resized/{id}/w{width}.avif`.
+    ///
+    /// Widths are rounded up to one of `RESIZE_WIDTHS` and capped at `max_width`,
+    /// qualities rounded to a multiple of `RESIZE_QUALITY_STEP`. Returns `None` when
+    /// the stored AVIF should be served instead: the request asks for its full size,
+    /// the original is gone, or the image already has `MAX_RESIZES_PER_IMAGE`
+    /// resizes cached.
+    pub async fn resized(
+        &self,
         image: &SyncedImage,
-        avif_base: &Path,
-        image_config: &ImageConfig,
-    ) -> Result<bool> {
-        let original_path = match &image.original_path {
-            Some(p) => PathBuf::from(p),
-            None => return Ok(false),
+        width: u32,
+        quality: Option<f32>,
+    ) -> Result<Option<PathBuf>> {
+        let mut config = self.config.image.for_album(&image.album_id);
+        let width = RESIZE_WIDTHS
+            .iter()
+            .copied()
+            .find(|&bucket| bucket >= width)
+            .unwrap_or(config.max_width)
+            .min(config.max_width);
+        let quality = quality.map(|q| {
+            ((q / RESIZE_QUALITY_STEP).round() * RESIZE_QUALITY_STEP)
+                .clamp(RESIZE_QUALITY_STEP, 100.0)
+        });
+        if width == config.max_width && quality.is_none() {
+            return Ok(None);
+        }
+        let Some(original) = image
+            .original_path
+            .as_deref()
+            .map(PathBuf::from)
+            .filter(|p| p.exists())
+        else {
+            return Ok(None);
+        };
+
+        let name = match quality {
+            Some(quality) => format!("w{}_q{}.avif", width, quality),
+            None => format!("w{}.avif", width),
+        };
+        let dir = self.config.avif_path().join(RESIZED_DIR).join(&image.id);
+        let path = dir.join(name);
+
+        // Reconverting rewrites the full AVIF, which makes older resizes stale
+        let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+        let converted = image
+            .avif_path
+            .as_deref()
+            .and_then(|p| modified(Path::new(p)));
+        let fresh = |p: &Path| {
+            modified(p).is_some_and(|cached| converted.is_none_or(|converted| cached >= converted))
+        };
+        if fresh(&path) {
+            return Ok(Some(path));
+        }
+
+        let _guard = self.cache_locks.lock(&path.to_string_lossy()).await;
+        // Made by a request that held the lock before us
+        if fresh(&path) {
+            return Ok(Some(path));
+        }
+        let mut cached = 0;
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            if fresh(&entry.path()) {
+                cached += 1;
+            } else {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        if cached >= MAX_RESIZES_PER_IMAGE {
+            debug!(
+                "{} already has {} resizes, serving the stored AVIF",
+                image.id, cached
+            );
+            return Ok(None);
+        }
+
+        // An explicit quality asks for lossy output, even in a lossless album
+        if let Some(quality) = quality {
+            config.quality = quality;
+            config.min_quality = config.min_quality.min(quality);
+            config.lossless = false;
+            config.auto_quality = false;
+            config.quality_ladder = QualityLadder::default();
+        }
+        debug!("Resizing {} to {}px", image.id, width);
+
+        let _memory = self.memory_budget.reserve(Some(original.clone())).await;
+        let dest = path.clone();
+        self.run_on_worker("Resize", move || {
+            PlainFile::open(&original)
+                .and_then(|source| Self::do_resize(&source, &dest, width, &config))
+        })
+        .await?;
+
+        Ok(Some(path))
+    }
+
+    fn do_resize(source: &PlainFile, dest: &Path, width: u32, config: &ImageConfig) -> Result<()> {
+        formats::check_pixels(source, config.max_pixels)?;
+        let img = Self::open_source(source)?;
+        let exif = if config.preserve_metadata {
+            metadata::read_preserved_exif(source)
+        } else {
+            None
+        };
+        let resized = watermark::apply(Self::resize_image(&img, width));
+        let (avif_file, _) =
+            Self::encode(&resized, &config.at_width(resized.width()), exif.as_deref())?;
+
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::write_replacing(dest, &avif_file)
+    }
+
+    /// A grid of the album's converted images, `cols` tiles of `size` pixels to a
+    /// row, cached under `mosaics/` until the album's converted count changes.
+    ///
+    /// Tiles come from Immich's thumbnails, as stored AVIFs cannot be decoded and
+    /// decoding originals is too costly for an unauthenticated request. At most
+    /// `mosaic::MAX_TILES` images are included. Returns `None` when the album has
+    /// no converted image with a synced Immich thumbnail.
+    pub async fn mosaic(
+        &self,
+        album_id: &str,
+        cols: u32,
+        size: u32,
+        format: MosaicFormat,
+    ) -> Result<Option<PathBuf>> {
+        let images = SyncedImage::get_converted_by_album(&self.pool(), album_id).await?;
+        if images.is_empty() {
+            return Ok(None);
+        }
+        let path = mosaic::cache_path(
+            &self.config.avif_path(),
+            album_id,
+            cols,
+            size,
+            images.len(),
+            format,
+        );
+        if path.exists() {
+            return Ok(Some(path));
+        }
+        if !self.config.sync.immich_thumbnails {
+            return Ok(None);
+        }
+
+        let _guard = self.cache_locks.lock(&path.to_string_lossy()).await;
+        // Built by a request that held the lock before us
+        if path.exists() {
+            return Ok(Some(path));
+        }
+
+        let sources: Vec<PathBuf> = images
+            .iter()
+            .filter_map(|image| image.original_path.as_deref())
+            .map(|original| storage::immich_thumbnail_path(Path::new(original)))
+            .filter(|thumbnail| thumbnail.exists())
+            .take(mosaic::MAX_TILES)
+            .collect();
+        if sources.is_empty() {
+            return Ok(None);
+        }
+
+        let config = ImageConfig {
+            // A grid of many thumbnails is not held to the single-thumbnail limit
+            max_file_size: u64::MAX,
+            ..self.config.image.for_album(album_id).for_thumbnail()
         };
+        debug!(
+            "Building mosaic of album {} from {} images",
+            album_id,
+            sources.len()
+        );
+
+        let rows = (sources.len() as u64).div_ceil(cols as u64);
+        let canvas = cols as u64 * rows * size as u64 * size as u64;
+        let _memory = self
+            .memory_budget
+            .reserve_pixels(canvas, &format!("the mosaic of album {}", album_id))
+            .await;
+        let dest = path.clone();
+        let built = self
+            .run_on_worker("Mosaic", move || {
+                Self::do_mosaic(&sources, &dest, cols, size, format, &config)
+            })
+            .await?;
+
+        Ok(built.then_some(path))
+    }
 
-        if !original_path.exists() {
-            warn!("Original file not found: {:?}", original_path);
+    fn do_mosaic(
+        sources: &[PathBuf],
+        dest: &Path,
+        cols: u32,
+        size: u32,
+        format: MosaicFormat,
+        config: &ImageConfig,
+    ) -> Result<bool> {
+        let tiles: Vec<_> = sources
+            .iter()
+            .filter_map(|thumbnail| Self::decode_immich_thumbnail(thumbnail))
+            .map(|img| mosaic::tile(&img, size))
+            .collect();
+        if tiles.is_empty() {
             return Ok(false);
         }
 
-        let avif_path = avif_base
-            .join(&image.album_id)
-            .join(format!("{}.avif", image.id));
+        let grid = mosaic::compose(&tiles, cols, size);
+        let bytes = match format {
+            MosaicFormat::Avif => Self::encode(&grid, config, None)?.0,
+            MosaicFormat::Jpeg => mosaic::encode_jpeg(&grid)?,
+        };
 
-        let thumbnail_path = avif_base
-            .join(&image.album_id)
-            .join(format!("{}_thumb.avif", image.id));
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::write_replacing(dest, &bytes)?;
+        mosaic::remove_stale(dest);
+        Ok(true)
+    }
+
+    /// Remove an image's AVIF, thumbnail and variants and clear its conversion data
+    async fn discard_output(&self, image: &SyncedImage) -> Result<()> {
+        let variants = image.variant_paths().into_values();
+        for path in [image.avif_path.clone(), image.thumbnail_path.clone()]
+            .into_iter()
+            .flatten()
+            .chain(variants)
+        {
+            if let Err(e) = storage::remove_output(&self.config, &path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(e).context(format!("Failed to remove {}", path));
+                }
+            }
+        }
+        SyncedImage::clear_conversion(&self.pool(), &image.id).await
+    }
+
+    /// Convert one image, persisting the error on the row if it fails
+    async fn convert_and_record(&self, image: &SyncedImage) -> Result<bool> {
+        let started = Instant::now();
+        let result = match self.force_since {
+            Some(_) if image.converted_at.is_some() => match self.discard_output(image).await {
+                Ok(()) => self.convert_image(image).await,
+                Err(e) => Err(e),
+            },
+            _ => self.convert_image(image).await,
+        };
+        // A filesystem error is only the image's failure while the volumes are
+        // still writable; otherwise it stops the run without being recorded
+        let result = match result {
+            Err(e) if storage::is_io_error(&e) => {
+                match storage::check_volumes(&self.config).await {
+                    Err(unavailable) => return Err(unavailable.into()),
+                    Ok(()) => Err(e),
+                }
+            }
+            result => result,
+        };
+        match &result {
+            Ok(true) => {
+                metrics::incr("conversions.converted");
+                metrics::timing("conversions.duration", started.elapsed());
+            }
+            Ok(false) => metrics::incr("conversions.skipped"),
+            Err(_) => metrics::incr("conversions.failed"),
+        }
+        if let Err(e) = &result {
+            warn!(
+                "Conversion failed for {} ({}): {:#}",
+                image.filename, image.id, e
+            );
+            let error = format!("{:#}", e);
+            self.log_conversion(image, "failed", None, started, Some(&error))
+                .await;
+            if let Err(db_err) =
+                SyncedImage::mark_conversion_failed(&self.pool(), &image.id, &error).await
+            {
+                warn!(
+                    "Failed to record conversion error for {}: {}",
+                    image.id, db_err
+                );
+            }
+        }
+        result
+    }
+
+    async fn convert_image(&self, image: &SyncedImage) -> Result<bool> {
+        let started = Instant::now();
+        let pool = &self.pool();
+        let image_config = &self.config.image.for_album(&image.album_id);
+
+        let Some((image, original_path)) = self.local_original(image).await? else {
+            return Ok(false);
+        };
+        let image = &image;
+
+        let avif_path = self.output_path(image, &original_path).await?;
+        let thumbnail_path = Self::thumbnail_path_for(&avif_path);
 
         if avif_path.exists() && thumbnail_path.exists() {
             debug!("AVIF and thumbnail already exist: {:?}", avif_path);
@@ -102,81 +1006,818 @@ impl AvifConverter {
                 thumbnail_path.to_str().unwrap_or(""),
             )
             .await?;
+            Self::record_avif_size(pool, &image.id, &avif_path).await?;
             return Ok(false);
         }
 
+        // Held until recorded, so an identical image converting in parallel waits
+        // and then reuses this one's output
+        let _guard = match &image.checksum {
+            Some(checksum) => Some(self.checksum_locks.lock(checksum).await),
+            None => None,
+        };
+        // The file watcher and a scheduled pass can both pick up a new image; the
+        // second to get here finds it already converted
+        if self.force_since.is_none() {
+            let current = SyncedImage::get_by_id(pool, &image.id).await?;
+            if current.is_some_and(|c| c.converted_at.is_some() && c.thumbnail_path.is_some()) {
+                debug!("{} was converted in the meantime", image.id);
+                return Ok(false);
+            }
+        }
+        if let Some(output) = self
+            .reuse_duplicate(image, image_config, &avif_path, &thumbnail_path)
+            .await?
+        {
+            self.record_output(image, image_config, &output).await?;
+            self.log_conversion(image, "reused", Some(&output), started, None)
+                .await;
+            return Ok(true);
+        }
+
         info!("Converting: {} -> {:?}", image.filename, avif_path);
 
         let original_path_clone = original_path.clone();
+        let immich_thumbnail = Some(storage::immich_thumbnail_path(&original_path))
+            .filter(|path| self.config.sync.immich_thumbnails && path.exists());
         let avif_path_clone = avif_path.clone();
         let thumbnail_path_clone = thumbnail_path.clone();
         let config_clone = image_config.clone();
+        let filename = image.filename.clone();
+        let mime_type = image.mime_type.clone();
+        let blobs_path = self
+            .config
+            .storage
+            .content_addressed
+            .then(|| self.config.blobs_path());
 
-        let result = tokio::task::spawn_blocking(move || {
-            Self::do_conversion(
-                &original_path_clone,
-                &avif_path_clone,
-                &thumbnail_path_clone,
-                &config_clone,
-            )
-        })
-        .await?;
+        let result = self
+            .run_on_worker("Conversion", move || {
+                let output = PlainFile::open(&original_path_clone).and_then(|source| {
+                    formats::check_declared(&source, &filename, mime_type.as_deref());
+                    Self::do_conversion(
+                        &source,
+                        immich_thumbnail.as_deref(),
+                        &avif_path_clone,
+                        &thumbnail_path_clone,
+                        &config_clone,
+                    )
+                });
+                match blobs_path {
+                    Some(blobs_path) => {
+                        output.and_then(|output| Self::store_blobs(output, &blobs_path))
+                    }
+                    None => output,
+                }
+            })
+            .await;
 
         match result {
-            Ok(()) => {
-                SyncedImage::mark_converted(
-                    pool,
-                    &image.id,
-                    avif_path.to_str().unwrap_or(""),
-                    thumbnail_path.to_str().unwrap_or(""),
-                )
-                .await?;
+            Ok(output) => {
+                self.record_output(image, image_config, &output).await?;
+                self.log_conversion(image, "converted", Some(&output), started, None)
+                    .await;
                 Ok(true)
             }
             Err(e) => Err(e),
         }
     }
 
+    /// Run `job` on the conversion workers. A panic in it, such as a decoder bug
+    /// hit by one odd file, fails only that job instead of aborting the process.
+    async fn run_on_worker<T, F>(&self, what: &'static str, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.workers.spawn(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job))
+                .unwrap_or_else(|panic| {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown cause".to_string());
+                    Err(anyhow::anyhow!("{} worker panicked: {}", what, message))
+                });
+            let _ = tx.send(result);
+        });
+        rx.await
+            .with_context(|| format!("{} worker exited unexpectedly", what))?
+    }
+
+    /// Add a row to the image's conversion history. A failed insert is only
+    /// logged, as the history must never fail a conversion.
+    async fn log_conversion(
+        &self,
+        image: &SyncedImage,
+        outcome: &str,
+        output: Option<&ConversionOutput>,
+        started: Instant,
+        error: Option<&str>,
+    ) {
+        let output_size = match output {
+            Some(output) => tokio::fs::metadata(&output.avif_path)
+                .await
+                .ok()
+                .map(|m| m.len() as i64),
+            None => None,
+        };
+        let entry = NewConversionLogEntry {
+            image_id: &image.id,
+            outcome,
+            input_size: image.file_size,
+            output_size,
+            quality: output.and_then(|o| o.quality),
+            thumbnail_quality: output.and_then(|o| o.thumbnail_quality),
+            duration_ms: started.elapsed().as_millis() as i64,
+            error,
+        };
+        if let Err(e) = ConversionLogEntry::insert(&self.pool(), &entry).await {
+            warn!(
+                "Failed to record conversion history for {}: {}",
+                image.id, e
+            );
+        }
+    }
+
+    /// The image's original on disk, downloaded first when it is missing and
+    /// `with_fetch_missing` is set. The returned row records the downloaded file.
+    async fn local_original(&self, image: &SyncedImage) -> Result<Option<(SyncedImage, PathBuf)>> {
+        if let Some(path) = &image.original_path {
+            let path = PathBuf::from(path);
+            if path.exists() {
+                return Ok(Some((image.clone(), path)));
+            }
+            if self.client.is_none() {
+                warn!("Original file not found: {:?}", path);
+            }
+        }
+        let Some(client) = &self.client else {
+            return Ok(None);
+        };
+
+        let dest = match &image.original_path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                storage::original_dest(&self.config, &image.album_id, &image.id, &image.filename)
+            }
+        };
+        storage::ensure_free_space(&self.config, image.file_size.unwrap_or(0).max(0) as u64)?;
+        info!("Fetching missing original: {}", image.filename);
+        let size = client
+            .download_asset(&image.id, &dest)
+            .await
+            .context("Failed to fetch missing original")?;
+        metrics::incr("downloads.completed");
+        metrics::count("downloads.bytes", size);
+
+        let original_path = dest.to_string_lossy().into_owned();
+        SyncedImage::set_original(&self.pool(), &image.id, &original_path, size as i64).await?;
+        let image = SyncedImage {
+            original_path: Some(original_path),
+            file_size: Some(size as i64),
+            ..image.clone()
+        };
+        Ok(Some((image, dest)))
+    }
+
+    async fn record_output(
+        &self,
+        image: &SyncedImage,
+        config: &ImageConfig,
+        output: &ConversionOutput,
+    ) -> Result<()> {
+        let pool = &self.pool();
+        if config.write_sidecar {
+            let sidecar_path = storage::sidecar_path(&self.config, &output.avif_path, &image.id);
+            Self::write_sidecar(image, config, output, &sidecar_path).await?;
+        }
+        SyncedImage::mark_converted(
+            pool,
+            &image.id,
+            output.avif_path.to_str().unwrap_or(""),
+            output.thumbnail_path.to_str().unwrap_or(""),
+        )
+        .await?;
+        SyncedImage::set_placeholder(pool, &image.id, output.placeholder.as_deref()).await?;
+        SyncedImage::set_dominant_color(pool, &image.id, output.dominant_color.as_deref()).await?;
+        SyncedImage::set_lqip(pool, &image.id, output.lqip.as_deref()).await?;
+        let variants = output
+            .variants
+            .iter()
+            .map(|(name, path)| (name.clone(), path.to_string_lossy().into_owned()))
+            .collect();
+        SyncedImage::set_variants(pool, &image.id, &variants).await?;
+        Self::record_taken_at(pool, image).await?;
+        Self::record_avif_size(pool, &image.id, &output.avif_path).await
+    }
+
+    /// Hardlink the output of an already converted image with the same checksum,
+    /// when its album's settings match this one's, instead of encoding it again
+    async fn reuse_duplicate(
+        &self,
+        image: &SyncedImage,
+        config: &ImageConfig,
+        avif_path: &Path,
+        thumbnail_path: &Path,
+    ) -> Result<Option<ConversionOutput>> {
+        let Some(checksum) = &image.checksum else {
+            return Ok(None);
+        };
+
+        for duplicate in SyncedImage::get_by_checksum(&self.pool(), checksum, &image.id).await? {
+            let (Some(avif), Some(thumbnail)) = (&duplicate.avif_path, &duplicate.thumbnail_path)
+            else {
+                continue;
+            };
+            let (avif, thumbnail) = (Path::new(avif), Path::new(thumbnail));
+            // A forced run only reuses output it produced itself, not stale files
+            // from before the settings change
+            let fresh = match (duplicate.converted_at, self.force_since) {
+                (Some(converted_at), Some(since)) => converted_at >= since,
+                (converted_at, None) => converted_at.is_some(),
+                (None, _) => false,
+            };
+            if !fresh
+                || !avif.exists()
+                || !thumbnail.exists()
+                || self.config.image.for_album(&duplicate.album_id) != *config
+            {
+                continue;
+            }
+
+            info!(
+                "Reusing conversion of duplicate {} for {}",
+                duplicate.id, image.filename
+            );
+            // Blobs are shared as they are
+            let variant_paths = duplicate.variant_paths();
+            if variant_paths.values().any(|path| !Path::new(path).exists()) {
+                continue;
+            }
+            let (avif_path, thumbnail_path, variants) = if self.config.storage.content_addressed {
+                let variants = variant_paths
+                    .into_iter()
+                    .map(|(name, path)| (name, PathBuf::from(path)))
+                    .collect();
+                (avif.to_path_buf(), thumbnail.to_path_buf(), variants)
+            } else {
+                if let Some(parent) = avif_path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                let variants = variant_paths
+                    .into_iter()
+                    .map(|(name, path)| {
+                        let dest = Self::variant_path_for(avif_path, &name);
+                        Ok((name, Self::link_original(Path::new(&path), &dest)?))
+                    })
+                    .collect::<Result<_>>()?;
+                (
+                    Self::link_original(avif, avif_path)?,
+                    Self::link_original(thumbnail, thumbnail_path)?,
+                    variants,
+                )
+            };
+            return Ok(Some(ConversionOutput {
+                avif_path,
+                thumbnail_path,
+                quality: None,
+                thumbnail_quality: None,
+                placeholder: duplicate.placeholder,
+                dominant_color: duplicate.dominant_color,
+                lqip: duplicate.lqip,
+                variants,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    async fn record_avif_size(pool: &SqlitePool, id: &str, avif_path: &Path) -> Result<()> {
+        let size = tokio::fs::metadata(avif_path)
+            .await
+            .ok()
+            .map(|m| m.len() as i64);
+        SyncedImage::set_avif_size(pool, id, size).await
+    }
+
+    /// Claim the output paths of a batch in ID order before it is converted in
+    /// parallel, so that of two new images resolving to the same file the lower ID
+    /// gets the plain name however the conversions interleave. Images whose
+    /// original is not on disk yet claim theirs when converted.
+    async fn claim_output_paths(&self, images: &[SyncedImage]) -> Result<()> {
+        if self.config.storage.content_addressed
+            || self.config.storage.path_template.contains_token("id")
+        {
+            return Ok(());
+        }
+        let mut by_id: Vec<&SyncedImage> = images.iter().collect();
+        by_id.sort_by(|a, b| a.id.cmp(&b.id));
+        for image in by_id {
+            let Some(original) = image.original_path.as_deref().map(Path::new) else {
+                continue;
+            };
+            if original.exists() {
+                self.output_path(image, original).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve the storage path template for an image.
+    ///
+    /// Templates without `{id}` can map several images to one file. The path then
+    /// belongs to the image that claimed it first, the lowest ID within a batch
+    /// (see `claim_output_paths`), and any other image gets a counter appended, so
+    /// an image's name never changes once assigned.
+    async fn output_path(&self, image: &SyncedImage, original: &Path) -> Result<PathBuf> {
+        let template = &self.config.storage.path_template;
+
+        let album_name = if template.contains_token("album_name") {
+            Album::get_by_id(&self.pool(), &image.album_id)
+                .await?
+                .map(|album| album.name)
+        } else {
+            None
+        };
+        let year = if template.contains_token("yyyy") {
+            let original = original.to_path_buf();
+            tokio::task::spawn_blocking(move || {
+                let source = PlainFile::open(&original).ok()?;
+                metadata::capture_year(&source)
+            })
+            .await
+            .unwrap_or_default()
+        } else {
+            None
+        };
+        let stem = Path::new(&image.filename)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(&image.filename);
+
+        let path = self.config.avif_path().join(template.render(&PathVars {
+            album_name: album_name.as_deref().unwrap_or(&image.album_id),
+            album_separator: self.config.storage.split_album_name_on.as_deref(),
+            album_id: &image.album_id,
+            filename: stem,
+            id: &image.id,
+            year,
+        }));
+        let config = self.config.image.for_album(&image.album_id);
+        if template.contains_token("id") || !self.path_taken(&path, &image.id, &config).await? {
+            return Ok(path);
+        }
+
+        // Counted up from 2; an image keeps the name it claimed on earlier runs,
+        // since a path it already owns is never taken
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let mut counter = 2;
+        loop {
+            let unique = path.with_file_name(format!("{}-{}.avif", stem, counter));
+            if !self.path_taken(&unique, &image.id, &config).await? {
+                debug!(
+                    "{:?} is taken by another image, using {:?} for {}",
+                    path, unique, image.id
+                );
+                return Ok(unique);
+            }
+            counter += 1;
+        }
+    }
+
+    /// Whether another image owns `path`, or the thumbnail or a variant that would be
+    /// written next to it, claiming them all for `id` otherwise. `photo_low.jpg`
+    /// must not become the `low` variant of `photo.jpg`, or the other way around.
+    async fn path_taken(&self, path: &Path, id: &str, config: &ImageConfig) -> Result<bool> {
+        let paths: Vec<PathBuf> = [path.to_path_buf(), Self::thumbnail_path_for(path)]
+            .into_iter()
+            .chain(
+                config
+                    .variants
+                    .iter()
+                    .map(|variant| Self::variant_path_for(path, &variant.name)),
+            )
+            .collect();
+
+        let pool = self.pool();
+        let mut owner = None;
+        for path in &paths {
+            owner = match SyncedImage::avif_path_owner(&pool, path, id).await? {
+                Some(owner) => Some(owner),
+                None => SyncedImage::output_owner(&pool, path, id).await?,
+            };
+            if owner.is_some() {
+                break;
+            }
+        }
+
+        let mut claimed = self.claimed_paths.lock().unwrap();
+        let owner = owner.or_else(|| {
+            paths
+                .iter()
+                .find_map(|path| claimed.get(path).filter(|o| *o != id).cloned())
+        });
+        if owner.is_none() {
+            for path in paths {
+                claimed.insert(path, id.to_string());
+            }
+        }
+        Ok(owner.is_some())
+    }
+
+    /// Move the AVIF, thumbnail and variants into the content-addressed store
+    fn store_blobs(output: ConversionOutput, blobs_path: &Path) -> Result<ConversionOutput> {
+        Ok(ConversionOutput {
+            avif_path: Self::store_blob(&output.avif_path, blobs_path)?,
+            thumbnail_path: Self::store_blob(&output.thumbnail_path, blobs_path)?,
+            variants: output
+                .variants
+                .iter()
+                .map(|(name, path)| Ok((name.clone(), Self::store_blob(path, blobs_path)?)))
+                .collect::<Result<_>>()?,
+            ..output
+        })
+    }
+
+    /// Move a finished file to `{ab}/{sha256}.{ext}` under `blobs_path`, or drop it
+    /// when that blob is already stored, and return the blob's path
+    fn store_blob(path: &Path, blobs_path: &Path) -> Result<PathBuf> {
+        let bytes = std::fs::read(path).context("Failed to read output for hashing")?;
+        let hash = hex::encode(Sha256::digest(&bytes));
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("avif");
+        let blob = blobs_path
+            .join(&hash[..2])
+            .join(format!("{}.{}", hash, extension));
+
+        // A stored blob of another length is damaged and gets replaced
+        if std::fs::metadata(&blob).is_ok_and(|m| m.len() == bytes.len() as u64) {
+            std::fs::remove_file(path)?;
+            // Restarts the garbage collector's grace period, as the blob may have
+            // been unreferenced until this conversion is recorded
+            std::fs::File::options()
+                .write(true)
+                .open(&blob)
+                .and_then(|file| file.set_modified(std::time::SystemTime::now()))
+                .context("Failed to touch reused blob")?;
+        } else {
+            if let Some(parent) = blob.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(path, &blob).context("Failed to move output into the blob store")?;
+        }
+        Ok(blob)
+    }
+
+    /// `{stem}_thumb.avif` next to the full-size output
+    fn thumbnail_path_for(avif_path: &Path) -> PathBuf {
+        let stem = avif_path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        avif_path.with_file_name(format!("{}_thumb.avif", stem))
+    }
+
+    /// `{stem}_{name}.avif` next to the full-size output
+    fn variant_path_for(avif_path: &Path, name: &str) -> PathBuf {
+        let stem = avif_path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        avif_path.with_file_name(format!("{}_{}.avif", stem, name))
+    }
+
+    async fn write_sidecar(
+        image: &SyncedImage,
+        config: &ImageConfig,
+        output: &ConversionOutput,
+        sidecar_path: &Path,
+    ) -> Result<()> {
+        let sidecar = Sidecar {
+            id: &image.id,
+            source_filename: &image.filename,
+            source_checksum: image.checksum.as_deref(),
+            quality: output.quality,
+            thumbnail_quality: output.thumbnail_quality,
+            max_width: config.max_width,
+            thumbnail_width: config.thumbnail_width,
+            thumbnail_square: config.thumbnail_square,
+            speed: ENCODER_SPEED,
+            encoder: ENCODER_VERSION,
+            generated_at: Utc::now().to_rfc3339(),
+        };
+
+        tokio::fs::write(sidecar_path, serde_json::to_vec_pretty(&sidecar)?)
+            .await
+            .context("Failed to write sidecar")?;
+        debug!("Wrote sidecar {:?}", sidecar_path);
+
+        Ok(())
+    }
+
+    /// Convert `source` into the AVIF and its thumbnail, making the thumbnail from
+    /// `thumbnail_source` (Immich's own) instead when it is large enough
     fn do_conversion(
-        source: &Path,
+        source: &PlainFile,
+        thumbnail_source: Option<&Path>,
         dest: &Path,
         thumbnail_dest: &Path,
         config: &ImageConfig,
-    ) -> Result<()> {
-        let img = image::open(source).context("Failed to open source image")?;
-        let img = Self::apply_exif_orientation(source, img);
-
+    ) -> Result<ConversionOutput> {
+        formats::check_pixels(source, config.max_pixels)?;
         if let Some(parent) = dest.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        // Resize main image if needed
-        let resized_img = Self::resize_image(&img, config.max_width);
+        let (main_passthrough, thumbnail_passthrough) = Self::passthrough(source, config);
 
-        // Generate and save main AVIF
-        Self::encode_and_save(
-            &resized_img,
-            dest,
-            config.quality,
-            config.max_file_size,
-            config.min_quality,
-        )?;
-        debug!("Converted {:?} to {:?}", source, dest);
+        if main_passthrough && thumbnail_passthrough {
+            debug!("Source already optimal, linking {:?}", source.path());
+            // Small enough to pass through, so also cheap to decode for the previews
+            let preview = Self::open_source(source)
+                .ok()
+                .map(|img| placeholder::preview(&img));
+            return Ok(ConversionOutput {
+                avif_path: Self::link_source(source, dest)?,
+                thumbnail_path: Self::link_source(source, thumbnail_dest)?,
+                quality: None,
+                thumbnail_quality: None,
+                placeholder: preview
+                    .as_ref()
+                    .and_then(|img| Self::compute_placeholder(img, config)),
+                dominant_color: preview.as_ref().and_then(placeholder::dominant_color),
+                lqip: preview
+                    .as_ref()
+                    .and_then(|img| Self::compute_lqip(img, config)),
+                variants: BTreeMap::new(),
+            });
+        }
+
+        let img = Self::open_source(source)?;
+        let exif = if config.preserve_metadata {
+            metadata::read_preserved_exif(source)
+        } else {
+            None
+        };
+
+        let mut animated = false;
+        let (avif_path, quality) = if main_passthrough {
+            debug!("Source already optimal, linking {:?}", source.path());
+            (Self::link_source(source, dest)?, None)
+        } else {
+            let quality = match Self::open_animation(source, config)? {
+                Some(animation) => {
+                    let (avif_file, quality) = Self::encode_animation(&animation, config)?;
+                    Self::write_replacing(dest, &avif_file)?;
+                    animated = true;
+                    quality
+                }
+                None => {
+                    // Resize main image if needed, then mark it
+                    let resized_img = watermark::apply(Self::resize_image(&img, config.max_width));
+
+                    // Generate and save main AVIF, at the quality for its size
+                    let config = &config.at_width(resized_img.width());
+                    Self::encode_and_save(&resized_img, dest, config, exif.as_deref())?
+                }
+            };
+            debug!("Converted {:?} to {:?}", source.path(), dest);
+            (dest.to_path_buf(), Some(quality))
+        };
 
         // Generate and save thumbnail
-        let thumbnail_img = Self::resize_image(&img, config.thumbnail_width);
-        Self::encode_and_save(
-            &thumbnail_img,
+        let thumbnail_img =
+            match thumbnail_source.and_then(|path| Self::open_immich_thumbnail(path, config)) {
+                Some(immich) => Self::thumbnail_image(&immich, config),
+                None => Self::thumbnail_image(&img, config),
+            };
+        let thumbnail_quality = Self::encode_and_save(
+            &watermark::apply_to_thumbnail(thumbnail_img),
             thumbnail_dest,
-            config.quality,
-            config.max_file_size,
-            config.min_quality,
+            &config.for_thumbnail(),
+            exif.as_deref(),
         )?;
         debug!("Created thumbnail {:?}", thumbnail_dest);
 
-        Ok(())
+        // Variants of an animation would only hold its first frame
+        let mut variants = BTreeMap::new();
+        if !animated {
+            for variant in config.variants.iter() {
+                let variant_config = &config.for_variant(variant);
+                let variant_dest = Self::variant_path_for(dest, &variant.name);
+                let variant_img =
+                    watermark::apply(Self::resize_image(&img, variant_config.max_width));
+                Self::encode_and_save(
+                    &variant_img,
+                    &variant_dest,
+                    variant_config,
+                    exif.as_deref(),
+                )?;
+                debug!("Created variant {:?}", variant_dest);
+                variants.insert(variant.name.clone(), variant_dest);
+            }
+        }
+
+        // Previews show the whole unmarked image, not the square thumbnail crop
+        let preview = placeholder::preview(&img);
+        Ok(ConversionOutput {
+            avif_path,
+            thumbnail_path: thumbnail_dest.to_path_buf(),
+            quality,
+            thumbnail_quality: Some(thumbnail_quality),
+            placeholder: Self::compute_placeholder(&preview, config),
+            dominant_color: placeholder::dominant_color(&preview),
+            lqip: Self::compute_lqip(&preview, config),
+            variants,
+        })
+    }
+
+    /// Same pipeline as `do_conversion`, but encoded in memory and never written
+    fn estimate_sizes(source: &PlainFile, config: &ImageConfig) -> Result<SizeEstimate> {
+        let source_bytes = source.size()?;
+        let (main_passthrough, thumbnail_passthrough) = Self::passthrough(source, config);
+
+        if main_passthrough && thumbnail_passthrough {
+            return Ok(SizeEstimate {
+                avif_bytes: source_bytes,
+                thumbnail_bytes: source_bytes,
+            });
+        }
+
+        formats::check_pixels(source, config.max_pixels)?;
+        let img = Self::open_source(source)?;
+        let exif = if config.preserve_metadata {
+            metadata::read_preserved_exif(source)
+        } else {
+            None
+        };
+
+        let avif_bytes = if main_passthrough {
+            source_bytes
+        } else if let Some(animation) = Self::open_animation(source, config)? {
+            Self::encode_animation(&animation, config)?.0.len() as u64
+        } else {
+            let resized_img = Self::resize_image(&img, config.max_width);
+            let config = &config.at_width(resized_img.width());
+            Self::encode(&resized_img, config, exif.as_deref())?.0.len() as u64
+        };
+        let thumbnail_img = Self::thumbnail_image(&img, config);
+        let thumbnail_bytes =
+            Self::encode(&thumbnail_img, &config.for_thumbnail(), exif.as_deref())?
+                .0
+                .len() as u64;
+
+        Ok(SizeEstimate {
+            avif_bytes,
+            thumbnail_bytes,
+        })
+    }
+
+    /// Whether the main image and thumbnail can link the original instead of being
+    /// re-encoded, because it is already small enough (`skip_if_smaller`)
+    fn passthrough(source: &PlainFile, config: &ImageConfig) -> (bool, bool) {
+        // A linked original would go out without the watermark
+        let source_width = if config.skip_if_smaller && !watermark::enabled() {
+            Self::already_optimal_width(source, config)
+        } else {
+            None
+        };
+        let thumbnail_passthrough = source_width
+            .is_some_and(|width| width <= config.thumbnail_width && !config.thumbnail_square);
+
+        (source_width.is_some(), thumbnail_passthrough)
+    }
+
+    fn thumbnail_image(img: &DynamicImage, config: &ImageConfig) -> DynamicImage {
+        if config.thumbnail_square {
+            Self::resize_image(&Self::crop_center_square(img), config.thumbnail_width)
+        } else {
+            Self::resize_image(img, config.thumbnail_width)
+        }
+    }
+
+    /// Immich's thumbnail of the image, if it decodes and is at least as wide as the
+    /// thumbnail to make (after cropping to a square, if configured)
+    fn open_immich_thumbnail(path: &Path, config: &ImageConfig) -> Option<DynamicImage> {
+        let img = Self::decode_immich_thumbnail(path)?;
+        let width = if config.thumbnail_square {
+            img.width().min(img.height())
+        } else {
+            img.width()
+        };
+        if width < config.thumbnail_width {
+            debug!(
+                "Immich thumbnail {:?} is only {}px wide, using the original",
+                path, width
+            );
+            return None;
+        }
+        Some(img)
+    }
+
+    /// Decrypt, if needed, and decode a downloaded Immich thumbnail
+    fn decode_immich_thumbnail(path: &Path) -> Option<DynamicImage> {
+        let bytes = match encryption::read_if_encrypted(path) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => std::fs::read(path).ok()?,
+            Err(e) => {
+                warn!("Failed to decrypt {:?}: {:#}", path, e);
+                return None;
+            }
+        };
+        match image::load_from_memory(&bytes) {
+            Ok(img) => Some(img),
+            Err(e) => {
+                warn!("Failed to decode Immich thumbnail {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// A failed hash is logged rather than failing the whole conversion
+    fn compute_placeholder(img: &DynamicImage, config: &ImageConfig) -> Option<String> {
+        placeholder::compute(img, config.placeholder_strategy).unwrap_or_else(|e| {
+            warn!("Failed to compute placeholder: {}", e);
+            None
+        })
+    }
+
+    /// Like `compute_placeholder`, a failure is only logged
+    fn compute_lqip(img: &DynamicImage, config: &ImageConfig) -> Option<String> {
+        if !config.lqip {
+            return None;
+        }
+        placeholder::lqip(img).unwrap_or_else(|e| {
+            warn!("Failed to compute LQIP: {}", e);
+            None
+        })
     }
 
+    /// Decode the original into an upright image, routing HEIC/HEIF through libheif
+    fn open_source(source: &PlainFile) -> Result<DynamicImage> {
+        if heif::is_heif(source) {
+            return heif::decode(source);
+        }
+
+        let img = formats::source_reader(source)
+            .map_err(image::ImageError::from)
+            .and_then(|reader| reader.decode())
+            .context("Failed to open source image")?;
+        Ok(Self::apply_exif_orientation(source, img))
+    }
+
+    /// Returns the source's displayed width when it is below both `max_file_size`
+    /// and `max_width`, meaning an AVIF re-encode would not make it any smaller.
+    fn already_optimal_width(source: &PlainFile, config: &ImageConfig) -> Option<u32> {
+        let file_size = source.size().ok()?;
+        if file_size >= config.max_file_size {
+            return None;
+        }
+
+        let (width, height) = formats::source_reader(source)
+            .ok()?
+            .into_dimensions()
+            .ok()?;
+        // Orientations 5-8 rotate by 90 degrees, swapping the displayed width
+        let width = match Self::read_exif_orientation(source) {
+            Some(5..=8) => height,
+            _ => width,
+        };
+
+        (width <= config.max_width).then_some(width)
+    }
+
+    /// `link_original` for the source being converted. An encrypted original is
+    /// written out decrypted instead, as outputs are never encrypted.
+    fn link_source(source: &PlainFile, dest: &Path) -> Result<PathBuf> {
+        if !source.is_encrypted() {
+            return Self::link_original(source.path(), dest);
+        }
+        let linked = dest.with_extension(Self::linked_extension(source.path()));
+        Self::write_replacing(&linked, &source.bytes()?)?;
+        Ok(linked)
+    }
+
+    /// Hardlink (or copy, across filesystems) the original next to the AVIF output,
+    /// keeping the original's extension so it is served with the right content type.
+    fn link_original(source: &Path, dest: &Path) -> Result<PathBuf> {
+        let linked = dest.with_extension(Self::linked_extension(source));
+
+        if linked.exists() {
+            std::fs::remove_file(&linked)?;
+        }
+        if std::fs::hard_link(source, &linked).is_err() {
+            std::fs::copy(source, &linked).context("Failed to copy original")?;
+        }
+
+        Ok(linked)
+    }
+
+    fn linked_extension(source: &Path) -> String {
+        source
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("bin")
+            .to_lowercase()
+    }
+
+    /// Scale down to `max_width`, preserving aspect ratio. Never upscales.
     fn resize_image(img: &DynamicImage, max_width: u32) -> DynamicImage {
         let (width, height) = (img.width(), img.height());
 
@@ -190,38 +1831,238 @@ impl AvifConverter {
         img.resize(max_width, new_height, FilterType::Lanczos3)
     }
 
+    fn crop_center_square(img: &DynamicImage) -> DynamicImage {
+        let (width, height) = (img.width(), img.height());
+        let side = width.min(height);
+        let x = (width - side) / 2;
+        let y = (height - side) / 2;
+
+        img.crop_imm(x, y, side, side)
+    }
+
     fn encode_and_save(
         img: &DynamicImage,
         dest: &Path,
-        quality: f32,
-        max_file_size: u64,
-        min_quality: f32,
-    ) -> Result<()> {
-        let rgba = Self::to_rgba(img);
-        let width = img.width() as usize;
-        let height = img.height() as usize;
+        config: &ImageConfig,
+        exif: Option<&[u8]>,
+    ) -> Result<f32> {
+        let (avif_file, quality) = Self::encode(img, config, exif)?;
+        Self::write_replacing(dest, &avif_file)?;
+        Ok(quality)
+    }
 
-        let pixels: Vec<RGBA8> = rgba
-            .chunks(4)
-            .map(|c| RGBA8::new(c[0], c[1], c[2], c[3]))
-            .collect();
+    /// Write `bytes` to a temporary file next to `dest` and rename it into place.
+    /// `dest` may be a hardlink shared with a duplicate image, which writing through
+    /// would change as well; a concurrent request also never sees a partial file.
+    fn write_replacing(dest: &Path, bytes: &[u8]) -> Result<()> {
+        let temp = dest.with_extension(format!(
+            "{}.tmp",
+            TEMP_FILE_SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temp, bytes)?;
+        if let Err(e) = std::fs::rename(&temp, dest) {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e.into());
+        }
+        Ok(())
+    }
 
-        let img_ref = Img::new(&pixels[..], width, height);
+    /// Multi-frame GIF/APNG source, when `convert_animations` is on. Thumbnails
+    /// and placeholders still use the first frame only.
+    fn open_animation(source: &PlainFile, config: &ImageConfig) -> Result<Option<Animation>> {
+        if !config.convert_animations {
+            return Ok(None);
+        }
+        Animation::open(
+            source,
+            config.max_width,
+            config.force_alpha,
+            config.max_pixels,
+        )
+    }
 
-        let mut current_quality = quality;
-        let quality_step = 5.0;
+    /// Encode an animated AVIF sequence, with the same size limit as still images.
+    /// Metadata is not carried over.
+    fn encode_animation(animation: &Animation, config: &ImageConfig) -> Result<(Vec<u8>, f32)> {
+        Self::downscale_to_fit(config, |factor, config| {
+            let animation = animation.scaled(factor);
+            Self::fit_to_size(config, |quality| {
+                animation
+                    .encode(quality, ENCODER_SPEED, config.lossless)
+                    .context("Failed to encode animated AVIF")
+            })
+        })
+    }
+
+    /// Encode in memory, lowering quality until the output fits `max_file_size`.
+    /// Returns the AVIF bytes and the quality that produced them.
+    fn encode(
+        img: &DynamicImage,
+        config: &ImageConfig,
+        exif: Option<&[u8]>,
+    ) -> Result<(Vec<u8>, f32)> {
+        Self::downscale_to_fit(config, |factor, config| {
+            if factor == 1.0 {
+                return Self::encode_at_size(img, config, exif);
+            }
+            let width = ((img.width() as f32 * factor) as u32).max(1);
+            let height = ((img.height() as f32 * factor) as u32).max(1);
+            let smaller = img.resize_exact(width, height, FilterType::Lanczos3);
+            Self::encode_at_size(&smaller, config, exif)
+        })
+    }
 
+    /// Run `encode` at full size and, with `on_size_exceeded = "downscale"`, at
+    /// 90%, 81%, ... of it while the output stays over the limit. `encode` is
+    /// given the scale factor and the config to encode with, and always resizes
+    /// from the unshrunk image. Only the full size walks down the quality range:
+    /// it ended at `min_quality`, so the smaller sizes are encoded just at that.
+    fn downscale_to_fit(
+        config: &ImageConfig,
+        mut encode: impl FnMut(f32, &ImageConfig) -> Result<(Vec<u8>, f32)>,
+    ) -> Result<(Vec<u8>, f32)> {
+        let at_min_quality = ImageConfig {
+            quality: config.min_quality,
+            auto_quality: false,
+            ..config.clone()
+        };
+        let mut step = 0;
         loop {
+            let factor = 0.9f32.powi(step);
+            let step_config = if step == 0 { config } else { &at_min_quality };
+            match encode(factor, step_config) {
+                Err(e)
+                    if config.on_size_exceeded == SizeExceededPolicy::Downscale
+                        && step < MAX_DOWNSCALE_STEPS
+                        && e.is::<SizeExceeded>() =>
+                {
+                    debug!(
+                        "{}, retrying at {:.0}% size",
+                        e,
+                        0.9f32.powi(step + 1) * 100.0
+                    );
+                    step += 1;
+                }
+                Ok(result) if step > 0 => {
+                    info!(
+                        "Downscaled to {:.0}% to meet the {} byte limit (final size: {} bytes)",
+                        factor * 100.0,
+                        config.max_file_size,
+                        result.0.len()
+                    );
+                    return Ok(result);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn encode_at_size(
+        img: &DynamicImage,
+        config: &ImageConfig,
+        exif: Option<&[u8]>,
+    ) -> Result<(Vec<u8>, f32)> {
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+
+        let tuned;
+        let config = if config.auto_quality && !config.lossless {
+            let quality = auto_quality::pick_quality(img, config, ENCODER_SPEED)?;
+            if quality < config.quality {
+                debug!(
+                    "Auto quality {} meets SSIM {} (configured {})",
+                    quality, config.target_ssim, config.quality
+                );
+            }
+            tuned = ImageConfig {
+                quality,
+                ..config.clone()
+            };
+            &tuned
+        } else {
+            config
+        };
+
+        // Opaque images are encoded as RGB, skipping the RGBA copy and alpha cleanup
+        let input = if config.force_alpha || Self::has_transparency(img) {
+            EncoderInput::Rgba(Self::to_rgba(img))
+        } else {
+            EncoderInput::Rgb(Self::to_rgb(img))
+        };
+
+        Self::fit_to_size(config, |current_quality| {
             let encoder = Encoder::new()
                 .with_quality(current_quality)
-                .with_speed(4)
+                .with_speed(ENCODER_SPEED)
                 .with_alpha_quality(current_quality);
+            // Quality 100 is quantizer 0, which rav1e codes losslessly; 8-bit RGB
+            // planes avoid the rounding of a YCbCr conversion
+            let encoder = if config.lossless {
+                encoder
+                    .with_internal_color_model(ColorModel::RGB)
+                    .with_bit_depth(BitDepth::Eight)
+            } else {
+                encoder
+            };
 
-            let result = encoder
-                .encode_rgba(img_ref.clone())
-                .context("Failed to encode AVIF")?;
+            let result = match &input {
+                EncoderInput::Rgb(pixels) => {
+                    encoder.encode_rgb(Img::new(&pixels[..], width, height))
+                }
+                EncoderInput::Rgba(pixels) if config.force_alpha => {
+                    Self::encode_with_alpha_plane(&encoder, pixels, width, height, config.lossless)
+                }
+                EncoderInput::Rgba(pixels) => {
+                    encoder.encode_rgba(Img::new(&pixels[..], width, height))
+                }
+            }
+            .context("Failed to encode AVIF")?;
+
+            // ravif's defaults: BT.601 YCbCr at 10 bits, or RGB at the 8 bits set above
+            let (matrix_coefficients, depth_bits) = if config.lossless {
+                (avif_serialize::constants::MatrixCoefficients::Rgb, 8)
+            } else {
+                (avif_serialize::constants::MatrixCoefficients::Bt601, 10)
+            };
+            Ok(match exif {
+                Some(exif) => metadata::embed_exif(
+                    &result,
+                    width as u32,
+                    height as u32,
+                    exif,
+                    matrix_coefficients,
+                    depth_bits,
+                )
+                .unwrap_or_else(|e| {
+                    warn!("Dropping metadata: {}", e);
+                    result.avif_file
+                }),
+                None => result.avif_file,
+            })
+        })
+    }
+
+    /// Run `encode` at the configured quality, then in steps down to
+    /// `min_quality` while the output is over `max_file_size`. Lossless output is
+    /// encoded once at quality 100, whatever its size. Output still over the limit
+    /// is kept or fails with `SizeExceeded`, depending on `on_size_exceeded`.
+    fn fit_to_size(
+        config: &ImageConfig,
+        mut encode: impl FnMut(f32) -> Result<Vec<u8>>,
+    ) -> Result<(Vec<u8>, f32)> {
+        if config.lossless {
+            return Ok((encode(100.0)?, 100.0));
+        }
+
+        let quality = config.quality;
+        let max_file_size = config.max_file_size;
+        let min_quality = config.min_quality;
+        let mut current_quality = quality;
+        let quality_step = 5.0;
 
-            let file_size = result.avif_file.len() as u64;
+        loop {
+            let avif_file = encode(current_quality)?;
+            let file_size = avif_file.len() as u64;
 
             if file_size <= max_file_size {
                 if current_quality < quality {
@@ -233,17 +2074,23 @@ impl AvifConverter {
                         file_size
                     );
                 }
-                std::fs::write(dest, result.avif_file)?;
-                return Ok(());
+                return Ok((avif_file, current_quality));
             }
 
             if current_quality <= min_quality {
+                if config.on_size_exceeded != SizeExceededPolicy::SaveAnyway {
+                    return Err(SizeExceeded {
+                        size: file_size,
+                        limit: max_file_size,
+                        quality: min_quality,
+                    }
+                    .into());
+                }
                 warn!(
                     "File size {} bytes exceeds limit of {} bytes even at minimum quality {}. Saving anyway.",
                     file_size, max_file_size, min_quality
                 );
-                std::fs::write(dest, result.avif_file)?;
-                return Ok(());
+                return Ok((avif_file, current_quality));
             }
 
             debug!(
@@ -256,21 +2103,90 @@ impl AvifConverter {
         }
     }
 
-    fn to_rgba(img: &DynamicImage) -> Vec<u8> {
-        img.to_rgba8().into_raw()
+    fn to_rgba(img: &DynamicImage) -> Vec<RGBA8> {
+        img.to_rgba8()
+            .pixels()
+            .map(|p| RGBA8::new(p[0], p[1], p[2], p[3]))
+            .collect()
     }
 
-    fn apply_exif_orientation(path: &Path, img: DynamicImage) -> DynamicImage {
-        let orientation = (|| -> Option<u32> {
-            let file = std::fs::File::open(path).ok()?;
-            let mut bufreader = std::io::BufReader::new(file);
-            let exif_reader = exif::Reader::new();
-            let exif = exif_reader.read_from_container(&mut bufreader).ok()?;
-            let field = exif.get_field(Tag::Orientation, In::PRIMARY)?;
-            field.value.get_uint(0)
-        })();
+    fn to_rgb(img: &DynamicImage) -> Vec<RGB8> {
+        img.to_rgb8()
+            .pixels()
+            .map(|p| RGB8::new(p[0], p[1], p[2]))
+            .collect()
+    }
+
+    /// Whether any pixel is not fully opaque. Formats without an alpha channel
+    /// (JPEG, most HEIC) are answered without scanning.
+    fn has_transparency(img: &DynamicImage) -> bool {
+        if !img.color().has_alpha() {
+            return false;
+        }
+        match img {
+            DynamicImage::ImageRgba8(buffer) => buffer.pixels().any(|p| p[3] != u8::MAX),
+            DynamicImage::ImageLumaA8(buffer) => buffer.pixels().any(|p| p[1] != u8::MAX),
+            other => other.to_rgba8().pixels().any(|p| p[3] != u8::MAX),
+        }
+    }
+
+    /// `encode_rgba` leaves out the alpha plane of opaque images; this always
+    /// writes one, converting to ravif's default 10-bit BT.601 YCbCr by hand, or
+    /// to 8-bit GBR for lossless output.
+    fn encode_with_alpha_plane(
+        encoder: &Encoder,
+        pixels: &[RGBA8],
+        width: usize,
+        height: usize,
+        lossless: bool,
+    ) -> Result<EncodedImage, ravif::Error> {
+        if lossless {
+            return encoder.encode_raw_planes_8_bit(
+                width,
+                height,
+                pixels.iter().map(|px| [px.g, px.b, px.r]),
+                Some(pixels.iter().map(|px| px.a)),
+                PixelRange::Full,
+                MatrixCoefficients::Identity,
+            );
+        }
+
+        const BT601: [f32; 3] = [0.2990, 0.5870, 0.1140];
+        const MAX: f32 = 1023.0;
+        let scale = MAX / 255.0;
+        let shift = (MAX * 0.5).round();
+
+        let planes = pixels.iter().map(|px| {
+            let (r, g, b) = (f32::from(px.r), f32::from(px.g), f32::from(px.b));
+            let y = scale * (BT601[0] * r + BT601[1] * g + BT601[2] * b);
+            let cb = (b * scale - y).mul_add(0.5 / (1.0 - BT601[2]), shift);
+            let cr = (r * scale - y).mul_add(0.5 / (1.0 - BT601[0]), shift);
+            [y.round() as u16, cb.round() as u16, cr.round() as u16]
+        });
+        let alpha = pixels
+            .iter()
+            .map(|px| (u16::from(px.a) << 2) | (u16::from(px.a) >> 6));
+
+        encoder.encode_raw_planes_10_bit(
+            width,
+            height,
+            planes,
+            Some(alpha),
+            PixelRange::Full,
+            MatrixCoefficients::BT601,
+        )
+    }
+
+    fn read_exif_orientation(source: &PlainFile) -> Option<u32> {
+        let mut reader = source.reader().ok()?;
+        let exif_reader = exif::Reader::new();
+        let exif = exif_reader.read_from_container(&mut reader).ok()?;
+        let field = exif.get_field(Tag::Orientation, In::PRIMARY)?;
+        field.value.get_uint(0)
+    }
 
-        match orientation {
+    fn apply_exif_orientation(source: &PlainFile, img: DynamicImage) -> DynamicImage {
+        match Self::read_exif_orientation(source) {
             Some(2) => img.fliph(),
             Some(3) => img.rotate180(),
             Some(4) => img.flipv(),
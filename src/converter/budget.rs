@@ -0,0 +1,69 @@
+use super::formats;
+use crate::encryption::PlainFile;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Rough peak bytes per source pixel while converting: the decoded image, its
+/// resized copy, the RGBA buffer handed to the encoder and the encoder's planes
+const BYTES_PER_PIXEL: u64 = 16;
+
+/// Caps the estimated memory of conversions in flight, on top of the task count
+/// limit. One semaphore permit stands for one MiB.
+pub struct MemoryBudget {
+    semaphore: Option<Arc<Semaphore>>,
+    total_mib: u32,
+}
+
+impl MemoryBudget {
+    /// A budget of `limit_mib`; 0 means no limit
+    pub fn new(limit_mib: u64) -> Self {
+        let total_mib = limit_mib
+            .min(Semaphore::MAX_PERMITS as u64)
+            .min(u32::MAX as u64) as u32;
+        Self {
+            semaphore: (total_mib > 0).then(|| Arc::new(Semaphore::new(total_mib as usize))),
+            total_mib,
+        }
+    }
+
+    /// Wait until the source's estimated memory fits in the budget and hold it until
+    /// the permit is dropped. Sources whose dimensions cannot be read from the file
+    /// header are left to the task count limit alone.
+    pub async fn reserve(&self, source: Option<PathBuf>) -> Option<OwnedSemaphorePermit> {
+        // Without a limit the header is not even read
+        self.semaphore.as_ref()?;
+        let source = source?;
+        let path = source.clone();
+        // Encrypted originals are decrypted for this, as their header is ciphertext
+        let (width, height) = tokio::task::spawn_blocking(move || {
+            let source = PlainFile::open(&path).ok()?;
+            formats::source_reader(&source)
+                .map_err(image::ImageError::from)
+                .and_then(|reader| reader.into_dimensions())
+                .ok()
+        })
+        .await
+        .ok()??;
+
+        let what = format!("{:?} ({}x{})", source, width, height);
+        self.reserve_pixels(width as u64 * height as u64, &what)
+            .await
+    }
+
+    /// Like `reserve`, for work on `pixels` pixels that do not come from one source
+    /// file, such as a mosaic canvas. `what` names it in the log.
+    pub async fn reserve_pixels(&self, pixels: u64, what: &str) -> Option<OwnedSemaphorePermit> {
+        let semaphore = self.semaphore.as_ref()?;
+        let bytes = pixels.saturating_mul(BYTES_PER_PIXEL);
+        // A single image larger than the whole budget still runs, just alone
+        let mib = bytes.div_ceil(MIB).clamp(1, self.total_mib as u64) as u32;
+        if semaphore.available_permits() < mib as usize {
+            debug!("Waiting for {} MiB of conversion memory for {}", mib, what);
+        }
+        semaphore.clone().acquire_many_owned(mib).await.ok()
+    }
+}
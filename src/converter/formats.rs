@@ -0,0 +1,188 @@
+use super::heif;
+use crate::encryption::{PlainFile, PlainReader};
+use image::io::Reader;
+use image::ImageFormat;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use tracing::warn;
+
+/// Leading bytes read to recognize a format; every signature `image` knows is shorter
+const SNIFF_LEN: u64 = 32;
+
+/// HEIF MIME types, decodable only with the `heif` feature
+const HEIF_MIME_TYPES: &[&str] = &[
+    "image/heic",
+    "image/heif",
+    "image/heic-sequence",
+    "image/heif-sequence",
+];
+
+/// How an original's type is named in logs and the database: its MIME type when
+/// known, else its extension
+pub fn type_label(filename: &str, mime_type: Option<&str>) -> String {
+    match mime_type {
+        Some(mime) => mime.to_lowercase(),
+        None => Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| format!(".{}", e.to_lowercase()))
+            .unwrap_or_else(|| "unknown type".to_string()),
+    }
+}
+
+/// Why an original will not be converted, or `None` when it can be
+pub fn unsupported_reason(
+    filename: &str,
+    mime_type: Option<&str>,
+    skip_mime_types: &[String],
+) -> Option<&'static str> {
+    if let Some(mime) = mime_type {
+        if skip_mime_types.iter().any(|s| s.eq_ignore_ascii_case(mime)) {
+            return Some("excluded by skip_mime_types");
+        }
+    }
+    (!is_decodable(filename, mime_type)).then_some("no decoder in this build")
+}
+
+/// Whether this build can decode an original, judged by its MIME type when that is
+/// a known one, else by its extension. Files with neither are given a try.
+fn is_decodable(filename: &str, mime_type: Option<&str>) -> bool {
+    if let Some(mime) = mime_type.map(str::to_lowercase) {
+        if HEIF_MIME_TYPES.contains(&mime.as_str()) {
+            return cfg!(feature = "heif");
+        }
+        if let Some(format) = ImageFormat::from_mime_type(&mime) {
+            return readable(format);
+        }
+    }
+
+    let path = Path::new(filename);
+    if heif::has_heif_extension(path) {
+        return cfg!(feature = "heif");
+    }
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ImageFormat::from_extension(ext).is_some_and(readable),
+        None => true,
+    }
+}
+
+/// Open an image for decoding as the format its leading bytes show, falling back to
+/// the extension only when the content is not recognized
+pub fn reader(path: &Path) -> std::io::Result<Reader<BufReader<File>>> {
+    Reader::open(path)?.with_guessed_format()
+}
+
+/// `reader` for an original, which may be decrypted in memory
+pub fn source_reader(source: &PlainFile) -> std::io::Result<Reader<PlainReader>> {
+    let mut reader = Reader::new(source.reader()?);
+    if let Ok(format) = ImageFormat::from_path(source.path()) {
+        reader.set_format(format);
+    }
+    reader.with_guessed_format()
+}
+
+/// A source whose header declares more pixels than `image.max_pixels`
+#[derive(Debug)]
+pub struct TooManyPixels {
+    width: u32,
+    height: u32,
+    limit: u64,
+}
+
+impl std::fmt::Display for TooManyPixels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Image declares {}x{} pixels ({:.1} MP), over the {:.1} MP limit (max_pixels); not decoded",
+            self.width,
+            self.height,
+            self.width as f64 * self.height as f64 / 1e6,
+            self.limit as f64 / 1e6
+        )
+    }
+}
+
+impl std::error::Error for TooManyPixels {}
+
+/// Reject a source whose header declares more than `max_pixels` pixels (0 for no
+/// limit) before any of it is decoded. Headers that cannot be read, such as HEIF
+/// ones, pass; libheif enforces its own size limits.
+pub fn check_pixels(source: &PlainFile, max_pixels: u64) -> Result<(), TooManyPixels> {
+    if max_pixels == 0 {
+        return Ok(());
+    }
+    let Ok((width, height)) = source_reader(source)
+        .map_err(image::ImageError::from)
+        .and_then(|reader| reader.into_dimensions())
+    else {
+        return Ok(());
+    };
+    if width as u64 * height as u64 > max_pixels {
+        return Err(TooManyPixels {
+            width,
+            height,
+            limit: max_pixels,
+        });
+    }
+    Ok(())
+}
+
+/// Format of the file judged by its content alone
+pub fn sniff(source: &PlainFile) -> Option<ImageFormat> {
+    let mut header = Vec::new();
+    source
+        .reader()
+        .ok()?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut header)
+        .ok()?;
+    image::guess_format(&header).ok()
+}
+
+/// Warn when an original's content does not match the format its MIME type, or
+/// failing that its filename, declares. It is decoded by its content either way.
+pub fn check_declared(source: &PlainFile, filename: &str, mime_type: Option<&str>) {
+    let Some(actual) = sniff(source) else {
+        return;
+    };
+    let declared = mime_type
+        .and_then(ImageFormat::from_mime_type)
+        .or_else(|| ImageFormat::from_path(filename).ok());
+    if let Some(declared) = declared.filter(|&declared| declared != actual) {
+        warn!(
+            "{} is labeled {:?} ({}) but contains {:?} data, decoding it as {:?}",
+            filename,
+            declared,
+            type_label(filename, mime_type),
+            actual,
+            actual
+        );
+    }
+}
+
+fn readable(format: ImageFormat) -> bool {
+    format.can_read() && format.reading_enabled()
+}
+
+/// Unsupported originals counted per type and reason, so each is logged once per run
+/// rather than once per file
+#[derive(Default)]
+pub struct UnsupportedTally(BTreeMap<(String, &'static str), usize>);
+
+impl UnsupportedTally {
+    pub fn add(&mut self, label: String, reason: &'static str) {
+        *self.0.entry((label, reason)).or_default() += 1;
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.values().sum()
+    }
+
+    pub fn log(&self, action: &str) {
+        for ((label, reason), count) in &self.0 {
+            warn!("{} {} {} images: {}", action, count, label, reason);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::encryption::PlainFile;
+use anyhow::Result;
+use image::DynamicImage;
+use std::io::Read;
+use std::path::Path;
+
+/// HEIF major brands written by iPhones and other HEVC-based cameras
+const HEIF_BRANDS: &[&[u8; 4]] = &[b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx"];
+
+/// Whether the file is a HEIC/HEIF container, judged by extension or `ftyp` brand
+pub fn is_heif(source: &PlainFile) -> bool {
+    has_heif_extension(source.path()) || has_heif_brand(source)
+}
+
+pub fn has_heif_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| matches!(e.to_lowercase().as_str(), "heic" | "heif" | "hif"))
+        .unwrap_or(false)
+}
+
+fn has_heif_brand(source: &PlainFile) -> bool {
+    let mut header = [0u8; 12];
+    let read = source.reader().and_then(|mut r| r.read_exact(&mut header));
+    if read.is_err() || &header[4..8] != b"ftyp" {
+        return false;
+    }
+
+    HEIF_BRANDS.iter().any(|brand| &header[8..12] == *brand)
+}
+
+/// Decode the primary image of a HEIF file. libheif applies the container's
+/// rotation/mirror transforms, so the result is already upright.
+#[cfg(feature = "heif")]
+pub fn decode(source: &PlainFile) -> Result<DynamicImage> {
+    use anyhow::{anyhow, Context};
+    use image::RgbaImage;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let bytes = source.bytes()?;
+    let lib_heif = LibHeif::new();
+    let ctx = HeifContext::read_from_bytes(&bytes).context("Failed to read HEIF container")?;
+    let handle = ctx
+        .primary_image_handle()
+        .context("HEIF file has no primary image")?;
+    let image = lib_heif
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .context("Failed to decode HEIF image")?;
+
+    let planes = image.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| anyhow!("Decoded HEIF image has no interleaved plane"))?;
+
+    // Rows may be padded, so copy them out one at a time
+    let row_bytes = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_bytes * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_bytes]);
+    }
+
+    let buffer = RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .ok_or_else(|| anyhow!("HEIF plane size does not match its dimensions"))?;
+
+    Ok(DynamicImage::ImageRgba8(buffer))
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn decode(source: &PlainFile) -> Result<DynamicImage> {
+    Err(anyhow::anyhow!(
+        "Cannot decode HEIC/HEIF file {:?}: HEIF support is not compiled in (rebuild with `--features heif`)",
+        source.path()
+    ))
+}
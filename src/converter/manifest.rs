@@ -0,0 +1,208 @@
+use crate::config::Config;
+use crate::db::models::{Album, SyncedImage};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Enough of an AVIF to reach the `ispe` property; the `meta` box precedes `mdat`
+const HEADER_READ_LIMIT: u64 = 64 * 1024;
+
+/// `avif/{album_id}/manifest.json`: everything needed to show an album from
+/// static files
+#[derive(Serialize)]
+struct Manifest {
+    album_id: String,
+    album_name: String,
+    generated_at: String,
+    images: Vec<ManifestImage>,
+}
+
+#[derive(Serialize)]
+struct ManifestImage {
+    id: String,
+    filename: String,
+    /// Dimensions of the full-size output, when they can be read from its header
+    width: Option<u32>,
+    height: Option<u32>,
+    /// Relative to the AVIF directory, with `/` separators
+    avif: String,
+    thumbnail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    placeholder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dominant_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lqip: Option<String>,
+    /// Capture time from EXIF, without a time zone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    taken_at: Option<String>,
+}
+
+/// Result of the `manifest` command
+#[derive(Debug, Default, Serialize)]
+pub struct ManifestResult {
+    pub albums: usize,
+    pub images: usize,
+}
+
+/// Write the manifest of one album's converted images, replacing any previous one.
+/// Returns the number of images listed.
+pub async fn write(pool: &SqlitePool, config: &Config, album_id: &str) -> Result<usize> {
+    let album = Album::get_by_id(pool, album_id)
+        .await?
+        .with_context(|| format!("Album {} has not been synced", album_id))?;
+    let images = SyncedImage::get_converted_by_album(pool, album_id).await?;
+    let avif_root = config.avif_path();
+
+    let entries = tokio::task::spawn_blocking(move || {
+        images
+            .into_iter()
+            .filter_map(|image| {
+                let avif_path = image.avif_path?;
+                let thumbnail_path = image.thumbnail_path?;
+                let (width, height) = dimensions(Path::new(&avif_path)).unzip();
+                Some(ManifestImage {
+                    avif: relative_to(&avif_root, &avif_path),
+                    thumbnail: relative_to(&avif_root, &thumbnail_path),
+                    id: image.id,
+                    filename: image.filename,
+                    width,
+                    height,
+                    placeholder: image.placeholder,
+                    dominant_color: image.dominant_color,
+                    lqip: image.lqip,
+                    taken_at: image
+                        .taken_at
+                        .map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string()),
+                })
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    let count = entries.len();
+    let manifest = Manifest {
+        album_id: album.id,
+        album_name: album.name,
+        generated_at: Utc::now().to_rfc3339(),
+        images: entries,
+    };
+
+    // Written aside and renamed, so a static server never hands out half a file
+    let dir = config.avif_path().join(album_id);
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(MANIFEST_FILE);
+    let temp = dir.join(format!("{}.tmp", MANIFEST_FILE));
+    tokio::fs::write(&temp, serde_json::to_vec_pretty(&manifest)?)
+        .await
+        .with_context(|| format!("Failed to write {:?}", temp))?;
+    tokio::fs::rename(&temp, &path).await?;
+    debug!("Wrote {:?} with {} images", path, count);
+
+    Ok(count)
+}
+
+/// Rewrite the manifests of albums whose images changed, when `storage.write_manifests`
+/// is on. Albums no longer synced lose theirs. Failures are only logged, as the
+/// change they follow has already happened.
+pub async fn refresh(pool: &SqlitePool, config: &Config, album_ids: &BTreeSet<String>) {
+    if !config.storage.write_manifests || album_ids.is_empty() {
+        return;
+    }
+    for album_id in album_ids {
+        let result = match Album::get_by_id(pool, album_id).await {
+            Ok(Some(_)) => write(pool, config, album_id).await.map(|_| ()),
+            Ok(None) => remove(config, album_id).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to update the manifest of album {}: {:#}",
+                album_id, e
+            );
+        }
+    }
+    info!("Updated manifests of {} albums", album_ids.len());
+}
+
+async fn remove(config: &Config, album_id: &str) -> Result<()> {
+    let path = config.avif_path().join(album_id).join(MANIFEST_FILE);
+    match tokio::fs::remove_file(&path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove {:?}", path))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn relative_to(root: &Path, path: &str) -> String {
+    let path = Path::new(path);
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Width and height from the AVIF `ispe` property, or by probing other formats
+/// (originals linked by `skip_if_smaller`)
+fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    let is_avif = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_none_or(|e| e.eq_ignore_ascii_case("avif"));
+    if !is_avif {
+        return image::image_dimensions(path).ok();
+    }
+
+    let mut header = Vec::new();
+    std::fs::File::open(path)
+        .ok()?
+        .take(HEADER_READ_LIMIT)
+        .read_to_end(&mut header)
+        .ok()?;
+
+    // meta and ispe are full boxes: version and flags come before their children
+    let meta = child_box(&header, b"meta")?.get(4..)?;
+    let ipco = child_box(child_box(meta, b"iprp")?, b"ipco")?;
+    let ispe = child_box(ipco, b"ispe")?;
+    let width = u32::from_be_bytes(ispe.get(4..8)?.try_into().ok()?);
+    let height = u32::from_be_bytes(ispe.get(8..12)?.try_into().ok()?);
+    Some((width, height))
+}
+
+/// Body of the first box of type `kind` among the boxes laid out in `data`
+fn child_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut offset = 0;
+    while data.len().saturating_sub(offset) >= 8 {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().ok()?) as usize;
+        let (size, header_len) = match size {
+            0 => (data.len() - offset, 8),
+            1 => {
+                let large = data.get(offset + 8..offset + 16)?;
+                let size = u64::from_be_bytes(large.try_into().ok()?);
+                (usize::try_from(size).ok()?, 16)
+            }
+            size => (size, 8),
+        };
+        if size < header_len {
+            return None;
+        }
+        // A corrupt 64-bit size must not wrap around to a small end offset
+        let end = offset.checked_add(size)?;
+        if &data[offset + 4..offset + 8] == kind {
+            // A box cut off by the read limit still yields its leading children
+            return data.get(offset + header_len..end.min(data.len()));
+        }
+        offset = end;
+    }
+    None
+}
@@ -0,0 +1,121 @@
+use crate::encryption::PlainFile;
+use anyhow::{bail, Context as _, Result};
+use avif_serialize::constants::MatrixCoefficients;
+use chrono::NaiveDateTime;
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Tag};
+use ravif::EncodedImage;
+use std::io::Cursor;
+
+/// Tags copied from the original besides the whole GPS IFD. Orientation is left
+/// out on purpose: pixels are already rotated upright before encoding.
+const PRESERVED_TAGS: &[Tag] = &[
+    Tag::Make,
+    Tag::Model,
+    Tag::DateTime,
+    Tag::DateTimeOriginal,
+    Tag::DateTimeDigitized,
+    Tag::OffsetTime,
+    Tag::OffsetTimeOriginal,
+    Tag::OffsetTimeDigitized,
+    Tag::SubSecTimeOriginal,
+];
+
+/// Read the capture date, camera and GPS tags from the original and serialize them
+/// as a standalone TIFF/EXIF block. Returns `None` when the source has no EXIF or
+/// none of the preserved tags.
+pub fn read_preserved_exif(source: &PlainFile) -> Option<Vec<u8>> {
+    let mut reader = source.reader().ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+
+    let fields: Vec<&Field> = exif
+        .fields()
+        .filter(|f| f.ifd_num == In::PRIMARY)
+        .filter(|f| f.tag.context() == Context::Gps || PRESERVED_TAGS.contains(&f.tag))
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut writer = Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+
+    let mut buf = Cursor::new(Vec::new());
+    writer.write(&mut buf, exif.little_endian()).ok()?;
+    Some(buf.into_inner())
+}
+
+/// Year the photo was taken, from the original's `DateTimeOriginal` tag
+pub fn capture_year(source: &PlainFile) -> Option<i32> {
+    let value = date_time_original(source)?;
+    value.get(..4)?.parse().ok()
+}
+
+/// When the photo was taken, from the original's `DateTimeOriginal` tag. EXIF has
+/// no time zone here, so this is the camera's local time.
+pub fn capture_time(source: &PlainFile) -> Option<NaiveDateTime> {
+    let value = date_time_original(source)?;
+    NaiveDateTime::parse_from_str(value.trim_end_matches('\0').trim(), "%Y:%m:%d %H:%M:%S").ok()
+}
+
+/// Raw `DateTimeOriginal` value, stored as "YYYY:MM:DD HH:MM:SS"
+fn date_time_original(source: &PlainFile) -> Option<String> {
+    let mut reader = source.reader().ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?;
+
+    match &field.value {
+        exif::Value::Ascii(values) => values
+            .first()
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Rebuild a ravif-encoded AVIF with an `Exif` item attached.
+///
+/// ravif cannot attach metadata itself, but its container is written by
+/// avif-serialize with the AV1 payloads (alpha, then color) filling a trailing
+/// `mdat`, so they can be sliced back out and re-muxed. `matrix_coefficients` and
+/// `depth_bits` must be what the image was encoded with: BT.601 at 10 bits for
+/// lossy output, Identity (RGB) at 8 bits for lossless.
+pub fn embed_exif(
+    encoded: &EncodedImage,
+    width: u32,
+    height: u32,
+    exif: &[u8],
+    matrix_coefficients: MatrixCoefficients,
+    depth_bits: u8,
+) -> Result<Vec<u8>> {
+    let avif = &encoded.avif_file[..];
+    let alpha_size = encoded.alpha_byte_size;
+    let payload_size = encoded.color_byte_size + alpha_size;
+    let mdat_start = avif
+        .len()
+        .checked_sub(payload_size + 8)
+        .context("AVIF is smaller than its payload")?;
+    let header = &avif[mdat_start..mdat_start + 8];
+    if &header[4..8] != b"mdat"
+        || u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize
+            != payload_size + 8
+    {
+        bail!("Unexpected AVIF layout, cannot attach EXIF");
+    }
+
+    let payload = &avif[mdat_start + 8..];
+    let (alpha, color) = payload.split_at(alpha_size);
+    let alpha = (alpha_size > 0).then_some(alpha);
+
+    let mut out = Vec::with_capacity(avif.len() + exif.len() + 64);
+    avif_serialize::Aviffy::new()
+        .matrix_coefficients(matrix_coefficients)
+        .premultiplied_alpha(false)
+        .set_exif(exif.to_vec())
+        .write(&mut out, color, alpha, width, height, depth_bits)
+        .context("Failed to write AVIF with EXIF")?;
+
+    Ok(out)
+}
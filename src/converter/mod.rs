@@ -1,4 +1,17 @@
+mod animation;
+mod auto_quality;
 pub mod avif;
+mod budget;
+pub mod formats;
+mod heif;
+pub mod manifest;
+mod metadata;
+pub mod mosaic;
+mod placeholder;
+mod sequence;
+mod verify;
+pub mod watch;
+pub mod watermark;
 
 pub use avif::AvifConverter;
 #[allow(unused)]
@@ -0,0 +1,131 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+
+/// Most tiles in one mosaic; later images of larger albums are left out
+pub const MAX_TILES: usize = 64;
+
+/// Accepted tiles per row. Layouts are fixed so an album has only a few cache
+/// files, and requests cannot keep building new ones.
+pub const COLS: &[u32] = &[2, 3, 4, 6, 8];
+
+/// Accepted tile sizes in pixels
+pub const TILE_SIZES: &[u32] = &[128, 256];
+
+/// Directory under the AVIF path holding cached mosaics (`GET /albums/:id/mosaic`)
+const MOSAIC_DIR: &str = "mosaics";
+
+/// JPEG quality for `format=jpeg` mosaics
+const JPEG_QUALITY: u8 = 85;
+
+/// Fill for the empty cells of an incomplete last row
+const BACKGROUND: Rgb<u8> = Rgb([24, 24, 24]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MosaicFormat {
+    Avif,
+    Jpeg,
+}
+
+impl MosaicFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            MosaicFormat::Avif => "avif",
+            MosaicFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+impl std::str::FromStr for MosaicFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "avif" => Ok(MosaicFormat::Avif),
+            "jpeg" | "jpg" => Ok(MosaicFormat::Jpeg),
+            other => anyhow::bail!("Unknown mosaic format '{}' (expected avif or jpeg)", other),
+        }
+    }
+}
+
+/// Cache file for a layout. The tile count is part of the name, so converting or
+/// removing an image in the album makes the old file unreachable.
+pub fn cache_path(
+    avif_path: &Path,
+    album_id: &str,
+    cols: u32,
+    size: u32,
+    tiles: usize,
+    format: MosaicFormat,
+) -> PathBuf {
+    avif_path.join(MOSAIC_DIR).join(format!(
+        "{}_c{}_s{}_n{}.{}",
+        album_id,
+        cols,
+        size,
+        tiles,
+        format.extension()
+    ))
+}
+
+/// Remove cached mosaics of the same album and layout made for another tile count
+pub fn remove_stale(current: &Path) {
+    let (Some(dir), Some(name)) = (
+        current.parent(),
+        current.file_name().and_then(|n| n.to_str()),
+    ) else {
+        return;
+    };
+    let Some(prefix) = name
+        .rsplit_once("_n")
+        .map(|(layout, _)| format!("{}_n", layout))
+    else {
+        return;
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let stale = path.extension() == current.extension()
+            && path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n != name && n.starts_with(&prefix));
+        if stale {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Scale and center-crop an image to a `size` pixel square tile
+pub fn tile(img: &DynamicImage, size: u32) -> RgbImage {
+    img.resize_to_fill(size, size, FilterType::Triangle)
+        .to_rgb8()
+}
+
+/// Lay out `size` pixel tiles `cols` to a row
+pub fn compose(tiles: &[RgbImage], cols: u32, size: u32) -> DynamicImage {
+    let cols = cols.min(tiles.len().max(1) as u32);
+    let rows = (tiles.len() as u32).div_ceil(cols).max(1);
+    let mut canvas = RgbImage::from_pixel(cols * size, rows * size, BACKGROUND);
+
+    for (i, tile) in tiles.iter().enumerate() {
+        let i = i as u32;
+        image::imageops::replace(
+            &mut canvas,
+            tile,
+            ((i % cols) * size) as i64,
+            ((i / cols) * size) as i64,
+        );
+    }
+    DynamicImage::ImageRgb8(canvas)
+}
+
+/// Encode a mosaic as JPEG; AVIF goes through the converter's encoder
+pub fn encode_jpeg(mosaic: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode_image(&mosaic.to_rgb8())?;
+    Ok(jpeg)
+}
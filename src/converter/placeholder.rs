@@ -0,0 +1,361 @@
+use crate::config::PlaceholderStrategy;
+use base64::Engine;
+use image::DynamicImage;
+use std::f32::consts::PI;
+
+/// ThumbHash input is limited to 100x100; BlurHash gains nothing from more pixels
+const MAX_INPUT_SIZE: u32 = 100;
+
+/// BlurHash component counts, matching the common 4x3 default
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// LQIP width; portrait images may be up to four times as tall
+const LQIP_WIDTH: u32 = 16;
+
+/// JPEG qualities tried for the LQIP, until one fits `MAX_LQIP_LEN`
+const LQIP_QUALITIES: [u8; 3] = [50, 30, 15];
+
+/// Longest LQIP data URI stored, in bytes
+const MAX_LQIP_LEN: usize = 1536;
+
+/// Width of the image served in place of one that is not converted yet
+const PENDING_WIDTH: u32 = 32;
+
+/// Fill of that image when neither a BlurHash nor a dominant color is known
+const PENDING_COLOR: [u8; 3] = [0xcc, 0xcc, 0xcc];
+
+/// Copy of `img` shrunk to fit `MAX_INPUT_SIZE` with its aspect ratio kept, large
+/// enough for every placeholder below, so a full-size image is scaled down once
+pub fn preview(img: &DynamicImage) -> DynamicImage {
+    img.thumbnail(MAX_INPUT_SIZE, MAX_INPUT_SIZE)
+}
+
+/// Compute the configured placeholder hash, from a copy of `img` shrunk to fit
+/// `MAX_INPUT_SIZE` with its aspect ratio kept. BlurHash is returned in its own
+/// base83 form, ThumbHash as standard base64.
+pub fn compute(
+    img: &DynamicImage,
+    strategy: PlaceholderStrategy,
+) -> anyhow::Result<Option<String>> {
+    if strategy == PlaceholderStrategy::None {
+        return Ok(None);
+    }
+
+    let small = img.thumbnail(MAX_INPUT_SIZE, MAX_INPUT_SIZE).to_rgba8();
+    let (width, height) = small.dimensions();
+
+    let hash = match strategy {
+        PlaceholderStrategy::None => unreachable!(),
+        PlaceholderStrategy::Blurhash => blurhash::encode(
+            BLURHASH_COMPONENTS.0,
+            BLURHASH_COMPONENTS.1,
+            width,
+            height,
+            small.as_raw(),
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to compute BlurHash: {:?}", e))?,
+        PlaceholderStrategy::Thumbhash => {
+            let hash = rgba_to_thumb_hash(width as usize, height as usize, small.as_raw());
+            base64::engine::general_purpose::STANDARD.encode(hash)
+        }
+    };
+
+    Ok(Some(hash))
+}
+
+/// Tiny JPEG of the image as a `data:image/jpeg;base64,...` URI, usable directly as
+/// an `<img src>`. Transparent areas are flattened onto white. `None` if even the
+/// lowest quality does not fit `MAX_LQIP_LEN`.
+pub fn lqip(img: &DynamicImage) -> anyhow::Result<Option<String>> {
+    let small = img.thumbnail(LQIP_WIDTH, LQIP_WIDTH * 4).to_rgba8();
+    let flattened = image::RgbImage::from_fn(small.width(), small.height(), |x, y| {
+        let [r, g, b, a] = small.get_pixel(x, y).0;
+        let over_white = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+        image::Rgb([over_white(r), over_white(g), over_white(b)])
+    });
+
+    for quality in LQIP_QUALITIES {
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, quality)
+            .encode_image(&flattened)?;
+        let uri = format!(
+            "data:image/jpeg;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(&jpeg)
+        );
+        if uri.len() <= MAX_LQIP_LEN {
+            return Ok(Some(uri));
+        }
+    }
+    Ok(None)
+}
+
+/// Average color as a `#rrggbb` hex string, weighted by alpha so transparent
+/// areas do not pull it towards black. `None` for fully transparent images.
+pub fn dominant_color(img: &DynamicImage) -> Option<String> {
+    let small = img.thumbnail(MAX_INPUT_SIZE, MAX_INPUT_SIZE).to_rgba8();
+
+    let (mut r, mut g, mut b, mut weight) = (0u64, 0u64, 0u64, 0u64);
+    for px in small.pixels() {
+        let alpha = u64::from(px[3]);
+        r += u64::from(px[0]) * alpha;
+        g += u64::from(px[1]) * alpha;
+        b += u64::from(px[2]) * alpha;
+        weight += alpha;
+    }
+    if weight == 0 {
+        return None;
+    }
+
+    let channel = |sum: u64| (sum as f64 / weight as f64).round() as u8;
+    Some(format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(r),
+        channel(g),
+        channel(b)
+    ))
+}
+
+/// PNG served while an image is not converted yet: its BlurHash rendered
+/// `PENDING_WIDTH` pixels wide when there is one, otherwise a solid fill of its
+/// `#rrggbb` dominant color. `aspect` (width / height) defaults to 4:3.
+pub fn pending_image(
+    blurhash: Option<&str>,
+    dominant_color: Option<&str>,
+    aspect: Option<f32>,
+) -> anyhow::Result<Vec<u8>> {
+    let width = PENDING_WIDTH;
+    let height = ((width as f32 / aspect.unwrap_or(4.0 / 3.0)).round() as u32).clamp(1, width * 4);
+
+    let decoded = blurhash
+        .and_then(|hash| blurhash::decode(hash, width, height, 1.0).ok())
+        .and_then(|pixels| image::RgbaImage::from_raw(width, height, pixels));
+    let img = match decoded {
+        Some(rgba) => DynamicImage::ImageRgba8(rgba),
+        None => {
+            let color = dominant_color
+                .and_then(|hex| hex.strip_prefix('#'))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .map(|rgb| [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+                .unwrap_or(PENDING_COLOR);
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                width,
+                height,
+                image::Rgb(color),
+            ))
+        }
+    };
+
+    let mut png = Vec::new();
+    img.write_to(
+        &mut std::io::Cursor::new(&mut png),
+        image::ImageOutputFormat::Png,
+    )?;
+    Ok(png)
+}
+
+/// Encode an RGBA image of at most 100x100 pixels as a ThumbHash.
+///
+/// Port of the reference encoder from <https://github.com/evanw/thumbhash>, so the
+/// output decodes with any of its implementations.
+fn rgba_to_thumb_hash(w: usize, h: usize, rgba: &[u8]) -> Vec<u8> {
+    debug_assert!(w <= 100 && h <= 100);
+    debug_assert_eq!(rgba.len(), w * h * 4);
+
+    // Average color, weighted by alpha
+    let (mut avg_r, mut avg_g, mut avg_b, mut avg_a) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+    for px in rgba.chunks_exact(4) {
+        let alpha = px[3] as f32 / 255.0;
+        avg_r += alpha / 255.0 * px[0] as f32;
+        avg_g += alpha / 255.0 * px[1] as f32;
+        avg_b += alpha / 255.0 * px[2] as f32;
+        avg_a += alpha;
+    }
+    if avg_a > 0.0 {
+        avg_r /= avg_a;
+        avg_g /= avg_a;
+        avg_b /= avg_a;
+    }
+
+    let has_alpha = avg_a < (w * h) as f32;
+    let l_limit = if has_alpha { 5 } else { 7 };
+    let lx = 1.max(((l_limit * w) as f32 / w.max(h) as f32).round() as usize);
+    let ly = 1.max(((l_limit * h) as f32 / w.max(h) as f32).round() as usize);
+
+    // Convert to LPQA, compositing transparent pixels over the average color
+    let mut l = Vec::with_capacity(w * h);
+    let mut p = Vec::with_capacity(w * h);
+    let mut q = Vec::with_capacity(w * h);
+    let mut a = Vec::with_capacity(w * h);
+    for px in rgba.chunks_exact(4) {
+        let alpha = px[3] as f32 / 255.0;
+        let r = avg_r * (1.0 - alpha) + alpha / 255.0 * px[0] as f32;
+        let g = avg_g * (1.0 - alpha) + alpha / 255.0 * px[1] as f32;
+        let b = avg_b * (1.0 - alpha) + alpha / 255.0 * px[2] as f32;
+        l.push((r + g + b) / 3.0);
+        p.push((r + g) / 2.0 - b);
+        q.push(r - g);
+        a.push(alpha);
+    }
+
+    // DCT of each channel, keeping the low-frequency triangle
+    let encode_channel = |channel: &[f32], nx: usize, ny: usize| -> (f32, Vec<f32>, f32) {
+        let mut dc = 0.0;
+        let mut ac = Vec::with_capacity(nx * ny / 2);
+        let mut scale = 0.0f32;
+        let mut fx = vec![0.0f32; w];
+        for cy in 0..ny {
+            let mut cx = 0;
+            while cx * ny < nx * (ny - cy) {
+                for (x, f) in fx.iter_mut().enumerate() {
+                    *f = (PI / w as f32 * cx as f32 * (x as f32 + 0.5)).cos();
+                }
+                let mut f = 0.0;
+                for y in 0..h {
+                    let fy = (PI / h as f32 * cy as f32 * (y as f32 + 0.5)).cos();
+                    for (x, fx) in fx.iter().enumerate() {
+                        f += channel[x + y * w] * fx * fy;
+                    }
+                }
+                f /= (w * h) as f32;
+                if cx > 0 || cy > 0 {
+                    ac.push(f);
+                    scale = scale.max(f.abs());
+                } else {
+                    dc = f;
+                }
+                cx += 1;
+            }
+        }
+        if scale > 0.0 {
+            for v in &mut ac {
+                *v = 0.5 + 0.5 / scale * *v;
+            }
+        }
+        (dc, ac, scale)
+    };
+
+    let (l_dc, l_ac, l_scale) = encode_channel(&l, lx.max(3), ly.max(3));
+    let (p_dc, p_ac, p_scale) = encode_channel(&p, 3, 3);
+    let (q_dc, q_ac, q_scale) = encode_channel(&q, 3, 3);
+    let (a_dc, a_ac, a_scale) = if has_alpha {
+        encode_channel(&a, 5, 5)
+    } else {
+        (1.0, Vec::new(), 1.0)
+    };
+
+    // Pack the header and quantized coefficients
+    let is_landscape = w > h;
+    let header24 = (63.0 * l_dc).round() as u32
+        | (((31.5 + 31.5 * p_dc).round() as u32) << 6)
+        | (((31.5 + 31.5 * q_dc).round() as u32) << 12)
+        | (((31.0 * l_scale).round() as u32) << 18)
+        | if has_alpha { 1 << 23 } else { 0 };
+    let header16 = (if is_landscape { ly } else { lx }) as u16
+        | (((63.0 * p_scale).round() as u16) << 3)
+        | (((63.0 * q_scale).round() as u16) << 9)
+        | if is_landscape { 1 << 15 } else { 0 };
+
+    let mut hash = Vec::with_capacity(25);
+    hash.extend_from_slice(&[
+        (header24 & 255) as u8,
+        ((header24 >> 8) & 255) as u8,
+        (header24 >> 16) as u8,
+        (header16 & 255) as u8,
+        (header16 >> 8) as u8,
+    ]);
+    if has_alpha {
+        hash.push((15.0 * a_dc).round() as u8 | (((15.0 * a_scale).round() as u8) << 4));
+    }
+
+    let mut is_odd = false;
+    let alpha_ac = if has_alpha { a_ac } else { Vec::new() };
+    for v in l_ac.into_iter().chain(p_ac).chain(q_ac).chain(alpha_ac) {
+        let u = (15.0 * v).round() as u8;
+        if is_odd {
+            if let Some(last) = hash.last_mut() {
+                *last |= u << 4;
+            }
+        } else {
+            hash.push(u);
+        }
+        is_odd = !is_odd;
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Average color as RGBA in 0..=1, decoded as the reference `thumbHashToAverageRGBA`
+    fn decode_average(hash: &[u8]) -> [f32; 4] {
+        let header = u32::from(hash[0]) | u32::from(hash[1]) << 8 | u32::from(hash[2]) << 16;
+        let l = (header & 63) as f32 / 63.0;
+        let p = ((header >> 6) & 63) as f32 / 31.5 - 1.0;
+        let q = ((header >> 12) & 63) as f32 / 31.5 - 1.0;
+        let a = if header >> 23 != 0 {
+            (hash[5] & 15) as f32 / 15.0
+        } else {
+            1.0
+        };
+        let b = l - 2.0 / 3.0 * p;
+        let r = (3.0 * l - b + q) / 2.0;
+        let g = r - q;
+        [r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0), a]
+    }
+
+    /// Width / height, decoded as the reference `thumbHashToApproximateAspectRatio`
+    fn decode_aspect(hash: &[u8]) -> f32 {
+        let has_alpha = hash[2] & 0x80 != 0;
+        let is_landscape = hash[4] & 0x80 != 0;
+        let l_limit = if has_alpha { 5.0 } else { 7.0 };
+        let l_size = (hash[3] & 7) as f32;
+        if is_landscape {
+            l_limit / l_size
+        } else {
+            l_size / l_limit
+        }
+    }
+
+    #[test]
+    fn thumbhash_round_trips_color_and_aspect() {
+        let color = [200u8, 100, 50];
+        for (width, height) in [(400, 200), (200, 400)] {
+            let img = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                width,
+                height,
+                image::Rgb(color),
+            ));
+            let hash = compute(&img, PlaceholderStrategy::Thumbhash)
+                .unwrap()
+                .unwrap();
+            let hash = base64::engine::general_purpose::STANDARD
+                .decode(hash)
+                .unwrap();
+
+            let [r, g, b, a] = decode_average(&hash);
+            for (decoded, expected) in [r, g, b].into_iter().zip(color) {
+                assert!(
+                    (decoded - expected as f32 / 255.0).abs() < 0.05,
+                    "{}x{}: decoded {} for {}",
+                    width,
+                    height,
+                    decoded,
+                    expected
+                );
+            }
+            assert_eq!(a, 1.0);
+
+            let aspect = decode_aspect(&hash);
+            let expected = width as f32 / height as f32;
+            assert!(
+                (aspect / expected - 1.0).abs() < 0.2,
+                "{}x{}: decoded aspect {}",
+                width,
+                height,
+                aspect
+            );
+        }
+    }
+}
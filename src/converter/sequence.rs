@@ -0,0 +1,396 @@
+use anyhow::{bail, Result};
+
+/// Timescale of every track: durations are in milliseconds
+const TIMESCALE: u32 = 1000;
+
+/// `nclx` colour of the frames, signalled as full range. It must match what the
+/// encoder wrote into the AV1 sequence header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Nclx {
+    pub colour_primaries: u16,
+    pub transfer_characteristics: u16,
+    pub matrix_coefficients: u16,
+}
+
+impl Nclx {
+    /// BT.709 primaries, sRGB transfer, BT.601 matrix: lossy YCbCr frames
+    pub const YCBCR: Nclx = Nclx {
+        colour_primaries: 1,
+        transfer_characteristics: 13,
+        matrix_coefficients: 6,
+    };
+    /// The same with the Identity matrix: lossless frames coded as GBR
+    pub const RGB: Nclx = Nclx {
+        matrix_coefficients: 0,
+        ..Nclx::YCBCR
+    };
+}
+
+const ALPHA_URN: &[u8] = b"urn:mpeg:mpegB:cicp:systems:auxiliary:alpha\0";
+
+/// Identity transform used by `mvhd` and `tkhd`
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Duration meaning "indefinite", used with the looping edit list
+const INDEFINITE: u32 = u32::MAX;
+
+/// One encoded AV1 track: its `av1C` record and one sample per frame
+pub struct Track {
+    pub config: Vec<u8>,
+    pub samples: Vec<Sample>,
+}
+
+pub struct Sample {
+    pub data: Vec<u8>,
+    pub keyframe: bool,
+}
+
+/// Mux AV1 tracks into an AVIF image sequence (`avis`) that loops forever.
+///
+/// Alongside the `moov` tracks, the first frame is also declared as the primary
+/// item in `meta`, pointing at the same bytes, so readers without sequence
+/// support still show a still poster image.
+pub fn write(
+    width: u32,
+    height: u32,
+    durations: &[u32],
+    color: &Track,
+    alpha: Option<&Track>,
+    nclx: Nclx,
+) -> Result<Vec<u8>> {
+    let tracks: Vec<&Track> = std::iter::once(color).chain(alpha).collect();
+    if tracks.iter().any(|t| t.samples.len() != durations.len()) {
+        bail!("Every track needs one sample per frame");
+    }
+    if durations.is_empty() {
+        bail!("Image sequence has no frames");
+    }
+    let payload_size: usize = tracks
+        .iter()
+        .flat_map(|t| &t.samples)
+        .map(|s| s.data.len())
+        .sum();
+    if payload_size > (u32::MAX - 8) as usize {
+        bail!("Image sequence is too large");
+    }
+
+    // Box sizes do not depend on the offsets, so lay out once to find where
+    // `mdat` starts, then write again with the real offsets
+    let ftyp = ftyp();
+    let header_len = |starts: &[u32]| {
+        meta(width, height, &tracks, starts, nclx).len()
+            + moov(width, height, durations, &tracks, starts, nclx).len()
+    };
+    let mdat_body_start = (ftyp.len() + header_len(&[0, 0]) + 8) as u32;
+
+    let mut starts = Vec::with_capacity(tracks.len());
+    let mut offset = mdat_body_start;
+    for track in &tracks {
+        starts.push(offset);
+        offset += track
+            .samples
+            .iter()
+            .map(|s| s.data.len() as u32)
+            .sum::<u32>();
+    }
+
+    let mut out = ftyp;
+    out.extend(meta(width, height, &tracks, &starts, nclx));
+    out.extend(moov(width, height, durations, &tracks, &starts, nclx));
+    out.extend(((payload_size + 8) as u32).to_be_bytes());
+    out.extend(b"mdat");
+    for sample in tracks.iter().flat_map(|t| &t.samples) {
+        out.extend(&sample.data);
+    }
+    debug_assert_eq!(out.len() as u32, offset);
+
+    Ok(out)
+}
+
+fn ftyp() -> Vec<u8> {
+    let mut b = Vec::new();
+    b.extend(b"avis");
+    b.extend(0u32.to_be_bytes());
+    for brand in [b"avif", b"avis", b"msf1", b"iso8", b"mif1", b"miaf"] {
+        b.extend(brand);
+    }
+    basic_box(b"ftyp", &b)
+}
+
+/// Still-image view of the first frame: item 1 is the colour frame and item 2,
+/// when present, its alpha plane
+fn meta(width: u32, height: u32, tracks: &[&Track], starts: &[u32], nclx: Nclx) -> Vec<u8> {
+    let mut iloc = vec![0x44, 0x00];
+    iloc.extend((tracks.len() as u16).to_be_bytes());
+    for (i, (track, start)) in tracks.iter().zip(starts).enumerate() {
+        iloc.extend((i as u16 + 1).to_be_bytes());
+        iloc.extend(0u16.to_be_bytes()); // data reference index
+        iloc.extend(1u16.to_be_bytes()); // extent count
+        iloc.extend(start.to_be_bytes());
+        iloc.extend((track.samples[0].data.len() as u32).to_be_bytes());
+    }
+
+    let mut iinf = Vec::new();
+    iinf.extend((tracks.len() as u16).to_be_bytes());
+    for i in 0..tracks.len() {
+        let mut infe = Vec::new();
+        infe.extend((i as u16 + 1).to_be_bytes());
+        infe.extend(0u16.to_be_bytes()); // protection index
+        infe.extend(b"av01");
+        infe.push(0); // empty name
+        iinf.extend(full_box(b"infe", 2, 0, &infe));
+    }
+
+    // Property indices in ipma are 1-based positions in ipco
+    let mut ipco = Vec::new();
+    ipco.extend(ispe(width, height));
+    ipco.extend(basic_box(b"av1C", &tracks[0].config));
+    ipco.extend(pixi(3));
+    ipco.extend(colr(nclx));
+    let mut ipma = Vec::new();
+    ipma.extend((tracks.len() as u32).to_be_bytes());
+    ipma.extend([0, 1, 4, 1, 0x80 | 2, 3, 4]);
+
+    let mut boxes = Vec::new();
+    boxes.extend(hdlr(b"pict"));
+    boxes.extend(full_box(b"pitm", 0, 0, &1u16.to_be_bytes()));
+    boxes.extend(full_box(b"iloc", 0, 0, &iloc));
+    boxes.extend(full_box(b"iinf", 0, 0, &iinf));
+
+    if let Some(alpha) = tracks.get(1) {
+        ipco.extend(basic_box(b"av1C", &alpha.config));
+        ipco.extend(pixi(1));
+        ipco.extend(full_box(b"auxC", 0, 0, ALPHA_URN));
+        ipma.extend([0, 2, 4, 1, 0x80 | 5, 6, 7]);
+
+        let auxl = [2u16, 1, 1].map(u16::to_be_bytes).concat();
+        boxes.extend(full_box(b"iref", 0, 0, &basic_box(b"auxl", &auxl)));
+    }
+
+    let mut iprp = basic_box(b"ipco", &ipco);
+    iprp.extend(full_box(b"ipma", 0, 0, &ipma));
+    boxes.extend(basic_box(b"iprp", &iprp));
+
+    full_box(b"meta", 0, 0, &boxes)
+}
+
+fn moov(
+    width: u32,
+    height: u32,
+    durations: &[u32],
+    tracks: &[&Track],
+    starts: &[u32],
+    nclx: Nclx,
+) -> Vec<u8> {
+    let mut mvhd = Vec::new();
+    mvhd.extend(
+        [0u32, 0, TIMESCALE, INDEFINITE]
+            .map(u32::to_be_bytes)
+            .concat(),
+    );
+    mvhd.extend(0x0001_0000u32.to_be_bytes()); // rate
+    mvhd.extend(0x0100u16.to_be_bytes()); // volume
+    mvhd.extend([0u8; 10]);
+    mvhd.extend(UNITY_MATRIX.map(u32::to_be_bytes).concat());
+    mvhd.extend([0u8; 24]);
+    mvhd.extend((tracks.len() as u32 + 1).to_be_bytes()); // next track ID
+
+    let mut boxes = full_box(b"mvhd", 0, 0, &mvhd);
+    for (i, (track, start)) in tracks.iter().zip(starts).enumerate() {
+        boxes.extend(trak(
+            i as u32 + 1,
+            width,
+            height,
+            durations,
+            track,
+            *start,
+            nclx,
+        ));
+    }
+    basic_box(b"moov", &boxes)
+}
+
+/// Track 1 carries colour, track 2 the alpha plane referencing it
+fn trak(
+    track_id: u32,
+    width: u32,
+    height: u32,
+    durations: &[u32],
+    track: &Track,
+    start: u32,
+    nclx: Nclx,
+) -> Vec<u8> {
+    let is_alpha = track_id > 1;
+    let total: u32 = durations.iter().sum();
+
+    let mut tkhd = Vec::new();
+    tkhd.extend(
+        [0u32, 0, track_id, 0, INDEFINITE]
+            .map(u32::to_be_bytes)
+            .concat(),
+    );
+    tkhd.extend([0u8; 16]); // reserved, layer, alternate group, volume, reserved
+    tkhd.extend(UNITY_MATRIX.map(u32::to_be_bytes).concat());
+    tkhd.extend((width << 16).to_be_bytes());
+    tkhd.extend((height << 16).to_be_bytes());
+
+    // Flag 1 on the edit list repeats it, looping the animation forever
+    let mut elst = Vec::new();
+    elst.extend([1u32, total, 0].map(u32::to_be_bytes).concat());
+    elst.extend([1u16, 0].map(u16::to_be_bytes).concat());
+
+    let mut mdhd = Vec::new();
+    mdhd.extend([0u32, 0, TIMESCALE, total].map(u32::to_be_bytes).concat());
+    mdhd.extend(0x55C4u16.to_be_bytes()); // "und"
+    mdhd.extend(0u16.to_be_bytes());
+
+    let mut minf = full_box(b"vmhd", 0, 1, &[0u8; 8]);
+    let dref = [1u32.to_be_bytes().to_vec(), full_box(b"url ", 0, 1, &[])].concat();
+    minf.extend(basic_box(b"dinf", &full_box(b"dref", 0, 0, &dref)));
+    let colr = (!is_alpha).then(|| colr(nclx));
+    minf.extend(stbl(width, height, durations, track, start, colr));
+
+    let mut mdia = full_box(b"mdhd", 0, 0, &mdhd);
+    mdia.extend(hdlr(if is_alpha { b"auxv" } else { b"pict" }));
+    mdia.extend(basic_box(b"minf", &minf));
+
+    let mut boxes = full_box(b"tkhd", 0, 3, &tkhd);
+    if is_alpha {
+        boxes.extend(basic_box(b"tref", &basic_box(b"auxl", &1u32.to_be_bytes())));
+    }
+    boxes.extend(basic_box(b"edts", &full_box(b"elst", 0, 1, &elst)));
+    boxes.extend(basic_box(b"mdia", &mdia));
+    basic_box(b"trak", &boxes)
+}
+
+/// Sample table for a track stored as a single chunk at `start`
+fn stbl(
+    width: u32,
+    height: u32,
+    durations: &[u32],
+    track: &Track,
+    start: u32,
+    colr: Option<Vec<u8>>,
+) -> Vec<u8> {
+    let mut entry = vec![0u8; 6];
+    entry.extend(1u16.to_be_bytes()); // data reference index
+    entry.extend([0u8; 16]);
+    entry.extend((width as u16).to_be_bytes());
+    entry.extend((height as u16).to_be_bytes());
+    entry.extend(
+        [0x0048_0000u32, 0x0048_0000, 0]
+            .map(u32::to_be_bytes)
+            .concat(),
+    );
+    entry.extend(1u16.to_be_bytes()); // frame count
+    entry.extend([0u8; 32]); // compressor name
+    entry.extend(0x0018u16.to_be_bytes()); // depth
+    entry.extend((-1i16).to_be_bytes());
+    entry.extend(basic_box(b"av1C", &track.config));
+    match colr {
+        Some(colr) => entry.extend(colr),
+        None => entry.extend(full_box(b"auxi", 0, 0, ALPHA_URN)),
+    }
+    // Coding constraints: intra prediction used, up to 15 reference frames
+    entry.extend(full_box(b"ccst", 0, 0, &0x7C00_0000u32.to_be_bytes()));
+
+    let mut stsd = 1u32.to_be_bytes().to_vec();
+    stsd.extend(basic_box(b"av01", &entry));
+
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for &duration in durations {
+        match runs.last_mut() {
+            Some((count, delta)) if *delta == duration => *count += 1,
+            _ => runs.push((1, duration)),
+        }
+    }
+    let mut stts = (runs.len() as u32).to_be_bytes().to_vec();
+    for (count, delta) in runs {
+        stts.extend([count, delta].map(u32::to_be_bytes).concat());
+    }
+
+    let samples = &track.samples;
+    let mut stsz = [0, samples.len() as u32].map(u32::to_be_bytes).concat();
+    for sample in samples {
+        stsz.extend((sample.data.len() as u32).to_be_bytes());
+    }
+
+    let mut boxes = full_box(b"stsd", 0, 0, &stsd);
+    boxes.extend(full_box(b"stts", 0, 0, &stts));
+    // Without stss every sample counts as a sync sample
+    if samples.iter().any(|s| !s.keyframe) {
+        let keyframes: Vec<u32> = (1..)
+            .zip(samples)
+            .filter(|(_, s)| s.keyframe)
+            .map(|(n, _)| n)
+            .collect();
+        let mut stss = (keyframes.len() as u32).to_be_bytes().to_vec();
+        stss.extend(keyframes.iter().flat_map(|n| n.to_be_bytes()));
+        boxes.extend(full_box(b"stss", 0, 0, &stss));
+    }
+    let stsc = [1, 1, samples.len() as u32, 1]
+        .map(u32::to_be_bytes)
+        .concat();
+    boxes.extend(full_box(b"stsc", 0, 0, &stsc));
+    boxes.extend(full_box(b"stsz", 0, 0, &stsz));
+    boxes.extend(full_box(
+        b"stco",
+        0,
+        0,
+        &[1, start].map(u32::to_be_bytes).concat(),
+    ));
+    basic_box(b"stbl", &boxes)
+}
+
+fn hdlr(handler: &[u8; 4]) -> Vec<u8> {
+    let mut b = 0u32.to_be_bytes().to_vec();
+    b.extend(handler);
+    b.extend([0u8; 12]);
+    b.push(0); // empty name
+    full_box(b"hdlr", 0, 0, &b)
+}
+
+fn ispe(width: u32, height: u32) -> Vec<u8> {
+    full_box(
+        b"ispe",
+        0,
+        0,
+        &[width, height].map(u32::to_be_bytes).concat(),
+    )
+}
+
+fn pixi(channels: u8) -> Vec<u8> {
+    let mut b = vec![channels];
+    b.extend(std::iter::repeat_n(8, channels as usize));
+    full_box(b"pixi", 0, 0, &b)
+}
+
+fn colr(nclx: Nclx) -> Vec<u8> {
+    let mut b = b"nclx".to_vec();
+    b.extend(
+        [
+            nclx.colour_primaries,
+            nclx.transfer_characteristics,
+            nclx.matrix_coefficients,
+        ]
+        .map(u16::to_be_bytes)
+        .concat(),
+    );
+    b.push(0x80); // full range
+    basic_box(b"colr", &b)
+}
+
+fn basic_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut b = Vec::with_capacity(body.len() + 8);
+    b.extend((body.len() as u32 + 8).to_be_bytes());
+    b.extend(kind);
+    b.extend(body);
+    b
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+    let mut b = Vec::with_capacity(body.len() + 4);
+    b.extend((((version as u32) << 24) | flags).to_be_bytes());
+    b.extend(body);
+    basic_box(kind, &b)
+}
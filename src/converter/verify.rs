@@ -0,0 +1,96 @@
+use super::formats;
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Brands that mark an ISO-BMFF file as AVIF (still image or sequence)
+const AVIF_BRANDS: &[&[u8; 4]] = &[b"avif", b"avis"];
+
+/// Check that a converted file is complete and readable.
+///
+/// AVIFs are validated structurally: the top-level boxes must start with an AVIF
+/// `ftyp`, include `meta` and a non-empty `mdat`, and add up to exactly the file
+/// length, which catches truncated writes without a full AV1 decode. Passthrough
+/// files in other formats are decoded with `image`.
+pub fn check_file(path: &Path) -> Result<()> {
+    let is_avif = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_none_or(|e| e.eq_ignore_ascii_case("avif"));
+
+    if is_avif {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        check_avif(&data)
+    } else {
+        formats::reader(path)
+            .map_err(image::ImageError::from)
+            .and_then(|reader| reader.decode())
+            .map(|_| ())
+            .with_context(|| format!("Failed to decode {:?}", path))
+    }
+}
+
+fn check_avif(data: &[u8]) -> Result<()> {
+    let mut offset = 0usize;
+    let mut seen_ftyp = false;
+    let mut seen_meta = false;
+    let mut seen_mdat = false;
+
+    while offset < data.len() {
+        let header = data
+            .get(offset..offset + 8)
+            .context("Truncated box header")?;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        let (size, header_len) = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            0 => (data.len() - offset, 8),
+            1 => {
+                let large = data
+                    .get(offset + 8..offset + 16)
+                    .context("Truncated box header")?;
+                let size = u64::from_be_bytes(large.try_into().unwrap());
+                (usize::try_from(size).unwrap_or(usize::MAX), 16)
+            }
+            size => (size as usize, 8),
+        };
+        // A corrupt 64-bit size must not wrap around to a small end offset
+        let Some(end) = offset
+            .checked_add(size)
+            .filter(|&end| size >= header_len && end <= data.len())
+        else {
+            bail!(
+                "Box '{}' at offset {} extends past end of file",
+                String::from_utf8_lossy(&kind),
+                offset
+            );
+        };
+
+        let body = &data[offset + header_len..end];
+        match &kind {
+            b"ftyp" if offset == 0 => {
+                // major brand, minor version, then compatible brands
+                let major = body.get(0..4).context("Truncated ftyp box")?;
+                let compatible = body.get(8..).unwrap_or_default().chunks_exact(4);
+                seen_ftyp = std::iter::once(major)
+                    .chain(compatible)
+                    .any(|brand| AVIF_BRANDS.iter().any(|b| b.as_slice() == brand));
+                if !seen_ftyp {
+                    bail!("Not an AVIF file (missing avif brand)");
+                }
+            }
+            _ if offset == 0 => bail!("Not an AVIF file (missing ftyp box)"),
+            b"meta" => seen_meta = true,
+            b"mdat" => seen_mdat = !body.is_empty(),
+            _ => {}
+        }
+
+        offset = end;
+    }
+
+    if !seen_ftyp || !seen_meta {
+        bail!("AVIF is missing its ftyp or meta box");
+    }
+    if !seen_mdat {
+        bail!("AVIF has no image data");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use super::AvifConverter;
+use crate::db::models::SyncedImage;
+use crate::storage;
+use notify::event::{CreateKind, ModifyKind};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Quiet time after a file's last event before it is converted
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How long a new file may wait for its database row; sync writes rows in
+/// batches, so they can trail the file by a while
+const ROW_TIMEOUT: Duration = Duration::from_secs(600);
+
+struct Pending {
+    first_seen: Instant,
+    last_event: Instant,
+}
+
+/// Watch `dir` for new originals and convert each once its download is complete
+/// and recorded. Never returns; if the watch cannot be set up, a warning is logged
+/// and conversion is left to the scheduled passes. Lookups use the converter's
+/// database pool.
+pub async fn run(converter: &AvifConverter, dir: PathBuf) {
+    let (tx, mut events) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })
+    .and_then(|mut watcher| {
+        watcher.watch(&dir, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+    // Dropping the watcher would end the events
+    let _watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Cannot watch {:?} for new originals: {}", dir, e);
+            return std::future::pending().await;
+        }
+    };
+    info!("Watching {:?} for new originals", dir);
+
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    let mut tick = tokio::time::interval(DEBOUNCE / 2);
+    loop {
+        tokio::select! {
+            Some(event) = events.recv() => match event {
+                Ok(event) => {
+                    let now = Instant::now();
+                    for path in new_files(event) {
+                        pending
+                            .entry(path)
+                            .and_modify(|p| p.last_event = now)
+                            .or_insert(Pending {
+                                first_seen: now,
+                                last_event: now,
+                            });
+                    }
+                }
+                Err(e) => warn!("File watcher error: {}", e),
+            },
+            _ = tick.tick() => {
+                let ready: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, p)| p.last_event.elapsed() >= DEBOUNCE)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                if ready.is_empty() {
+                    continue;
+                }
+
+                let mut images = Vec::new();
+                for path in ready {
+                    match recorded_images(&converter.pool(), &path).await {
+                        Ok(Some(found)) => {
+                            pending.remove(&path);
+                            images.extend(found);
+                        }
+                        Ok(None) => {
+                            let Some(entry) = pending.get_mut(&path) else {
+                                continue;
+                            };
+                            if entry.first_seen.elapsed() >= ROW_TIMEOUT || !path.exists() {
+                                debug!("No image recorded for {:?}, no longer waiting", path);
+                                pending.remove(&path);
+                            } else {
+                                // Checked again after another quiet period
+                                entry.last_event = Instant::now();
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Failed to look up image for {:?}: {}", path, e);
+                            pending.remove(&path);
+                        }
+                    }
+                }
+
+                if !images.is_empty() {
+                    if let Err(e) = converter.convert_new(images).await {
+                        warn!("Converting new originals failed: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Files an event leaves under their final name: created directly (hardlinks) or
+/// renamed into place (downloads finishing their `.part` file)
+fn new_files(event: Event) -> Vec<PathBuf> {
+    match event.kind {
+        EventKind::Create(CreateKind::File | CreateKind::Any)
+        | EventKind::Modify(ModifyKind::Name(_)) => event
+            .paths
+            .into_iter()
+            .filter(|path| storage::is_original(path))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The unconverted images stored at `path`, or `None` while it has no row yet
+async fn recorded_images(
+    pool: &SqlitePool,
+    path: &Path,
+) -> anyhow::Result<Option<Vec<SyncedImage>>> {
+    let images = SyncedImage::get_by_original_path(pool, &path.to_string_lossy()).await?;
+    if images.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        images
+            .into_iter()
+            .filter(|image| {
+                image.unsupported.is_none()
+                    && (image.converted_at.is_none() || image.thumbnail_path.is_none())
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,265 @@
+//! Logo or text composited onto converted images, configured by `[watermark]`.
+
+use super::formats;
+use crate::config::{WatermarkConfig, WatermarkPosition};
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use anyhow::{bail, Context, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::sync::OnceLock;
+use tracing::info;
+
+/// Font size text is measured at before being scaled to the configured width
+const MEASURE_PX: f32 = 100.0;
+
+/// Largest share of the image height a mark may cover, for tall logos
+const MAX_HEIGHT_FRACTION: f32 = 0.5;
+
+static WATERMARK: OnceLock<Watermark> = OnceLock::new();
+
+struct Watermark {
+    mark: Mark,
+    position: WatermarkPosition,
+    opacity: f32,
+    scale: f32,
+    margin: f32,
+    thumbnails: bool,
+}
+
+enum Mark {
+    Logo(RgbaImage),
+    Text {
+        font: FontVec,
+        text: String,
+        color: [u8; 3],
+    },
+}
+
+/// Load the configured logo or font. Does nothing when neither `image` nor `text`
+/// is set.
+pub fn init(config: &WatermarkConfig) -> Result<()> {
+    let mark = match (&config.image, &config.text) {
+        (None, None) => return Ok(()),
+        (Some(_), Some(_)) => bail!("Set either watermark.image or watermark.text, not both"),
+        (Some(path), None) => Mark::Logo(
+            formats::reader(path)
+                .map_err(image::ImageError::from)
+                .and_then(|reader| reader.decode())
+                .with_context(|| format!("Failed to open watermark image {:?}", path))?
+                .to_rgba8(),
+        ),
+        (None, Some(text)) => {
+            let path = config
+                .font_path
+                .as_ref()
+                .context("watermark.text needs watermark.font_path")?;
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Failed to read watermark font {:?}", path))?;
+            let font = FontVec::try_from_vec(bytes)
+                .with_context(|| format!("Invalid watermark font {:?}", path))?;
+            Mark::Text {
+                font,
+                text: text.clone(),
+                color: parse_color(&config.color)?,
+            }
+        }
+    };
+    if !(config.scale > 0.0 && config.scale <= 1.0) {
+        bail!("watermark.scale must be above 0 and at most 1");
+    }
+
+    info!("Watermarking converted images ({:?})", config.position);
+    let _ = WATERMARK.set(Watermark {
+        mark,
+        position: config.position,
+        opacity: config.opacity.clamp(0.0, 1.0),
+        scale: config.scale,
+        margin: config.margin.max(0.0),
+        thumbnails: config.thumbnails,
+    });
+    Ok(())
+}
+
+/// Whether images are marked at all; originals must then be re-encoded even when
+/// they could be linked as-is
+pub fn enabled() -> bool {
+    WATERMARK.get().is_some()
+}
+
+/// Mark a full-size image, or return it unchanged when no watermark is configured
+pub fn apply(img: DynamicImage) -> DynamicImage {
+    match WATERMARK.get() {
+        Some(watermark) => watermark.apply(img),
+        None => img,
+    }
+}
+
+/// Mark a thumbnail, if `watermark.thumbnails` asks for it
+pub fn apply_to_thumbnail(img: DynamicImage) -> DynamicImage {
+    match WATERMARK.get() {
+        Some(watermark) if watermark.thumbnails => watermark.apply(img),
+        _ => img,
+    }
+}
+
+impl Watermark {
+    fn apply(&self, img: DynamicImage) -> DynamicImage {
+        let (width, height) = (img.width(), img.height());
+        let Some(overlay) = self.render(width, height) else {
+            return img;
+        };
+
+        let margin = (self.margin * width.min(height) as f32).round() as u32;
+        let (x, y) = self.origin(width, height, overlay.dimensions(), margin);
+
+        let has_alpha = img.color().has_alpha();
+        let mut base = img.into_rgba8();
+        for (ox, oy, pixel) in overlay.enumerate_pixels() {
+            let (bx, by) = (x + ox, y + oy);
+            if bx >= width || by >= height {
+                continue;
+            }
+            let alpha = pixel[3] as f32 / 255.0 * self.opacity;
+            if alpha <= 0.0 {
+                continue;
+            }
+            let under = base.get_pixel_mut(bx, by);
+            for c in 0..3 {
+                under[c] =
+                    (pixel[c] as f32 * alpha + under[c] as f32 * (1.0 - alpha)).round() as u8;
+            }
+            under[3] = (255.0 * alpha + under[3] as f32 * (1.0 - alpha)).round() as u8;
+        }
+
+        let marked = DynamicImage::ImageRgba8(base);
+        if has_alpha {
+            marked
+        } else {
+            DynamicImage::ImageRgb8(marked.into_rgb8())
+        }
+    }
+
+    /// The mark sized for an image of `width` x `height`, or `None` if it would be
+    /// too small to draw
+    fn render(&self, width: u32, height: u32) -> Option<RgbaImage> {
+        let target_width = self.scale * width as f32;
+        let max_height = MAX_HEIGHT_FRACTION * height as f32;
+        match &self.mark {
+            Mark::Logo(logo) => {
+                let factor =
+                    (target_width / logo.width() as f32).min(max_height / logo.height() as f32);
+                let w = (logo.width() as f32 * factor).round() as u32;
+                let h = (logo.height() as f32 * factor).round() as u32;
+                if w == 0 || h == 0 {
+                    return None;
+                }
+                Some(image::imageops::resize(logo, w, h, FilterType::Lanczos3))
+            }
+            Mark::Text { font, text, color } => {
+                let measured = text_width(font, text, MEASURE_PX);
+                if measured <= 0.0 {
+                    return None;
+                }
+                let line_height = font.as_scaled(MEASURE_PX).height();
+                let px = (MEASURE_PX * target_width / measured)
+                    .min(MEASURE_PX * max_height / line_height);
+                if px < 1.0 {
+                    return None;
+                }
+                Some(render_text(font, text, px, *color))
+            }
+        }
+    }
+
+    /// Top-left corner of a mark of `size` placed at the configured position
+    fn origin(&self, width: u32, height: u32, size: (u32, u32), margin: u32) -> (u32, u32) {
+        use WatermarkPosition::*;
+        // 0 = start, 1 = middle, 2 = end, along each axis
+        let (column, row) = match self.position {
+            TopLeft => (0, 0),
+            Top => (1, 0),
+            TopRight => (2, 0),
+            Left => (0, 1),
+            Center => (1, 1),
+            Right => (2, 1),
+            BottomLeft => (0, 2),
+            Bottom => (1, 2),
+            BottomRight => (2, 2),
+        };
+        let place = |align: u8, outer: u32, inner: u32| match align {
+            0 => margin,
+            1 => outer.saturating_sub(inner) / 2,
+            _ => outer.saturating_sub(inner + margin),
+        };
+        (place(column, width, size.0), place(row, height, size.1))
+    }
+}
+
+/// Advance width of a single line of text at `px`
+fn text_width(font: &FontVec, text: &str, px: f32) -> f32 {
+    let font = font.as_scaled(PxScale::from(px));
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let glyph = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, glyph);
+        }
+        width += font.h_advance(glyph);
+        previous = Some(glyph);
+    }
+    width
+}
+
+/// Draw one line of text in `color`, with coverage as alpha
+fn render_text(font: &FontVec, text: &str, px: f32, color: [u8; 3]) -> RgbaImage {
+    let scaled = font.as_scaled(PxScale::from(px));
+    let width = text_width(font, text, px).ceil() as u32;
+    let height = scaled.height().ceil() as u32;
+    let mut canvas = RgbaImage::from_pixel(
+        width.max(1),
+        height.max(1),
+        Rgba([color[0], color[1], color[2], 0]),
+    );
+
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(px, ab_glyph::point(caret, scaled.ascent()));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let x = bounds.min.x as i32 + gx as i32;
+            let y = bounds.min.y as i32 + gy as i32;
+            if x < 0 || y < 0 || x as u32 >= canvas.width() || y as u32 >= canvas.height() {
+                return;
+            }
+            let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+            let alpha = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+            pixel[3] = pixel[3].max(alpha);
+        });
+    }
+    canvas
+}
+
+/// `#rrggbb` (the `#` is optional) as RGB
+fn parse_color(value: &str) -> Result<[u8; 3]> {
+    let hex = value.trim().trim_start_matches('#');
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => bail!("Invalid watermark color '{}', expected #rrggbb", value),
+    }
+}
@@ -0,0 +1,169 @@
+use super::models::{Album, SyncedImage};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+
+/// Bumped when a change to the rows would make older exports import wrongly
+const FORMAT_VERSION: u32 = 1;
+
+/// Every row of the database, as written by `export` and read by `import`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub format_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub albums: Vec<Album>,
+    pub images: Vec<SyncedImage>,
+    /// Which albums each image is listed in
+    pub album_members: Vec<AlbumMember>,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct AlbumMember {
+    pub image_id: String,
+    pub album_id: String,
+}
+
+/// Result of the `export` and `import` commands
+#[derive(Debug, Serialize)]
+pub struct BackupResult {
+    pub albums: usize,
+    pub images: usize,
+    pub album_members: usize,
+}
+
+impl Backup {
+    pub fn counts(&self) -> BackupResult {
+        BackupResult {
+            albums: self.albums.len(),
+            images: self.images.len(),
+            album_members: self.album_members.len(),
+        }
+    }
+}
+
+pub async fn export(pool: &SqlitePool) -> Result<Backup> {
+    let albums = sqlx::query_as::<_, Album>("SELECT * FROM albums ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    let images = sqlx::query_as::<_, SyncedImage>("SELECT * FROM synced_images ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    let album_members = sqlx::query_as::<_, AlbumMember>(
+        "SELECT image_id, album_id FROM image_albums ORDER BY album_id, image_id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Backup {
+        format_version: FORMAT_VERSION,
+        exported_at: Utc::now(),
+        albums,
+        images,
+        album_members,
+    })
+}
+
+/// Upsert every row of `backup` in one transaction, overwriting rows with the same
+/// ID and leaving all others alone. Timestamps are stored in SQLite's own
+/// `YYYY-MM-DD HH:MM:SS` form, as `datetime('now')` writes them elsewhere.
+pub async fn import(pool: &SqlitePool, backup: &Backup) -> Result<()> {
+    if backup.format_version != FORMAT_VERSION {
+        anyhow::bail!(
+            "Unsupported export format version {} (expected {})",
+            backup.format_version,
+            FORMAT_VERSION
+        );
+    }
+
+    let mut tx = pool.begin().await?;
+
+    for album in &backup.albums {
+        sqlx::query(
+            r#"
+            INSERT INTO albums (id, name, asset_count, last_sync, cover_image_id)
+            VALUES (?, ?, ?, datetime(?, 'unixepoch'), ?)
+            ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                asset_count = excluded.asset_count,
+                last_sync = excluded.last_sync,
+                cover_image_id = excluded.cover_image_id
+            "#,
+        )
+        .bind(&album.id)
+        .bind(&album.name)
+        .bind(album.asset_count)
+        .bind(album.last_sync.map(|t| t.timestamp()))
+        .bind(&album.cover_image_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    for image in &backup.images {
+        sqlx::query(
+            r#"
+            INSERT INTO synced_images (
+                id, album_id, filename, checksum, original_path, avif_path, thumbnail_path,
+                file_size, synced_at, converted_at, conversion_error, conversion_attempts,
+                placeholder, dominant_color, lqip, avif_size, taken_at, mime_type, unsupported,
+                variants
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, datetime(?, 'unixepoch'), datetime(?, 'unixepoch'),
+                ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                album_id = excluded.album_id,
+                filename = excluded.filename,
+                checksum = excluded.checksum,
+                original_path = excluded.original_path,
+                avif_path = excluded.avif_path,
+                thumbnail_path = excluded.thumbnail_path,
+                file_size = excluded.file_size,
+                synced_at = excluded.synced_at,
+                converted_at = excluded.converted_at,
+                conversion_error = excluded.conversion_error,
+                conversion_attempts = excluded.conversion_attempts,
+                placeholder = excluded.placeholder,
+                dominant_color = excluded.dominant_color,
+                lqip = excluded.lqip,
+                avif_size = excluded.avif_size,
+                taken_at = excluded.taken_at,
+                mime_type = excluded.mime_type,
+                unsupported = excluded.unsupported,
+                variants = excluded.variants
+            "#,
+        )
+        .bind(&image.id)
+        .bind(&image.album_id)
+        .bind(&image.filename)
+        .bind(&image.checksum)
+        .bind(&image.original_path)
+        .bind(&image.avif_path)
+        .bind(&image.thumbnail_path)
+        .bind(image.file_size)
+        .bind(image.synced_at.map(|t| t.timestamp()))
+        .bind(image.converted_at.map(|t| t.timestamp()))
+        .bind(&image.conversion_error)
+        .bind(image.conversion_attempts)
+        .bind(&image.placeholder)
+        .bind(&image.dominant_color)
+        .bind(&image.lqip)
+        .bind(image.avif_size)
+        .bind(image.taken_at)
+        .bind(&image.mime_type)
+        .bind(&image.unsupported)
+        .bind(&image.variants)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    for member in &backup.album_members {
+        sqlx::query("INSERT OR IGNORE INTO image_albums (image_id, album_id) VALUES (?, ?)")
+            .bind(&member.image_id)
+            .bind(&member.album_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
@@ -1,14 +1,29 @@
+pub mod backup;
 pub mod models;
 
+use crate::config::JournalMode;
 use anyhow::Result;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
 use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
-pub async fn create_pool(db_path: &Path) -> Result<SqlitePool> {
+pub async fn create_pool(
+    db_path: &Path,
+    journal_mode: JournalMode,
+    busy_timeout: Duration,
+) -> Result<SqlitePool> {
     let db_url = format!("sqlite:{}?mode=rwc", db_path.display());
 
-    let options = SqliteConnectOptions::from_str(&db_url)?.create_if_missing(true);
+    let options = SqliteConnectOptions::from_str(&db_url)?
+        .create_if_missing(true)
+        .journal_mode(match journal_mode {
+            JournalMode::Wal => SqliteJournalMode::Wal,
+            JournalMode::Delete => SqliteJournalMode::Delete,
+            JournalMode::Truncate => SqliteJournalMode::Truncate,
+            JournalMode::Persist => SqliteJournalMode::Persist,
+        })
+        .busy_timeout(busy_timeout);
 
     let pool = SqlitePoolOptions::new()
         .max_connections(5)
@@ -27,7 +42,8 @@ async fn run_migrations(pool: &SqlitePool) -> Result<()> {
             id TEXT PRIMARY KEY,
             name TEXT NOT NULL,
             asset_count INTEGER DEFAULT 0,
-            last_sync DATETIME
+            last_sync DATETIME,
+            cover_image_id TEXT
         )
         "#,
     )
@@ -47,6 +63,14 @@ async fn run_migrations(pool: &SqlitePool) -> Result<()> {
             file_size INTEGER,
             synced_at DATETIME,
             converted_at DATETIME,
+            conversion_error TEXT,
+            conversion_attempts INTEGER NOT NULL DEFAULT 0,
+            placeholder TEXT,
+            dominant_color TEXT,
+            avif_size INTEGER,
+            taken_at DATETIME,
+            mime_type TEXT,
+            unsupported TEXT,
             FOREIGN KEY (album_id) REFERENCES albums(id)
         )
         "#,
@@ -64,6 +88,220 @@ async fn run_migrations(pool: &SqlitePool) -> Result<()> {
     .await
     .ok(); // Ignore error if column already exists
 
+    // Migration: add conversion failure tracking columns if they don't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN conversion_error TEXT
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN conversion_attempts INTEGER NOT NULL DEFAULT 0
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: add placeholder hash column if it doesn't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN placeholder TEXT
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: add dominant color column if it doesn't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN dominant_color TEXT
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: add LQIP data URI column if it doesn't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN lqip TEXT
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: add quality variants column if it doesn't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN variants TEXT
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: add AVIF size column if it doesn't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN avif_size INTEGER
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: add capture time column if it doesn't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN taken_at DATETIME
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: add MIME type and unsupported status columns if they don't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN mime_type TEXT
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN unsupported TEXT
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: add eviction time column if it doesn't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN evicted_at DATETIME
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Migration: images converted before capture times were recorded are flagged,
+    // and the next conversion pass reads theirs
+    let taken_at_pending_added = sqlx::query(
+        r#"
+        ALTER TABLE synced_images ADD COLUMN taken_at_pending INTEGER NOT NULL DEFAULT 0
+        "#,
+    )
+    .execute(pool)
+    .await
+    .is_ok();
+    if taken_at_pending_added {
+        sqlx::query(
+            r#"
+            UPDATE synced_images SET taken_at_pending = 1
+            WHERE converted_at IS NOT NULL AND taken_at IS NULL
+            "#,
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    // Migration: add album cover column if it doesn't exist
+    sqlx::query(
+        r#"
+        ALTER TABLE albums ADD COLUMN cover_image_id TEXT
+        "#,
+    )
+    .execute(pool)
+    .await
+    .ok();
+
+    // Album membership: an image can be in several albums, while `album_id` stays
+    // the album whose directory holds its files
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS image_albums (
+            image_id TEXT NOT NULL,
+            album_id TEXT NOT NULL,
+            PRIMARY KEY (image_id, album_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Migration: every existing image is a member of its own album
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO image_albums (image_id, album_id)
+        SELECT id, album_id FROM synced_images
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // One row per conversion attempt, for `GET /images/:id/history`
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS conversion_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            image_id TEXT NOT NULL,
+            logged_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            outcome TEXT NOT NULL,
+            input_size INTEGER,
+            output_size INTEGER,
+            quality REAL,
+            thumbnail_quality REAL,
+            duration_ms INTEGER NOT NULL,
+            error TEXT
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_conversion_log_image
+        ON conversion_log(image_id, id)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Files of removed images moved to the trash with `removal_mode = "trash"`
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS trash (
+            path TEXT PRIMARY KEY,
+            original_location TEXT NOT NULL,
+            image_id TEXT NOT NULL,
+            trashed_at DATETIME NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_image_albums_album
+        ON image_albums(album_id)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
     sqlx::query(
         r#"
         CREATE INDEX IF NOT EXISTS idx_synced_images_album
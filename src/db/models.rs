@@ -1,6 +1,8 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct Album {
@@ -8,6 +10,8 @@ pub struct Album {
     pub name: String,
     pub asset_count: Option<i64>,
     pub last_sync: Option<DateTime<Utc>>,
+    /// Immich's album thumbnail asset, used as the cover when it has been converted
+    pub cover_image_id: Option<String>,
 }
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
@@ -22,6 +26,309 @@ pub struct SyncedImage {
     pub file_size: Option<i64>,
     pub synced_at: Option<DateTime<Utc>>,
     pub converted_at: Option<DateTime<Utc>>,
+    pub conversion_error: Option<String>,
+    pub conversion_attempts: i64,
+    pub placeholder: Option<String>,
+    /// Average color of the thumbnail as `#rrggbb`
+    pub dominant_color: Option<String>,
+    /// Tiny JPEG of the thumbnail as a `data:` URI
+    pub lqip: Option<String>,
+    /// Size of the full AVIF in bytes
+    pub avif_size: Option<i64>,
+    /// EXIF `DateTimeOriginal` of the original, in the camera's local time
+    pub taken_at: Option<NaiveDateTime>,
+    /// MIME type of the original as reported by Immich
+    pub mime_type: Option<String>,
+    /// Why the original is not converted, e.g. `"image/x-sony-arw: no decoder in this build"`
+    pub unsupported: Option<String>,
+    /// JSON object mapping `image.variants` names to their AVIF paths
+    pub variants: Option<String>,
+    /// When the storage limit deleted the image's files, which keeps
+    /// `convert --fetch-missing` from downloading them again
+    pub evicted_at: Option<DateTime<Utc>>,
+}
+
+/// Image totals for the `status` command
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct ImageCounts {
+    pub images: i64,
+    pub downloaded: i64,
+    pub converted: i64,
+    /// Downloaded but not yet (fully) converted, as `get_unconverted` sees them
+    pub pending: i64,
+    pub failed: i64,
+    /// Downloaded originals of a type that is not converted
+    pub unsupported: i64,
+}
+
+/// Size totals for `GET /stats`, over all images or a single album
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct SizeStats {
+    pub images: i64,
+    pub converted: i64,
+    pub original_bytes: i64,
+    pub avif_bytes: i64,
+    /// AVIF bytes over original bytes, counting only images with a recorded AVIF size
+    pub compression_ratio: Option<f64>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct AlbumSizeStats {
+    pub album_id: String,
+    pub album_name: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub stats: SizeStats,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ConversionStats {
+    #[serde(flatten)]
+    pub total: SizeStats,
+    pub albums: Vec<AlbumSizeStats>,
+}
+
+/// One conversion attempt of an image, newest first in `GET /images/:id/history`
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct ConversionLogEntry {
+    pub logged_at: DateTime<Utc>,
+    /// `converted`, `reused` (the output of an identical image) or `failed`
+    pub outcome: String,
+    /// Size of the original in bytes
+    pub input_size: Option<i64>,
+    /// Size of the full AVIF in bytes
+    pub output_size: Option<i64>,
+    /// Quality of the full AVIF; unset when the original was linked as-is or reused
+    pub quality: Option<f64>,
+    pub thumbnail_quality: Option<f64>,
+    pub duration_ms: i64,
+    pub error: Option<String>,
+}
+
+/// A `conversion_log` row to insert
+#[derive(Debug, Clone)]
+pub struct NewConversionLogEntry<'a> {
+    pub image_id: &'a str,
+    pub outcome: &'a str,
+    pub input_size: Option<i64>,
+    pub output_size: Option<i64>,
+    pub quality: Option<f32>,
+    pub thumbnail_quality: Option<f32>,
+    pub duration_ms: i64,
+    pub error: Option<&'a str>,
+}
+
+/// Most history rows kept per image; older ones are dropped as new ones come in
+const HISTORY_PER_IMAGE: i64 = 100;
+
+impl ConversionLogEntry {
+    pub async fn insert(
+        pool: &sqlx::SqlitePool,
+        entry: &NewConversionLogEntry<'_>,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO conversion_log (
+                image_id, outcome, input_size, output_size, quality, thumbnail_quality,
+                duration_ms, error
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(entry.image_id)
+        .bind(entry.outcome)
+        .bind(entry.input_size)
+        .bind(entry.output_size)
+        .bind(entry.quality)
+        .bind(entry.thumbnail_quality)
+        .bind(entry.duration_ms)
+        .bind(entry.error)
+        .execute(pool)
+        .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM conversion_log
+            WHERE image_id = ? AND id NOT IN (
+                SELECT id FROM conversion_log WHERE image_id = ? ORDER BY id DESC LIMIT ?
+            )
+            "#,
+        )
+        .bind(entry.image_id)
+        .bind(entry.image_id)
+        .bind(HISTORY_PER_IMAGE)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// An image's conversion attempts, newest first
+    pub async fn get_by_image(
+        pool: &sqlx::SqlitePool,
+        image_id: &str,
+    ) -> anyhow::Result<Vec<ConversionLogEntry>> {
+        let entries = sqlx::query_as::<_, ConversionLogEntry>(
+            r#"
+            SELECT logged_at, outcome, input_size, output_size, quality, thumbnail_quality,
+                duration_ms, error
+            FROM conversion_log
+            WHERE image_id = ?
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(image_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(entries)
+    }
+}
+
+/// A file of a removed image waiting in the trash
+#[derive(Debug, Clone, FromRow)]
+pub struct TrashedFile {
+    /// Where the file is now, under `trash_path()`
+    pub path: String,
+    /// Where it was before it was removed
+    pub original_location: String,
+    pub image_id: String,
+    pub trashed_at: DateTime<Utc>,
+}
+
+impl TrashedFile {
+    /// Record a file moved to `path`; trashing the same location again restarts
+    /// its retention period
+    pub async fn insert(
+        pool: &sqlx::SqlitePool,
+        path: &str,
+        original_location: &str,
+        image_id: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO trash (path, original_location, image_id, trashed_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(path) DO UPDATE SET
+                original_location = excluded.original_location,
+                image_id = excluded.image_id,
+                trashed_at = excluded.trashed_at
+            "#,
+        )
+        .bind(path)
+        .bind(original_location)
+        .bind(image_id)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Files trashed before `cutoff`, or all of them without one
+    pub async fn get_before(
+        pool: &sqlx::SqlitePool,
+        cutoff: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<TrashedFile>> {
+        let files = sqlx::query_as::<_, TrashedFile>(
+            r#"
+            SELECT path, original_location, image_id, trashed_at
+            FROM trash
+            WHERE ? IS NULL OR trashed_at < ?
+            ORDER BY trashed_at
+            "#,
+        )
+        .bind(cutoff)
+        .bind(cutoff)
+        .fetch_all(pool)
+        .await?;
+        Ok(files)
+    }
+
+    pub async fn delete(pool: &sqlx::SqlitePool, path: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM trash WHERE path = ?")
+            .bind(path)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Sync freshness and conversion progress of one album
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct AlbumProgress {
+    pub album_id: String,
+    pub album_name: String,
+    /// Assets Immich reported for the album at the last sync
+    pub asset_count: Option<i64>,
+    pub last_sync: Option<DateTime<Utc>>,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub images: ImageCounts,
+}
+
+/// Sort keys accepted by `Album::get_all_sorted`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AlbumSort {
+    #[default]
+    Name,
+    Count,
+    LastSync,
+}
+
+impl AlbumSort {
+    fn column(self) -> &'static str {
+        match self {
+            AlbumSort::Name => "name",
+            AlbumSort::Count => "asset_count",
+            AlbumSort::LastSync => "last_sync",
+        }
+    }
+}
+
+impl std::str::FromStr for AlbumSort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "name" => Ok(AlbumSort::Name),
+            "count" => Ok(AlbumSort::Count),
+            "last_sync" => Ok(AlbumSort::LastSync),
+            other => Err(anyhow::anyhow!(
+                "Unknown sort key '{}' (expected name, count or last_sync)",
+                other
+            )),
+        }
+    }
+}
+
+/// Orders accepted by `SyncedImage::get_by_album_paginated`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ImageSort {
+    #[default]
+    Filename,
+    /// Capture time, images without one last by filename
+    TakenAt,
+}
+
+impl ImageSort {
+    fn order_by(self) -> &'static str {
+        match self {
+            ImageSort::Filename => "filename, synced_images.id",
+            ImageSort::TakenAt => "taken_at IS NULL, taken_at, filename, synced_images.id",
+        }
+    }
+}
+
+impl std::str::FromStr for ImageSort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "filename" => Ok(ImageSort::Filename),
+            "taken_at" => Ok(ImageSort::TakenAt),
+            other => Err(anyhow::anyhow!(
+                "Unknown sort key '{}' (expected filename or taken_at)",
+                other
+            )),
+        }
+    }
 }
 
 impl Album {
@@ -30,25 +337,41 @@ impl Album {
         id: &str,
         name: &str,
         asset_count: i64,
+        cover_image_id: Option<&str>,
     ) -> anyhow::Result<()> {
         sqlx::query(
             r#"
-            INSERT INTO albums (id, name, asset_count, last_sync)
-            VALUES (?, ?, ?, datetime('now'))
+            INSERT INTO albums (id, name, asset_count, cover_image_id)
+            VALUES (?, ?, ?, ?)
             ON CONFLICT(id) DO UPDATE SET
                 name = excluded.name,
                 asset_count = excluded.asset_count,
-                last_sync = datetime('now')
+                cover_image_id = excluded.cover_image_id
             "#,
         )
         .bind(id)
         .bind(name)
         .bind(asset_count)
+        .bind(cover_image_id)
         .execute(pool)
         .await?;
         Ok(())
     }
 
+    /// Record a completed sync; `at` is when its asset listing was requested
+    pub async fn set_last_sync(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE albums SET last_sync = datetime(?, 'unixepoch') WHERE id = ?")
+            .bind(at.timestamp())
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn get_all(pool: &sqlx::SqlitePool) -> anyhow::Result<Vec<Album>> {
         let albums = sqlx::query_as::<_, Album>("SELECT * FROM albums ORDER BY name")
             .fetch_all(pool)
@@ -56,6 +379,53 @@ impl Album {
         Ok(albums)
     }
 
+    /// All albums ordered by `sort`, optionally limited to names containing `name_filter`
+    pub async fn get_all_sorted(
+        pool: &sqlx::SqlitePool,
+        sort: AlbumSort,
+        descending: bool,
+        name_filter: Option<&str>,
+    ) -> anyhow::Result<Vec<Album>> {
+        // The column comes from a closed enum, never from user input
+        let sql = format!(
+            "SELECT * FROM albums WHERE (? IS NULL OR name LIKE ? ESCAPE '\\') ORDER BY {} {}, name, id",
+            sort.column(),
+            if descending { "DESC" } else { "ASC" }
+        );
+        let pattern = name_filter.map(like_pattern);
+
+        let albums = sqlx::query_as::<_, Album>(&sql)
+            .bind(&pattern)
+            .bind(&pattern)
+            .fetch_all(pool)
+            .await?;
+        Ok(albums)
+    }
+
+    /// Image counts by conversion state for every album, in one query
+    pub async fn get_progress(pool: &sqlx::SqlitePool) -> anyhow::Result<Vec<AlbumProgress>> {
+        let albums = sqlx::query_as::<_, AlbumProgress>(
+            r#"
+            SELECT a.id AS album_id, a.name AS album_name, a.asset_count, a.last_sync,
+                COUNT(i.id) AS images,
+                COUNT(i.original_path) AS downloaded,
+                COALESCE(SUM(i.converted_at IS NOT NULL AND i.thumbnail_path IS NOT NULL), 0) AS converted,
+                COALESCE(SUM(i.original_path IS NOT NULL AND i.unsupported IS NULL
+                    AND (i.converted_at IS NULL OR i.thumbnail_path IS NULL)), 0) AS pending,
+                COALESCE(SUM(i.conversion_error IS NOT NULL), 0) AS failed,
+                COALESCE(SUM(i.unsupported IS NOT NULL), 0) AS unsupported
+            FROM albums a
+            LEFT JOIN image_albums m ON m.album_id = a.id
+            LEFT JOIN synced_images i ON i.id = m.image_id
+            GROUP BY a.id
+            ORDER BY a.name, a.id
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(albums)
+    }
+
     pub async fn get_by_id(pool: &sqlx::SqlitePool, id: &str) -> anyhow::Result<Option<Album>> {
         let album = sqlx::query_as::<_, Album>("SELECT * FROM albums WHERE id = ?")
             .bind(id)
@@ -65,6 +435,10 @@ impl Album {
     }
 
     pub async fn delete_by_id(pool: &sqlx::SqlitePool, id: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM image_albums WHERE album_id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
         sqlx::query("DELETE FROM albums WHERE id = ?")
             .bind(id)
             .execute(pool)
@@ -73,38 +447,153 @@ impl Album {
     }
 }
 
+/// `%query%` with LIKE wildcards in `query` escaped, for use with `ESCAPE '\'`
+fn like_pattern(query: &str) -> String {
+    format!("%{}%", escape_like(query))
+}
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// A downloaded original, as written by `SyncedImage::upsert_many`
+#[derive(Debug, Clone)]
+pub struct NewSyncedImage {
+    pub id: String,
+    pub album_id: String,
+    pub filename: String,
+    pub checksum: Option<String>,
+    pub original_path: Option<String>,
+    pub file_size: Option<i64>,
+    pub mime_type: Option<String>,
+    /// When the download finished, which may be well before the row is written
+    pub synced_at: DateTime<Utc>,
+}
+
 impl SyncedImage {
-    pub async fn upsert(
+    /// AVIF paths of the image's variants by name; empty when none were made
+    pub fn variant_paths(&self) -> BTreeMap<String, String> {
+        self.variants
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Insert or update downloaded originals and their album membership, all in one
+    /// transaction
+    pub async fn upsert_many(
+        pool: &sqlx::SqlitePool,
+        images: &[NewSyncedImage],
+    ) -> anyhow::Result<()> {
+        let mut tx = pool.begin().await?;
+        for image in images {
+            sqlx::query(
+                r#"
+                INSERT INTO synced_images
+                    (id, album_id, filename, checksum, original_path, file_size, mime_type, synced_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, datetime(?, 'unixepoch'))
+                ON CONFLICT(id) DO UPDATE SET
+                    unsupported = CASE
+                        WHEN checksum IS excluded.checksum AND mime_type IS excluded.mime_type
+                        THEN unsupported
+                    END,
+                    album_id = excluded.album_id,
+                    filename = excluded.filename,
+                    checksum = excluded.checksum,
+                    original_path = excluded.original_path,
+                    file_size = excluded.file_size,
+                    mime_type = excluded.mime_type,
+                    synced_at = excluded.synced_at,
+                    evicted_at = NULL
+                "#,
+            )
+            .bind(&image.id)
+            .bind(&image.album_id)
+            .bind(&image.filename)
+            .bind(&image.checksum)
+            .bind(&image.original_path)
+            .bind(image.file_size)
+            .bind(&image.mime_type)
+            .bind(image.synced_at.timestamp())
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("INSERT OR IGNORE INTO image_albums (image_id, album_id) VALUES (?, ?)")
+                .bind(&image.id)
+                .bind(&image.album_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record that the images are in `album_id`, in addition to any other albums
+    pub async fn add_album_members(
         pool: &sqlx::SqlitePool,
-        id: &str,
         album_id: &str,
-        filename: &str,
-        checksum: Option<&str>,
-        original_path: Option<&str>,
-        file_size: Option<i64>,
+        image_ids: &[String],
     ) -> anyhow::Result<()> {
-        sqlx::query(
+        let mut tx = pool.begin().await?;
+        for image_id in image_ids {
+            sqlx::query("INSERT OR IGNORE INTO image_albums (image_id, album_id) VALUES (?, ?)")
+                .bind(image_id)
+                .bind(album_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Other images with the same content, converted ones first
+    pub async fn get_by_checksum(
+        pool: &sqlx::SqlitePool,
+        checksum: &str,
+        exclude_id: &str,
+    ) -> anyhow::Result<Vec<SyncedImage>> {
+        let images = sqlx::query_as::<_, SyncedImage>(
             r#"
-            INSERT INTO synced_images (id, album_id, filename, checksum, original_path, file_size, synced_at)
-            VALUES (?, ?, ?, ?, ?, ?, datetime('now'))
-            ON CONFLICT(id) DO UPDATE SET
-                album_id = excluded.album_id,
-                filename = excluded.filename,
-                checksum = excluded.checksum,
-                original_path = excluded.original_path,
-                file_size = excluded.file_size,
-                synced_at = datetime('now')
+            SELECT * FROM synced_images
+            WHERE checksum = ? AND id != ? AND original_path IS NOT NULL
+            ORDER BY converted_at IS NULL, id
             "#,
         )
+        .bind(checksum)
+        .bind(exclude_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(images)
+    }
+
+    /// Update the name and checksum of an already synced image, leaving its files
+    /// alone. New content is given another chance if it was unsupported. Returns
+    /// whether anything changed.
+    pub async fn update_metadata(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        filename: &str,
+        checksum: &str,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE synced_images
+            SET filename = ?, checksum = ?,
+                unsupported = CASE WHEN checksum IS ? THEN unsupported END
+            WHERE id = ? AND (filename != ? OR checksum IS NOT ?)
+            "#,
+        )
+        .bind(filename)
+        .bind(checksum)
+        .bind(checksum)
         .bind(id)
-        .bind(album_id)
         .bind(filename)
         .bind(checksum)
-        .bind(original_path)
-        .bind(file_size)
         .execute(pool)
         .await?;
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
     pub async fn mark_converted(
@@ -116,7 +605,8 @@ impl SyncedImage {
         sqlx::query(
             r#"
             UPDATE synced_images
-            SET avif_path = ?, thumbnail_path = ?, converted_at = datetime('now')
+            SET avif_path = ?, thumbnail_path = ?, converted_at = datetime('now'),
+                conversion_error = NULL, unsupported = NULL
             WHERE id = ?
             "#,
         )
@@ -128,6 +618,105 @@ impl SyncedImage {
         Ok(())
     }
 
+    /// Record a failed conversion attempt and its error message
+    pub async fn mark_conversion_failed(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        error: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE synced_images
+            SET conversion_error = ?, conversion_attempts = conversion_attempts + 1
+            WHERE id = ?
+            "#,
+        )
+        .bind(error)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_failed(
+        pool: &sqlx::SqlitePool,
+        offset: i64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<SyncedImage>> {
+        let images = sqlx::query_as::<_, SyncedImage>(
+            "SELECT * FROM synced_images WHERE conversion_error IS NOT NULL ORDER BY id LIMIT ? OFFSET ?",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(images)
+    }
+
+    pub async fn count_failed(pool: &sqlx::SqlitePool) -> anyhow::Result<i64> {
+        let count: (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM synced_images WHERE conversion_error IS NOT NULL")
+                .fetch_one(pool)
+                .await?;
+        Ok(count.0)
+    }
+
+    pub async fn counts(pool: &sqlx::SqlitePool) -> anyhow::Result<ImageCounts> {
+        let counts = sqlx::query_as::<_, ImageCounts>(
+            r#"
+            SELECT COUNT(*) AS images,
+                COUNT(original_path) AS downloaded,
+                COALESCE(SUM(converted_at IS NOT NULL AND thumbnail_path IS NOT NULL), 0) AS converted,
+                COALESCE(SUM(original_path IS NOT NULL AND unsupported IS NULL
+                    AND (converted_at IS NULL OR thumbnail_path IS NULL)), 0) AS pending,
+                COALESCE(SUM(conversion_error IS NOT NULL), 0) AS failed,
+                COALESCE(SUM(unsupported IS NOT NULL), 0) AS unsupported
+            FROM synced_images
+            "#,
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(counts)
+    }
+
+    /// Original and AVIF size totals, overall and per album
+    pub async fn stats(pool: &sqlx::SqlitePool) -> anyhow::Result<ConversionStats> {
+        let total = sqlx::query_as::<_, SizeStats>(
+            r#"
+            SELECT COUNT(*) AS images,
+                COALESCE(SUM(converted_at IS NOT NULL AND thumbnail_path IS NOT NULL), 0) AS converted,
+                COALESCE(SUM(file_size), 0) AS original_bytes,
+                COALESCE(SUM(avif_size), 0) AS avif_bytes,
+                CAST(SUM(avif_size) AS REAL)
+                    / SUM(CASE WHEN avif_size IS NOT NULL THEN file_size END) AS compression_ratio
+            FROM synced_images
+            "#,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let albums = sqlx::query_as::<_, AlbumSizeStats>(
+            r#"
+            SELECT a.id AS album_id, a.name AS album_name,
+                COUNT(i.id) AS images,
+                COALESCE(SUM(i.converted_at IS NOT NULL AND i.thumbnail_path IS NOT NULL), 0) AS converted,
+                COALESCE(SUM(i.file_size), 0) AS original_bytes,
+                COALESCE(SUM(i.avif_size), 0) AS avif_bytes,
+                CAST(SUM(i.avif_size) AS REAL)
+                    / SUM(CASE WHEN i.avif_size IS NOT NULL THEN i.file_size END) AS compression_ratio
+            FROM albums a
+            LEFT JOIN image_albums m ON m.album_id = a.id
+            LEFT JOIN synced_images i ON i.id = m.image_id
+            GROUP BY a.id
+            ORDER BY a.name
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(ConversionStats { total, albums })
+    }
+
     pub async fn get_by_id(
         pool: &sqlx::SqlitePool,
         id: &str,
@@ -139,12 +728,61 @@ impl SyncedImage {
         Ok(image)
     }
 
+    /// Images whose original is stored at `path`
+    pub async fn get_by_original_path(
+        pool: &sqlx::SqlitePool,
+        path: &str,
+    ) -> anyhow::Result<Vec<SyncedImage>> {
+        let images =
+            sqlx::query_as::<_, SyncedImage>("SELECT * FROM synced_images WHERE original_path = ?")
+                .bind(path)
+                .fetch_all(pool)
+                .await?;
+        Ok(images)
+    }
+
+    /// Images converted before capture times were recorded, still waiting for theirs
+    pub async fn get_taken_at_pending(pool: &sqlx::SqlitePool) -> anyhow::Result<Vec<SyncedImage>> {
+        let images = sqlx::query_as::<_, SyncedImage>(
+            "SELECT * FROM synced_images WHERE taken_at_pending = 1",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(images)
+    }
+
+    /// Pick one converted image at random, optionally restricted to an album
+    pub async fn get_random(
+        pool: &sqlx::SqlitePool,
+        album_id: Option<&str>,
+    ) -> anyhow::Result<Option<SyncedImage>> {
+        let image = sqlx::query_as::<_, SyncedImage>(
+            r#"
+            SELECT * FROM synced_images
+            WHERE avif_path IS NOT NULL
+                AND (? IS NULL OR id IN (SELECT image_id FROM image_albums WHERE album_id = ?))
+            ORDER BY RANDOM() LIMIT 1
+            "#,
+        )
+        .bind(album_id)
+        .bind(album_id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(image)
+    }
+
+    /// Images listed in an album, including those first synced through another
     pub async fn get_by_album(
         pool: &sqlx::SqlitePool,
         album_id: &str,
     ) -> anyhow::Result<Vec<SyncedImage>> {
         let images = sqlx::query_as::<_, SyncedImage>(
-            "SELECT * FROM synced_images WHERE album_id = ? ORDER BY filename",
+            r#"
+            SELECT synced_images.* FROM synced_images
+            JOIN image_albums ON image_albums.image_id = synced_images.id
+            WHERE image_albums.album_id = ?
+            ORDER BY synced_images.filename
+            "#,
         )
         .bind(album_id)
         .fetch_all(pool)
@@ -152,16 +790,156 @@ impl SyncedImage {
         Ok(images)
     }
 
+    /// Converted images listed in an album, including those first synced through
+    /// another album
+    pub async fn get_converted_by_album(
+        pool: &sqlx::SqlitePool,
+        album_id: &str,
+    ) -> anyhow::Result<Vec<SyncedImage>> {
+        let images = sqlx::query_as::<_, SyncedImage>(
+            r#"
+            SELECT synced_images.* FROM synced_images
+            JOIN image_albums ON image_albums.image_id = synced_images.id
+            WHERE image_albums.album_id = ?
+                AND synced_images.avif_path IS NOT NULL
+                AND synced_images.thumbnail_path IS NOT NULL
+            ORDER BY synced_images.filename, synced_images.id
+            "#,
+        )
+        .bind(album_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(images)
+    }
+
+    /// IDs of every image listed in an album
+    pub async fn member_ids(
+        pool: &sqlx::SqlitePool,
+        album_id: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let ids: Vec<(String,)> =
+            sqlx::query_as("SELECT image_id FROM image_albums WHERE album_id = ?")
+                .bind(album_id)
+                .fetch_all(pool)
+                .await?;
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    /// Take the image out of `album_id`, returning the albums it is still in. If
+    /// that was its primary album, the first remaining one takes its place.
+    pub async fn remove_album_member(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        album_id: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM image_albums WHERE image_id = ? AND album_id = ?")
+            .bind(id)
+            .bind(album_id)
+            .execute(&mut *tx)
+            .await?;
+        let remaining: Vec<(String,)> = sqlx::query_as(
+            "SELECT album_id FROM image_albums WHERE image_id = ? ORDER BY album_id",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+        if let Some((first,)) = remaining.first() {
+            sqlx::query("UPDATE synced_images SET album_id = ? WHERE id = ? AND album_id = ?")
+                .bind(first)
+                .bind(id)
+                .bind(album_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(remaining.into_iter().map(|(id,)| id).collect())
+    }
+
+    /// Every album the image is listed in
+    pub async fn album_ids(pool: &sqlx::SqlitePool, id: &str) -> anyhow::Result<Vec<String>> {
+        let ids: Vec<(String,)> =
+            sqlx::query_as("SELECT album_id FROM image_albums WHERE image_id = ?")
+                .bind(id)
+                .fetch_all(pool)
+                .await?;
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    /// Thumbnail-bearing image to represent an album: the Immich cover if it has
+    /// been converted, otherwise the first converted image by filename
+    pub async fn get_album_cover(
+        pool: &sqlx::SqlitePool,
+        album_id: &str,
+    ) -> anyhow::Result<Option<SyncedImage>> {
+        let image = sqlx::query_as::<_, SyncedImage>(
+            r#"
+            SELECT synced_images.* FROM synced_images
+            JOIN image_albums ON image_albums.image_id = synced_images.id
+            LEFT JOIN albums ON albums.id = image_albums.album_id
+            WHERE image_albums.album_id = ? AND synced_images.thumbnail_path IS NOT NULL
+            ORDER BY synced_images.id = albums.cover_image_id DESC,
+                synced_images.filename, synced_images.id
+            LIMIT 1
+            "#,
+        )
+        .bind(album_id)
+        .fetch_optional(pool)
+        .await?;
+        Ok(image)
+    }
+
     pub async fn get_by_album_paginated(
         pool: &sqlx::SqlitePool,
         album_id: &str,
+        sort: ImageSort,
         offset: i64,
         limit: i64,
     ) -> anyhow::Result<Vec<SyncedImage>> {
-        let images = sqlx::query_as::<_, SyncedImage>(
-            "SELECT * FROM synced_images WHERE album_id = ? AND avif_path IS NOT NULL ORDER BY filename LIMIT ? OFFSET ?",
+        // The ordering comes from a closed enum, never from user input
+        let sql = format!(
+            r#"
+            SELECT synced_images.* FROM synced_images
+            JOIN image_albums ON image_albums.image_id = synced_images.id
+            WHERE image_albums.album_id = ? AND avif_path IS NOT NULL
+            ORDER BY {} LIMIT ? OFFSET ?
+            "#,
+            sort.order_by()
+        );
+        let images = sqlx::query_as::<_, SyncedImage>(&sql)
+            .bind(album_id)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(pool)
+            .await?;
+        Ok(images)
+    }
+
+    pub async fn count_by_album(pool: &sqlx::SqlitePool, album_id: &str) -> anyhow::Result<i64> {
+        let count: (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*) FROM synced_images
+            JOIN image_albums ON image_albums.image_id = synced_images.id
+            WHERE image_albums.album_id = ? AND avif_path IS NOT NULL
+            "#,
         )
         .bind(album_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(count.0)
+    }
+
+    /// Converted images whose filename contains `query`, matched literally
+    pub async fn search(
+        pool: &sqlx::SqlitePool,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> anyhow::Result<Vec<SyncedImage>> {
+        let images = sqlx::query_as::<_, SyncedImage>(
+            "SELECT * FROM synced_images WHERE avif_path IS NOT NULL AND filename LIKE ? ESCAPE '\\' ORDER BY filename, id LIMIT ? OFFSET ?",
+        )
+        .bind(like_pattern(query))
         .bind(limit)
         .bind(offset)
         .fetch_all(pool)
@@ -169,11 +947,11 @@ impl SyncedImage {
         Ok(images)
     }
 
-    pub async fn count_by_album(pool: &sqlx::SqlitePool, album_id: &str) -> anyhow::Result<i64> {
+    pub async fn count_search(pool: &sqlx::SqlitePool, query: &str) -> anyhow::Result<i64> {
         let count: (i64,) = sqlx::query_as(
-            "SELECT COUNT(*) FROM synced_images WHERE album_id = ? AND avif_path IS NOT NULL",
+            "SELECT COUNT(*) FROM synced_images WHERE avif_path IS NOT NULL AND filename LIKE ? ESCAPE '\\'",
         )
-        .bind(album_id)
+        .bind(like_pattern(query))
         .fetch_one(pool)
         .await?;
         Ok(count.0)
@@ -181,15 +959,110 @@ impl SyncedImage {
 
     pub async fn get_unconverted(pool: &sqlx::SqlitePool) -> anyhow::Result<Vec<SyncedImage>> {
         // Include images that either haven't been converted yet, or were converted
-        // before thumbnail support was added (thumbnail_path is NULL)
+        // before thumbnail support was added (thumbnail_path is NULL).
+        // Ordered by sync time then id so an interrupted run resumes in the same order.
+        let images = sqlx::query_as::<_, SyncedImage>(
+            "SELECT * FROM synced_images WHERE original_path IS NOT NULL AND unsupported IS NULL AND (converted_at IS NULL OR thumbnail_path IS NULL) ORDER BY synced_at, id",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(images)
+    }
+
+    /// Like `get_unconverted`, or `get_downloaded` with `include_converted`, but
+    /// also with images whose original is not downloaded, for conversions that
+    /// fetch missing originals
+    pub async fn get_convertible(
+        pool: &sqlx::SqlitePool,
+        include_converted: bool,
+    ) -> anyhow::Result<Vec<SyncedImage>> {
         let images = sqlx::query_as::<_, SyncedImage>(
-            "SELECT * FROM synced_images WHERE original_path IS NOT NULL AND (converted_at IS NULL OR thumbnail_path IS NULL)",
+            "SELECT * FROM synced_images WHERE ? OR (unsupported IS NULL AND (converted_at IS NULL OR thumbnail_path IS NULL)) ORDER BY synced_at, id",
         )
+        .bind(include_converted)
         .fetch_all(pool)
         .await?;
         Ok(images)
     }
 
+    /// Every image with a downloaded original, converted or not, in queue order
+    pub async fn get_downloaded(pool: &sqlx::SqlitePool) -> anyhow::Result<Vec<SyncedImage>> {
+        let images = sqlx::query_as::<_, SyncedImage>(
+            "SELECT * FROM synced_images WHERE original_path IS NOT NULL ORDER BY synced_at, id",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(images)
+    }
+
+    /// ID of another image whose output is `avif_path` with any extension, which
+    /// covers originals linked in place of an AVIF
+    pub async fn avif_path_owner(
+        pool: &sqlx::SqlitePool,
+        avif_path: &std::path::Path,
+        exclude_id: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let stem = avif_path.with_extension("");
+        let candidates: Vec<(String, String)> = sqlx::query_as(
+            "SELECT id, avif_path FROM synced_images WHERE avif_path LIKE ? ESCAPE '\\' AND id != ?",
+        )
+        .bind(format!("{}.%", escape_like(&stem.to_string_lossy())))
+        .bind(exclude_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(candidates
+            .into_iter()
+            .find(|(_, path)| std::path::Path::new(path).with_extension("") == stem)
+            .map(|(id, _)| id))
+    }
+
+    /// ID of another image with `path` as its thumbnail or one of its variants
+    pub async fn output_owner(
+        pool: &sqlx::SqlitePool,
+        path: &std::path::Path,
+        exclude_id: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let path = path.to_string_lossy();
+        let owner: Option<(String,)> = sqlx::query_as(
+            r#"
+            SELECT id FROM synced_images
+            WHERE id != ?
+              AND (thumbnail_path = ?
+                   OR EXISTS (SELECT 1 FROM json_each(variants) WHERE value = ?))
+            LIMIT 1
+            "#,
+        )
+        .bind(exclude_id)
+        .bind(&path)
+        .bind(&path)
+        .fetch_optional(pool)
+        .await?;
+        Ok(owner.map(|(id,)| id))
+    }
+
+    /// Images that reference at least one file on disk
+    pub async fn get_with_files(pool: &sqlx::SqlitePool) -> anyhow::Result<Vec<SyncedImage>> {
+        let images = sqlx::query_as::<_, SyncedImage>(
+            "SELECT * FROM synced_images WHERE original_path IS NOT NULL OR avif_path IS NOT NULL ORDER BY id",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(images)
+    }
+
+    /// Forget an evicted image's files. The row stays, marked as evicted, so
+    /// fetching missing originals does not download it again.
+    pub async fn clear_files(pool: &sqlx::SqlitePool, id: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            "UPDATE synced_images SET original_path = NULL, avif_path = NULL, thumbnail_path = NULL, converted_at = NULL, avif_size = NULL, variants = NULL, evicted_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
     pub async fn get_all_synced_ids(pool: &sqlx::SqlitePool) -> anyhow::Result<Vec<String>> {
         let ids: Vec<(String,)> = sqlx::query_as("SELECT id FROM synced_images")
             .fetch_all(pool)
@@ -198,6 +1071,14 @@ impl SyncedImage {
     }
 
     pub async fn delete_by_id(pool: &sqlx::SqlitePool, id: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM image_albums WHERE image_id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        sqlx::query("DELETE FROM conversion_log WHERE image_id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
         sqlx::query("DELETE FROM synced_images WHERE id = ?")
             .bind(id)
             .execute(pool)
@@ -205,21 +1086,224 @@ impl SyncedImage {
         Ok(())
     }
 
-    pub async fn delete_by_album(pool: &sqlx::SqlitePool, album_id: &str) -> anyhow::Result<()> {
-        sqlx::query("DELETE FROM synced_images WHERE album_id = ?")
-            .bind(album_id)
+    /// Store the image's BlurHash or ThumbHash, or clear it with `None`
+    pub async fn set_placeholder(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        placeholder: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE synced_images SET placeholder = ? WHERE id = ?")
+            .bind(placeholder)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_dominant_color(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        dominant_color: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE synced_images SET dominant_color = ? WHERE id = ?")
+            .bind(dominant_color)
+            .bind(id)
             .execute(pool)
             .await?;
         Ok(())
     }
 
-    /// Clear conversion data for all images (resets avif_path, thumbnail_path, converted_at)
+    /// Record an original downloaded outside of sync
+    pub async fn set_original(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        original_path: &str,
+        file_size: i64,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "UPDATE synced_images SET original_path = ?, file_size = ?, evicted_at = NULL WHERE id = ?",
+        )
+            .bind(original_path)
+            .bind(file_size)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_variants(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        variants: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let json = (!variants.is_empty())
+            .then(|| serde_json::to_string(variants))
+            .transpose()?;
+        sqlx::query("UPDATE synced_images SET variants = ? WHERE id = ?")
+            .bind(json)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_lqip(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        lqip: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE synced_images SET lqip = ? WHERE id = ?")
+            .bind(lqip)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Take an image out of the conversion queue, recording why
+    pub async fn mark_unsupported(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE synced_images SET unsupported = ? WHERE id = ?")
+            .bind(reason)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_taken_at(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        taken_at: Option<NaiveDateTime>,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE synced_images SET taken_at = ?, taken_at_pending = 0 WHERE id = ?")
+            .bind(taken_at)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_avif_size(
+        pool: &sqlx::SqlitePool,
+        id: &str,
+        avif_size: Option<i64>,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE synced_images SET avif_size = ? WHERE id = ?")
+            .bind(avif_size)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Clear conversion data for a single image
+    pub async fn clear_conversion(pool: &sqlx::SqlitePool, id: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            "UPDATE synced_images SET avif_path = NULL, thumbnail_path = NULL, converted_at = NULL, avif_size = NULL, variants = NULL WHERE id = ?",
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Clear conversion data for every image listed in one album, and whether it was
+    /// found unsupported, so the reindex tries every original again
+    pub async fn clear_conversions_for_album(
+        pool: &sqlx::SqlitePool,
+        album_id: &str,
+    ) -> anyhow::Result<u64> {
+        let result = sqlx::query(
+            "UPDATE synced_images SET avif_path = NULL, thumbnail_path = NULL, converted_at = NULL, avif_size = NULL, variants = NULL, unsupported = NULL WHERE id IN (SELECT image_id FROM image_albums WHERE album_id = ?)",
+        )
+        .bind(album_id)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Clear conversion data for all images (resets avif_path, thumbnail_path, converted_at, avif_size, variants, unsupported)
     pub async fn clear_all_conversions(pool: &sqlx::SqlitePool) -> anyhow::Result<u64> {
         let result = sqlx::query(
-            "UPDATE synced_images SET avif_path = NULL, thumbnail_path = NULL, converted_at = NULL",
+            "UPDATE synced_images SET avif_path = NULL, thumbnail_path = NULL, converted_at = NULL, avif_size = NULL, variants = NULL, unsupported = NULL",
         )
         .execute(pool)
         .await?;
         Ok(result.rows_affected())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn resumed_conversion_covers_every_image_once() {
+        let db_path = std::env::temp_dir().join(format!(
+            "avif-generator-resume-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&db_path);
+        let pool = crate::db::create_pool(
+            &db_path,
+            crate::config::JournalMode::default(),
+            std::time::Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+        Album::upsert(&pool, "album", "Album", 10, None)
+            .await
+            .unwrap();
+        for i in (0..10).rev() {
+            let id = format!("image-{:02}", i);
+            let image = NewSyncedImage {
+                id,
+                album_id: "album".to_string(),
+                filename: "a.jpg".to_string(),
+                checksum: None,
+                original_path: Some("/x".to_string()),
+                file_size: None,
+                mime_type: None,
+                synced_at: Utc::now(),
+            };
+            SyncedImage::upsert_many(&pool, &[image]).await.unwrap();
+        }
+
+        // First run converts a prefix of the queue, then "crashes"
+        let first_run = SyncedImage::get_unconverted(&pool).await.unwrap();
+        let first_ids: Vec<String> = first_run.iter().map(|i| i.id.clone()).collect();
+        let again: Vec<String> = SyncedImage::get_unconverted(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|i| i.id)
+            .collect();
+        assert_eq!(first_ids, again, "queue order must be deterministic");
+
+        for image in &first_run[..4] {
+            SyncedImage::mark_converted(&pool, &image.id, "/a.avif", "/t.avif")
+                .await
+                .unwrap();
+        }
+
+        // The resumed run continues exactly where the first one stopped
+        let resumed = SyncedImage::get_unconverted(&pool).await.unwrap();
+        let resumed_ids: Vec<String> = resumed.iter().map(|i| i.id.clone()).collect();
+        assert_eq!(resumed_ids, first_ids[4..].to_vec());
+
+        let mut seen = HashSet::new();
+        for id in first_ids[..4].iter().chain(&resumed_ids) {
+            assert!(seen.insert(id.clone()), "{} converted twice", id);
+        }
+        assert_eq!(seen.len(), 10);
+
+        pool.close().await;
+        let _ = std::fs::remove_file(&db_path);
+    }
+}
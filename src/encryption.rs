@@ -0,0 +1,193 @@
+//! Optional AES-256-GCM encryption of originals at rest, keyed by
+//! `storage.encryption_key`. Encrypted files are the magic bytes, a random 96-bit
+//! nonce and the ciphertext with its tag, so plaintext originals written before
+//! encryption was enabled stay readable.
+
+use crate::config::StorageConfig;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tracing::info;
+
+const MAGIC: &[u8; 8] = b"AVGENC1\0";
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+static CIPHER: OnceLock<Aes256Gcm> = OnceLock::new();
+
+/// Load the key, if configured. Must be called once at startup, before any
+/// original is read or written.
+pub fn init(config: &StorageConfig) -> Result<()> {
+    let encoded = match (&config.encryption_key, &config.encryption_key_file) {
+        (Some(key), _) => key.clone(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read encryption key file {:?}", path))?,
+        (None, None) => return Ok(()),
+    };
+    let key = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .context("Encryption key is not valid base64")?;
+    if key.len() != KEY_LEN {
+        anyhow::bail!(
+            "Encryption key must be {} bytes, got {}",
+            KEY_LEN,
+            key.len()
+        );
+    }
+
+    let _ = CIPHER.set(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)));
+    info!("Encrypting originals at rest");
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    CIPHER.get().is_some()
+}
+
+/// What to write to disk for an original: encrypted with a fresh nonce when a key
+/// is set, unchanged otherwise
+pub fn seal(plaintext: &[u8]) -> Result<Cow<'_, [u8]>> {
+    let Some(cipher) = CIPHER.get() else {
+        return Ok(Cow::Borrowed(plaintext));
+    };
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow!("Failed to encrypt original"))?;
+
+    let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(Cow::Owned(sealed))
+}
+
+/// Plaintext of file contents written by `seal`
+fn open(data: Vec<u8>) -> Result<Vec<u8>> {
+    let Some(rest) = data.strip_prefix(MAGIC.as_slice()) else {
+        return Ok(data);
+    };
+    let cipher = CIPHER
+        .get()
+        .context("Original is encrypted, but no storage.encryption_key is set")?;
+    if rest.len() < NONCE_LEN {
+        anyhow::bail!("Encrypted original is truncated");
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt original: wrong key or corrupted file"))
+}
+
+/// Whether `path` was written encrypted, judging by its first bytes
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let mut header = Vec::with_capacity(MAGIC.len());
+    std::fs::File::open(path)?
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut header)?;
+    Ok(header == MAGIC)
+}
+
+/// Decrypted contents of `path`, or `None` when it is stored in plaintext
+pub fn read_if_encrypted(path: &Path) -> Result<Option<Vec<u8>>> {
+    if !is_encrypted(path)? {
+        return Ok(None);
+    }
+    open(std::fs::read(path)?).map(Some)
+}
+
+/// An original ready for decoding: the file itself when stored in plaintext,
+/// otherwise its contents decrypted into memory, so no plaintext copy ever
+/// reaches the disk
+#[derive(Clone)]
+pub struct PlainFile {
+    path: PathBuf,
+    plaintext: Option<Arc<[u8]>>,
+}
+
+impl PlainFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(PlainFile {
+            path: path.to_path_buf(),
+            plaintext: read_if_encrypted(path)?.map(Arc::from),
+        })
+    }
+
+    /// The stored file. Its extension still names the format, but it may be
+    /// encrypted, so its contents are read through `reader` or `bytes`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.plaintext.is_some()
+    }
+
+    /// Size of the plaintext in bytes
+    pub fn size(&self) -> std::io::Result<u64> {
+        match &self.plaintext {
+            Some(plaintext) => Ok(plaintext.len() as u64),
+            None => Ok(std::fs::metadata(&self.path)?.len()),
+        }
+    }
+
+    pub fn reader(&self) -> std::io::Result<PlainReader> {
+        Ok(match &self.plaintext {
+            Some(plaintext) => PlainReader::Memory(Cursor::new(plaintext.clone())),
+            None => PlainReader::File(BufReader::new(File::open(&self.path)?)),
+        })
+    }
+
+    pub fn bytes(&self) -> std::io::Result<Cow<'_, [u8]>> {
+        Ok(match &self.plaintext {
+            Some(plaintext) => Cow::Borrowed(plaintext),
+            None => Cow::Owned(std::fs::read(&self.path)?),
+        })
+    }
+}
+
+/// Reads a `PlainFile` from disk or from its decrypted copy in memory
+pub enum PlainReader {
+    File(BufReader<File>),
+    Memory(Cursor<Arc<[u8]>>),
+}
+
+impl Read for PlainReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            PlainReader::File(r) => r.read(buf),
+            PlainReader::Memory(r) => r.read(buf),
+        }
+    }
+}
+
+impl BufRead for PlainReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        match self {
+            PlainReader::File(r) => r.fill_buf(),
+            PlainReader::Memory(r) => r.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            PlainReader::File(r) => r.consume(amount),
+            PlainReader::Memory(r) => r.consume(amount),
+        }
+    }
+}
+
+impl Seek for PlainReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            PlainReader::File(r) => r.seek(pos),
+            PlainReader::Memory(r) => r.seek(pos),
+        }
+    }
+}
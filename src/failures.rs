@@ -0,0 +1,45 @@
+//! Per-item failures of a sync or convert run, for machine-readable reports.
+
+use serde::Serialize;
+
+/// Failures listed per run; the rest are only counted, so a run where everything
+/// breaks does not produce an enormous report
+const MAX_REPORTED_FAILURES: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailureInfo {
+    /// Asset ID, or the album ID when a whole album failed
+    pub id: String,
+    /// Original filename, or the album name when a whole album failed
+    pub filename: String,
+    pub error: String,
+}
+
+/// Flattened into run results as `failures` and `failures_omitted`
+#[derive(Debug, Default, Serialize)]
+pub struct FailureList {
+    pub failures: Vec<FailureInfo>,
+    /// Failures past the cap, left out of `failures`
+    pub failures_omitted: usize,
+}
+
+impl FailureList {
+    pub fn push(&mut self, id: &str, filename: &str, error: &anyhow::Error) {
+        if self.failures.len() >= MAX_REPORTED_FAILURES {
+            self.failures_omitted += 1;
+            return;
+        }
+        self.failures.push(FailureInfo {
+            id: id.to_string(),
+            filename: filename.to_string(),
+            error: format!("{:#}", error),
+        });
+    }
+
+    pub fn extend(&mut self, other: FailureList) {
+        let room = MAX_REPORTED_FAILURES.saturating_sub(self.failures.len());
+        let kept = other.failures.len().min(room);
+        self.failures_omitted += other.failures.len() - kept + other.failures_omitted;
+        self.failures.extend(other.failures.into_iter().take(kept));
+    }
+}
@@ -12,6 +12,7 @@ use tokio::sync::RwLock;
 pub enum AuthProvider {
     ApiKey(String),
     OAuth(Arc<OAuthProvider>),
+    SharedLink(String),
 }
 
 pub struct OAuthProvider {
@@ -25,6 +26,7 @@ impl AuthProvider {
     pub fn from_config(config: &AuthConfig) -> Result<Self> {
         match config {
             AuthConfig::ApiKey { api_key } => Ok(AuthProvider::ApiKey(api_key.clone())),
+            AuthConfig::SharedLink { key } => Ok(AuthProvider::SharedLink(key.clone())),
             AuthConfig::OAuth {
                 client_id,
                 client_secret,
@@ -52,6 +54,7 @@ impl AuthProvider {
     pub async fn get_auth_header(&self) -> Result<(String, String)> {
         match self {
             AuthProvider::ApiKey(key) => Ok(("x-api-key".to_string(), key.clone())),
+            AuthProvider::SharedLink(key) => Ok(("x-immich-share-key".to_string(), key.clone())),
             AuthProvider::OAuth(provider) => {
                 let token = provider.token.read().await;
                 match token.as_ref() {
@@ -62,9 +65,15 @@ impl AuthProvider {
         }
     }
 
+    pub fn is_shared_link(&self) -> bool {
+        matches!(self, AuthProvider::SharedLink(_))
+    }
+
     pub fn get_oauth_url(&self) -> Result<(String, String)> {
         match self {
-            AuthProvider::ApiKey(_) => Err(anyhow!("Cannot get OAuth URL for API key auth")),
+            AuthProvider::ApiKey(_) | AuthProvider::SharedLink(_) => {
+                Err(anyhow!("Cannot get OAuth URL for non-OAuth auth"))
+            }
             AuthProvider::OAuth(provider) => {
                 let (auth_url, csrf_token) = provider
                     .client
@@ -79,7 +88,9 @@ impl AuthProvider {
 
     pub async fn exchange_code(&self, code: &str) -> Result<()> {
         match self {
-            AuthProvider::ApiKey(_) => Err(anyhow!("Cannot exchange code for API key auth")),
+            AuthProvider::ApiKey(_) | AuthProvider::SharedLink(_) => {
+                Err(anyhow!("Cannot exchange code for non-OAuth auth"))
+            }
             AuthProvider::OAuth(provider) => {
                 let token_result = provider
                     .client
@@ -104,7 +115,9 @@ impl AuthProvider {
     #[allow(dead_code)]
     pub async fn set_token(&self, access_token: &str) -> Result<()> {
         match self {
-            AuthProvider::ApiKey(_) => Err(anyhow!("Cannot set token for API key auth")),
+            AuthProvider::ApiKey(_) | AuthProvider::SharedLink(_) => {
+                Err(anyhow!("Cannot set token for non-OAuth auth"))
+            }
             AuthProvider::OAuth(provider) => {
                 let mut token = provider.token.write().await;
                 *token = Some(access_token.to_string());
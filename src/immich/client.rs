@@ -1,45 +1,233 @@
 use super::auth::AuthProvider;
-use super::types::{AlbumResponse, AssetResponse, ServerInfo};
-use anyhow::{Context, Result};
-use reqwest::Client;
+use super::types::{
+    AlbumResponse, AssetResponse, MetadataSearchResponse, ServerInfo, SharedLinkResponse,
+};
+use crate::config::{ApiProbeMode, ImmichConfig};
+use crate::encryption;
+use crate::storage;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Proxy, RequestBuilder, Response, StatusCode};
+use std::collections::HashSet;
 use std::path::Path;
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
-use tracing::{debug, info};
+use tokio::sync::{OnceCell, Semaphore, SemaphorePermit};
+use tracing::{debug, info, warn};
+
+/// Endpoints the sync path depends on, probed once at startup. The asset ID is a
+/// placeholder: an existing route answers 400/403, a missing one a NestJS 404.
+const PROBED_ENDPOINTS: &[&str] = &[
+    "/api/albums",
+    "/api/assets/00000000-0000-0000-0000-000000000000/original",
+];
+
+/// Page size for metadata searches; Immich caps `size` at 1000
+const SEARCH_PAGE_SIZE: u32 = 1000;
+
+/// Longest wait between retries, however many attempts are configured
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Describe a failed Immich request by its likely cause: rejected credentials,
+/// nothing listening, an unresolvable host, or a timeout
+pub fn explain_error(error: &anyhow::Error) -> String {
+    let Some(e) = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<reqwest::Error>())
+    else {
+        return format!("{:#}", error);
+    };
+
+    if let Some(status) = e.status() {
+        return match status {
+            StatusCode::UNAUTHORIZED => {
+                "authentication failed (HTTP 401): the API key or token was rejected".to_string()
+            }
+            StatusCode::FORBIDDEN => {
+                "access denied (HTTP 403): the credentials lack the required permissions"
+                    .to_string()
+            }
+            status => format!("server answered HTTP {}", status),
+        };
+    }
+    if e.is_timeout() {
+        return "request timed out".to_string();
+    }
+    if e.is_connect() {
+        let mut source = std::error::Error::source(e);
+        while let Some(cause) = source {
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                if io.kind() == std::io::ErrorKind::ConnectionRefused {
+                    return "connection refused: nothing is listening at the Immich URL"
+                        .to_string();
+                }
+            }
+            let text = cause.to_string();
+            if text.contains("dns error") || text.contains("failed to lookup address") {
+                let detail = std::iter::successors(Some(cause), |c| c.source())
+                    .last()
+                    .map_or(text, |c| c.to_string());
+                return format!("DNS lookup failed: {}", detail);
+            }
+            source = cause.source();
+        }
+        return format!("could not connect: {:#}", error);
+    }
+
+    format!("{:#}", error)
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiCapabilities {
+    pub version: Option<String>,
+    pub missing_endpoints: Vec<&'static str>,
+}
 
 pub struct ImmichClient {
     client: Client,
     base_url: String,
     auth: AuthProvider,
+    album_page_size: u32,
+    max_album_pages: u32,
+    api_probe: ApiProbeMode,
+    capabilities: OnceCell<ApiCapabilities>,
+    api_timeout: Duration,
+    download_timeout: Duration,
+    retry_attempts: u32,
+    retry_backoff: Duration,
+    /// Caps requests in flight to the server; `None` when unlimited
+    requests: Option<Semaphore>,
 }
 
 impl ImmichClient {
-    pub fn new(base_url: &str, auth: AuthProvider) -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300))
-            .build()
-            .expect("Failed to create HTTP client");
+    pub fn new(config: &ImmichConfig, auth: AuthProvider) -> Result<Self> {
+        // Timeouts are set per request: JSON calls should fail fast, downloads of
+        // large originals need much longer
+        let mut builder = Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .pool_max_idle_per_host(config.pool_max_idle)
+            .user_agent(&config.user_agent);
+        if let Some(proxy) = &config.proxy {
+            let proxy =
+                Proxy::all(proxy).with_context(|| format!("Invalid proxy URL {}", proxy))?;
+            builder = builder.proxy(proxy);
+        }
+        if config.insecure_skip_tls_verify {
+            warn!(
+                "TLS certificate verification is DISABLED for {}: anyone on the network path can impersonate the server and read the API key",
+                config.url
+            );
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        let client = builder.build().context("Failed to create HTTP client")?;
 
-        let base_url = base_url.trim_end_matches('/').to_string();
+        let base_url = config.url.trim_end_matches('/').to_string();
 
-        Self {
+        Ok(Self {
             client,
             base_url,
             auth,
+            album_page_size: config.album_page_size,
+            max_album_pages: config.max_album_pages,
+            api_probe: config.api_probe,
+            capabilities: OnceCell::new(),
+            api_timeout: Duration::from_secs(config.api_timeout_secs),
+            download_timeout: Duration::from_secs(config.download_timeout_secs),
+            retry_attempts: config.retry_attempts.max(1),
+            retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+            requests: (config.max_concurrent_requests > 0).then(|| {
+                Semaphore::new(config.max_concurrent_requests.min(Semaphore::MAX_PERMITS))
+            }),
+        })
+    }
+
+    /// Wait for a free request slot, if `max_concurrent_requests` limits them
+    async fn acquire_slot(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.requests {
+            // The semaphore is never closed
+            Some(requests) => requests.acquire().await.ok(),
+            None => None,
+        }
+    }
+
+    /// Send a request, retrying connection errors, timeouts and 5xx responses with
+    /// exponential backoff. Other responses, including 4xx, go back to the caller,
+    /// as does the last 5xx once the attempts run out.
+    async fn send_with_retry(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        self.send_holding_slot(request)
+            .await
+            .map(|(response, _slot)| response)
+    }
+
+    /// `send_with_retry`, also returning the request slot so the caller can keep it
+    /// while reading a large body. The slot is given up between retries.
+    async fn send_holding_slot(
+        &self,
+        request: RequestBuilder,
+    ) -> reqwest::Result<(Response, Option<SemaphorePermit<'_>>)> {
+        let mut attempt = 1;
+        loop {
+            let slot = self.acquire_slot().await;
+
+            // Only streaming bodies cannot be cloned, and none are sent here
+            let Some(this_try) = request.try_clone() else {
+                return request.send().await.map(|response| (response, slot));
+            };
+
+            let error = match this_try.send().await {
+                Ok(response) if response.status().is_server_error() => {
+                    if attempt >= self.retry_attempts {
+                        return Ok((response, slot));
+                    }
+                    format!("HTTP {}", response.status())
+                }
+                Ok(response) => return Ok((response, slot)),
+                Err(e) if e.is_connect() || e.is_timeout() || e.is_request() => {
+                    if attempt >= self.retry_attempts {
+                        return Err(e);
+                    }
+                    e.to_string()
+                }
+                Err(e) => return Err(e),
+            };
+            drop(slot);
+
+            let delay = self
+                .retry_backoff
+                .checked_mul(2u32.saturating_pow(attempt - 1))
+                .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY));
+            warn!(
+                "Immich request failed ({}), retrying in {:?} (attempt {}/{})",
+                error, delay, attempt, self.retry_attempts
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
     }
 
     async fn request_builder(&self, url: &str) -> Result<reqwest::RequestBuilder> {
         let (header_name, header_value) = self.auth.get_auth_header().await?;
-        Ok(self.client.get(url).header(&header_name, &header_value))
+        Ok(self
+            .client
+            .get(url)
+            .header(&header_name, &header_value)
+            .timeout(self.api_timeout))
+    }
+
+    async fn post_request_builder(&self, url: &str) -> Result<reqwest::RequestBuilder> {
+        let (header_name, header_value) = self.auth.get_auth_header().await?;
+        Ok(self
+            .client
+            .post(url)
+            .header(&header_name, &header_value)
+            .timeout(self.api_timeout))
     }
 
     pub async fn ping(&self) -> Result<ServerInfo> {
         let url = format!("{}/api/server/version", self.base_url);
         let response = self
-            .request_builder(&url)
-            .await?
-            .send()
+            .send_with_retry(self.request_builder(&url).await?)
             .await?
             .error_for_status()
             .context("Failed to ping Immich server")?;
@@ -49,35 +237,95 @@ impl ImmichClient {
         Ok(info)
     }
 
+    /// Probe the endpoints we depend on. The result is cached for the client's lifetime.
+    pub async fn capabilities(&self) -> Result<&ApiCapabilities> {
+        self.capabilities
+            .get_or_try_init(|| async {
+                let version = self.ping().await.ok().map(|info| info.version);
+
+                let mut missing_endpoints = Vec::new();
+                for endpoint in PROBED_ENDPOINTS {
+                    if !self.endpoint_exists(endpoint).await? {
+                        missing_endpoints.push(*endpoint);
+                    }
+                }
+
+                Ok(ApiCapabilities {
+                    version,
+                    missing_endpoints,
+                })
+            })
+            .await
+    }
+
+    async fn endpoint_exists(&self, endpoint: &str) -> Result<bool> {
+        let url = format!("{}{}", self.base_url, endpoint);
+        let response = self
+            .send_with_retry(self.request_builder(&url).await?)
+            .await
+            .context(format!("Failed to probe {}", endpoint))?;
+
+        if response.status() != StatusCode::NOT_FOUND {
+            return Ok(true);
+        }
+
+        // Unknown routes produce "Cannot GET /api/..."; a 404 about a resource does not
+        let body = response.text().await.unwrap_or_default();
+        Ok(!body.contains("Cannot GET"))
+    }
+
+    /// Fail (or warn, per `api_probe`) when the server lacks an endpoint we depend on
+    pub async fn ensure_supported_api(&self) -> Result<()> {
+        if self.api_probe == ApiProbeMode::Off {
+            return Ok(());
+        }
+
+        let capabilities = self.capabilities().await?;
+        let Some(endpoint) = capabilities.missing_endpoints.first() else {
+            return Ok(());
+        };
+
+        let message = format!(
+            "Immich {} does not provide GET {}; this Immich version uses a different API path",
+            capabilities
+                .version
+                .as_deref()
+                .unwrap_or("(unknown version)"),
+            endpoint
+        );
+
+        match self.api_probe {
+            ApiProbeMode::Strict => Err(anyhow!(message)),
+            _ => {
+                warn!("{}", message);
+                Ok(())
+            }
+        }
+    }
+
     pub async fn get_albums(&self) -> Result<Vec<AlbumResponse>> {
+        if self.auth.is_shared_link() {
+            let album = self.shared_link_album().await?;
+            info!("Shared link gives access to album '{}'", album.album_name);
+            return Ok(vec![album]);
+        }
+
         let url = format!("{}/api/albums", self.base_url);
         debug!("Fetching owned albums from {}", url);
-
-        let owned_response = self
-            .request_builder(&url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()
-            .context("Failed to fetch owned albums")?;
-        let owned: Vec<AlbumResponse> = owned_response.json().await?;
+        let owned = self
+            .fetch_album_pages(&url, "Failed to fetch owned albums")
+            .await?;
         debug!("Found {} owned albums", owned.len());
 
         let shared_url = format!("{}/api/albums?shared=true", self.base_url);
         debug!("Fetching shared albums from {}", shared_url);
-
-        let shared_response = self
-            .request_builder(&shared_url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()
-            .context("Failed to fetch shared albums")?;
-        let shared: Vec<AlbumResponse> = shared_response.json().await?;
+        let shared = self
+            .fetch_album_pages(&shared_url, "Failed to fetch shared albums")
+            .await?;
         debug!("Found {} shared albums", shared.len());
 
         // Merge and deduplicate by album ID
-        let mut seen = std::collections::HashSet::new();
+        let mut seen = HashSet::new();
         let mut albums = Vec::new();
         for album in owned.into_iter().chain(shared) {
             if seen.insert(album.id.clone()) {
@@ -85,18 +333,90 @@ impl ImmichClient {
             }
         }
 
-        debug!("Total unique albums: {}", albums.len());
+        info!("Fetched {} unique albums from Immich", albums.len());
+        Ok(albums)
+    }
+
+    /// Fetch an album listing, following `page`/`size` parameters when paging is enabled.
+    ///
+    /// Paging stops on a short page, on a page that contributes no new album IDs (the
+    /// server ignored the parameters), or when `max_album_pages` is reached.
+    async fn fetch_album_pages(
+        &self,
+        url: &str,
+        error_context: &str,
+    ) -> Result<Vec<AlbumResponse>> {
+        if self.album_page_size == 0 {
+            let response = self
+                .send_with_retry(self.request_builder(url).await?)
+                .await?
+                .error_for_status()
+                .context(error_context.to_string())?;
+            return Ok(response.json().await?);
+        }
+
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let mut seen = HashSet::new();
+        let mut albums = Vec::new();
+
+        for page in 1..=self.max_album_pages {
+            let page_url = format!(
+                "{}{}page={}&size={}",
+                url, separator, page, self.album_page_size
+            );
+            let response = self
+                .send_with_retry(self.request_builder(&page_url).await?)
+                .await?
+                .error_for_status()
+                .context(error_context.to_string())?;
+            let batch: Vec<AlbumResponse> = response.json().await?;
+            let batch_len = batch.len();
+
+            let mut new_albums = 0;
+            for album in batch {
+                if seen.insert(album.id.clone()) {
+                    albums.push(album);
+                    new_albums += 1;
+                }
+            }
+            debug!(
+                "Album page {}: {} albums ({} new)",
+                page, batch_len, new_albums
+            );
+
+            if batch_len < self.album_page_size as usize || new_albums == 0 {
+                return Ok(albums);
+            }
+
+            if page == self.max_album_pages {
+                warn!(
+                    "Stopped album listing after {} pages ({} albums); raise max_album_pages if albums are missing",
+                    page,
+                    albums.len()
+                );
+            }
+        }
+
         Ok(albums)
     }
 
     pub async fn get_album(&self, album_id: &str) -> Result<AlbumResponse> {
+        if self.auth.is_shared_link() {
+            let album = self.shared_link_album().await?;
+            if album.id != album_id {
+                return Err(anyhow!("Album {} is not part of the shared link", album_id));
+            }
+            // Individual-asset links have no real album to fetch
+            if !album.assets.is_empty() {
+                return Ok(album);
+            }
+        }
+
         let url = format!("{}/api/albums/{}", self.base_url, album_id);
         debug!("Fetching album {} from {}", album_id, url);
 
         let response = self
-            .request_builder(&url)
-            .await?
-            .send()
+            .send_with_retry(self.request_builder(&url).await?)
             .await?
             .error_for_status()
             .context(format!("Failed to fetch album {}", album_id))?;
@@ -109,14 +429,152 @@ impl ImmichClient {
         Ok(album)
     }
 
+    /// Album name, count and cover without its asset list
+    pub async fn get_album_info(&self, album_id: &str) -> Result<AlbumResponse> {
+        if self.auth.is_shared_link() {
+            let album = self.shared_link_album().await?;
+            if album.id != album_id {
+                return Err(anyhow!("Album {} is not part of the shared link", album_id));
+            }
+            return Ok(album);
+        }
+
+        let url = format!(
+            "{}/api/albums/{}?withoutAssets=true",
+            self.base_url, album_id
+        );
+        let response = self
+            .send_with_retry(self.request_builder(&url).await?)
+            .await?
+            .error_for_status()
+            .context(format!("Failed to fetch album {}", album_id))?;
+
+        Ok(response.json().await?)
+    }
+
+    /// Assets of an album created or modified after `since`, via the metadata search.
+    ///
+    /// Returns `None` when an incremental listing is unavailable: shared links cannot
+    /// search, and servers without `albumIds`/`updatedAfter` support answer 400 or 404.
+    pub async fn get_album_assets_updated_after(
+        &self,
+        album_id: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<Vec<AssetResponse>>> {
+        let assets = self.search_album_assets(album_id, Some(since)).await?;
+        if let Some(assets) = &assets {
+            debug!(
+                "Album {}: {} assets changed since {}",
+                album_id,
+                assets.len(),
+                since
+            );
+        }
+        Ok(assets)
+    }
+
+    /// Every asset of an album, paged through the metadata search. For albums whose
+    /// inline asset list the server truncated; `None` when searching is unavailable.
+    pub async fn get_album_assets(&self, album_id: &str) -> Result<Option<Vec<AssetResponse>>> {
+        let assets = self.search_album_assets(album_id, None).await?;
+        if let Some(assets) = &assets {
+            debug!(
+                "Album {}: {} assets listed by search",
+                album_id,
+                assets.len()
+            );
+        }
+        Ok(assets)
+    }
+
+    async fn search_album_assets(
+        &self,
+        album_id: &str,
+        updated_after: Option<DateTime<Utc>>,
+    ) -> Result<Option<Vec<AssetResponse>>> {
+        if self.auth.is_shared_link() {
+            return Ok(None);
+        }
+
+        let url = format!("{}/api/search/metadata", self.base_url);
+        let mut assets = Vec::new();
+        let mut page = 1u32;
+
+        loop {
+            let mut body = serde_json::json!({
+                "albumIds": [album_id],
+                "page": page,
+                "size": SEARCH_PAGE_SIZE,
+            });
+            if let Some(since) = updated_after {
+                body["updatedAfter"] = since.to_rfc3339().into();
+            }
+            let response = self
+                .send_with_retry(self.post_request_builder(&url).await?.json(&body))
+                .await?;
+
+            if matches!(
+                response.status(),
+                StatusCode::BAD_REQUEST | StatusCode::NOT_FOUND
+            ) {
+                debug!(
+                    "Server rejected asset search for album {} ({})",
+                    album_id,
+                    response.status()
+                );
+                return Ok(None);
+            }
+
+            let response = response
+                .error_for_status()
+                .context(format!("Failed to search assets of album {}", album_id))?;
+            let results: MetadataSearchResponse = response.json().await?;
+            assets.extend(results.assets.items);
+
+            match results.assets.next_page {
+                Some(_) => page += 1,
+                None => break,
+            }
+        }
+
+        Ok(Some(assets))
+    }
+
+    /// The album behind the configured shared link. Links to individual assets are
+    /// presented as a pseudo-album keyed by the link ID, with the assets inlined.
+    async fn shared_link_album(&self) -> Result<AlbumResponse> {
+        let url = format!("{}/api/shared-links/me", self.base_url);
+        let response = self
+            .send_with_retry(self.request_builder(&url).await?)
+            .await?
+            .error_for_status()
+            .context("Failed to fetch shared link")?;
+        let link: SharedLinkResponse = response.json().await?;
+
+        if let Some(mut album) = link.album {
+            // Album links list assets through the album endpoint instead
+            album.assets.clear();
+            return Ok(album);
+        }
+
+        Ok(AlbumResponse {
+            album_name: link
+                .description
+                .filter(|d| !d.is_empty())
+                .unwrap_or_else(|| format!("Shared link {}", link.id)),
+            id: link.id,
+            asset_count: link.assets.len() as i64,
+            album_thumbnail_asset_id: None,
+            assets: link.assets,
+        })
+    }
+
     pub async fn get_asset(&self, asset_id: &str) -> Result<AssetResponse> {
         let url = format!("{}/api/assets/{}", self.base_url, asset_id);
         debug!("Fetching asset metadata for {}", asset_id);
 
         let response = self
-            .request_builder(&url)
-            .await?
-            .send()
+            .send_with_retry(self.request_builder(&url).await?)
             .await?
             .error_for_status()
             .context(format!("Failed to fetch asset {}", asset_id))?;
@@ -128,15 +586,41 @@ impl ImmichClient {
     pub async fn download_asset(&self, asset_id: &str, dest_path: &Path) -> Result<u64> {
         let url = format!("{}/api/assets/{}/original", self.base_url, asset_id);
         debug!("Downloading asset {} to {:?}", asset_id, dest_path);
+        self.download_to(&url, asset_id, dest_path).await
+    }
+
+    /// Download the thumbnail Immich generated for an asset: `size` is `"thumbnail"`
+    /// (small WebP) or `"preview"` (large JPEG)
+    pub async fn download_thumbnail(
+        &self,
+        asset_id: &str,
+        size: &str,
+        dest_path: &Path,
+    ) -> Result<u64> {
+        let url = format!(
+            "{}/api/assets/{}/thumbnail?size={}",
+            self.base_url, asset_id, size
+        );
+        debug!(
+            "Downloading {} thumbnail of {} to {:?}",
+            size, asset_id, dest_path
+        );
+        self.download_to(&url, asset_id, dest_path).await
+    }
 
+    /// Write the response body to `dest_path`, encrypted when that is enabled
+    async fn download_to(&self, url: &str, asset_id: &str, dest_path: &Path) -> Result<u64> {
         let (header_name, header_value) = self.auth.get_auth_header().await?;
 
-        let response = self
+        let request = self
             .client
-            .get(&url)
+            .get(url)
             .header(&header_name, &header_value)
-            .send()
-            .await?
+            .timeout(self.download_timeout);
+        // The slot is held until the whole body is in, which is most of the time
+        // a download spends on the server
+        let (response, slot) = self.send_holding_slot(request).await?;
+        let response = response
             .error_for_status()
             .context(format!("Failed to download asset {}", asset_id))?;
 
@@ -144,11 +628,22 @@ impl ImmichClient {
             tokio::fs::create_dir_all(parent).await?;
         }
 
-        let mut file = File::create(dest_path).await?;
         let bytes = response.bytes().await?;
+        drop(slot);
         let size = bytes.len() as u64;
-        file.write_all(&bytes).await?;
-        file.flush().await?;
+        let part_path = storage::part_path(dest_path);
+        let written = async {
+            let mut file = File::create(&part_path).await?;
+            file.write_all(&encryption::seal(&bytes)?).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&part_path, dest_path).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e.context(format!("Failed to write {:?}", dest_path)));
+        }
 
         debug!("Downloaded {} bytes to {:?}", size, dest_path);
         Ok(size)
@@ -3,7 +3,7 @@ pub mod client;
 pub mod types;
 
 pub use auth::AuthProvider;
-pub use client::ImmichClient;
+pub use client::{explain_error, ImmichClient};
 pub use types::AssetResponse;
 #[allow(unused)]
 pub use types::{AlbumResponse, AssetType};
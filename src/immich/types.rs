@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -7,6 +8,8 @@ pub struct AlbumResponse {
     pub album_name: String,
     pub asset_count: i64,
     #[serde(default)]
+    pub album_thumbnail_asset_id: Option<String>,
+    #[serde(default)]
     pub assets: Vec<AssetResponse>,
 }
 
@@ -20,6 +23,18 @@ pub struct AssetResponse {
     pub asset_type: AssetType,
     pub original_mime_type: Option<String>,
     pub file_size: Option<i64>,
+    /// Where Immich stores the original, as seen inside the Immich server
+    #[serde(default)]
+    pub original_path: Option<String>,
+    /// Capture time as Immich read it from the file
+    #[serde(default)]
+    pub file_created_at: Option<DateTime<Utc>>,
+    /// When the asset was uploaded to Immich
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Immich user who uploaded the asset
+    #[serde(default)]
+    pub owner_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
@@ -35,12 +50,44 @@ impl AssetResponse {
     pub fn is_image(&self) -> bool {
         self.asset_type == AssetType::Image
     }
+
+    /// The capture time, or the upload time for assets Immich found none for
+    pub fn date(&self) -> Option<DateTime<Utc>> {
+        self.file_created_at.or(self.created_at)
+    }
+}
+
+/// `GET /api/shared-links/me`: either an album link or a hand-picked set of assets
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedLinkResponse {
+    pub id: String,
+    pub description: Option<String>,
+    pub album: Option<AlbumResponse>,
+    #[serde(default)]
+    pub assets: Vec<AssetResponse>,
+}
+
+/// `POST /api/search/metadata`: only the asset results are used
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetadataSearchResponse {
+    pub assets: MetadataSearchPage,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataSearchPage {
+    pub items: Vec<AssetResponse>,
+    pub next_page: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ServerInfo {
     pub version: String,
+    /// Release notes link, on servers that report one
+    #[serde(default)]
+    pub version_url: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
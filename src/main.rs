@@ -1,17 +1,34 @@
 mod config;
 mod converter;
 mod db;
+mod encryption;
+mod failures;
 mod immich;
+mod metrics;
+mod notifications;
+mod progress;
 mod server;
+mod storage;
 mod sync;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use config::Config;
-use converter::AvifConverter;
-use db::models::SyncedImage;
-use immich::{AuthProvider, ImmichClient};
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{Config, SyncSchedule};
+use converter::{watch, AvifConverter};
+use db::models::{Album, ImageCounts, SyncedImage};
+use immich::{explain_error, AuthProvider, ImmichClient};
+use notifications::{CompletionEvent, Notifier};
+use serde::Serialize;
+use server::health;
+use server::integrity::{self, IntegrityCache};
+use server::rate_limit::RateLimiter;
+use server::routes::{NotFoundImage, ResponseHeaders};
 use server::{AppState, create_router};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use sync::SyncService;
 use tracing::{Level, info, warn};
 use tracing_subscriber::EnvFilter;
@@ -24,17 +41,58 @@ struct Cli {
     #[arg(short, long)]
     config: Option<String>,
 
+    /// Draw progress bars for sync and conversion when attached to a terminal
+    #[arg(long, global = true)]
+    progress: bool,
+
+    /// How command results are printed on stdout; logs always go to stderr
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// One human-readable summary line
+    Text,
+    /// A single JSON object, for scripts
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Sync albums from Immich (incremental download)
-    Sync,
+    Sync {
+        /// Only refresh filenames and checksums of already synced images, without downloading
+        #[arg(long)]
+        metadata_only: bool,
+        /// Only download assets captured (or, lacking a capture time, uploaded) after
+        /// this RFC 3339 time; assets with neither are skipped
+        #[arg(long, conflicts_with = "metadata_only")]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        /// Only sync this album ID
+        #[arg(long, conflicts_with = "metadata_only")]
+        album: Option<String>,
+    },
 
     /// Convert downloaded images to AVIF format
-    Convert,
+    Convert {
+        /// Encode in memory and report estimated sizes without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Reconvert images that were already converted, replacing their AVIFs
+        #[arg(long)]
+        force: bool,
+        /// Only convert this album ID
+        #[arg(long)]
+        album: Option<String>,
+        /// Download originals that are missing locally from Immich instead of
+        /// skipping their images
+        #[arg(long, conflicts_with = "dry_run")]
+        fetch_missing: bool,
+    },
 
     /// Start the HTTP server to serve AVIF images
     Serve,
@@ -42,11 +100,88 @@ enum Commands {
     /// Sync, convert, and serve (all-in-one)
     Run,
 
+    /// Serve, and sync + convert whenever `sync.schedule` comes due
+    Daemon,
+
     /// Test connection to Immich server
     Ping,
 
+    /// List the albums on the Immich server and whether each is already synced
+    Albums,
+
     /// Delete all AVIF images and reconvert from originals
-    Reindex,
+    Reindex {
+        /// Only reindex this album ID
+        #[arg(long)]
+        album: Option<String>,
+    },
+
+    /// Check stored AVIFs for missing or corrupt files and queue them for reconversion
+    Verify,
+
+    /// Show album and image counts from the local database
+    Status,
+
+    /// Delete content-addressed blobs that no image refers to any more
+    Clean,
+
+    /// Delete the files of removed images kept with `removal_mode = "trash"`
+    EmptyTrash {
+        /// Only delete files older than `trash_retention_days`
+        #[arg(long)]
+        expired: bool,
+    },
+
+    /// Write `manifest.json` into each album's AVIF directory for static hosting
+    Manifest {
+        /// Only write this album ID's manifest
+        #[arg(long)]
+        album: Option<String>,
+    },
+
+    /// Write all albums and images in the database to a JSON file
+    Export {
+        /// File to write
+        #[arg(long)]
+        output: PathBuf,
+    },
+
+    /// Upsert albums and images from a file written by `export`
+    Import {
+        /// File to read
+        #[arg(long)]
+        input: PathBuf,
+    },
+}
+
+/// `status` output: the album count plus image totals
+#[derive(Serialize)]
+struct Status {
+    albums: usize,
+    #[serde(flatten)]
+    images: ImageCounts,
+}
+
+/// One row of `albums` output: an album on the Immich server
+#[derive(Serialize)]
+struct AlbumListing {
+    id: String,
+    name: String,
+    asset_count: i64,
+    /// Whether the album is in the local database, i.e. has been synced
+    tracked: bool,
+    last_sync: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Print a command's result on stdout, as JSON or as the text from `text`
+fn report<T: Serialize>(format: OutputFormat, result: &T, text: impl FnOnce(&T) -> String) {
+    match format {
+        OutputFormat::Text => println!("{}", text(result)),
+        OutputFormat::Json => match serde_json::to_string(result) {
+            Ok(json) => println!("{}", json),
+            Err(e) => warn!("Failed to serialize result: {}", e),
+        },
+    }
 }
 
 #[tokio::main]
@@ -57,71 +192,461 @@ async fn main() -> Result<()> {
                 .with_default_directive(Level::INFO.into())
                 .from_env_lossy(),
         )
+        .with_writer(std::io::stderr)
         .init();
 
     let cli = Cli::parse();
     let config = match &cli.config {
-        Some(path) => Config::load(path)?,
+        Some(path) => Config::load_with_env_overrides(path)?,
         None => Config::from_env()?,
     };
 
+    metrics::init(&config.metrics)?;
+    encryption::init(&config.storage)?;
+    converter::watermark::init(&config.watermark)?;
+
     tokio::fs::create_dir_all(&config.original_path()).await?;
     tokio::fs::create_dir_all(&config.avif_path()).await?;
+    // Commands that write fail here with one clear error when a storage volume is
+    // missing, rather than once per image
+    if matches!(
+        cli.command,
+        Commands::Sync { .. } | Commands::Convert { .. } | Commands::Run | Commands::Daemon
+    ) {
+        storage::check_volumes(&config).await?;
+    }
+    // Only commands that download clean up, so a `serve` started during a sync
+    // leaves that sync's downloads alone
+    if matches!(
+        cli.command,
+        Commands::Sync { .. }
+            | Commands::Convert {
+                fetch_missing: true,
+                ..
+            }
+            | Commands::Run
+            | Commands::Daemon
+    ) {
+        let partial = storage::remove_partial_downloads(&config.original_path()).await?;
+        if partial > 0 {
+            warn!(
+                "Removed {} partial downloads left by an interrupted sync; they will be downloaded again",
+                partial
+            );
+        }
+    }
 
-    let pool = db::create_pool(&config.db_path()).await?;
+    let pool = db::create_pool(
+        &config.db_path(),
+        config.storage.db_journal_mode,
+        Duration::from_millis(config.storage.db_busy_timeout_ms),
+    )
+    .await?;
     let auth = AuthProvider::from_config(&config.immich.auth)?;
-    let client = ImmichClient::new(&config.immich.url, auth);
+    let client = ImmichClient::new(&config.immich, auth)?;
+    let notifier = Notifier::new(&config.notifications);
 
+    let show_progress = cli.progress;
+    let format = cli.format;
     match cli.command {
         Commands::Ping => {
-            let info = client.ping().await?;
-            println!("Connected to Immich server version: {}", info.version);
+            let info = client
+                .ping()
+                .await
+                .map_err(|e| anyhow::anyhow!("Cannot reach Immich: {}", explain_error(&e)))?;
+            // The version endpoint is public, so listing albums is what proves the credentials
+            let albums = client
+                .get_albums()
+                .await
+                .map(|albums| albums.len())
+                .map_err(|e| explain_error(&e));
+            let capabilities = client.capabilities().await?;
+            let result = serde_json::json!({
+                "version": info.version,
+                "version_url": info.version_url,
+                "authenticated": albums.is_ok(),
+                "albums": albums.as_ref().ok(),
+                "auth_error": albums.as_ref().err(),
+                "missing_endpoints": capabilities.missing_endpoints,
+            });
+            report(format, &result, |_| {
+                let mut text = format!("Connected to Immich server version: {}", info.version);
+                if let Some(url) = &info.version_url {
+                    text.push_str(&format!(" ({})", url));
+                }
+                // A failed check is reported as the command's error below
+                if let Ok(count) = &albums {
+                    text.push_str(&format!("\nAuthenticated, {} albums accessible", count));
+                }
+                for endpoint in &capabilities.missing_endpoints {
+                    text.push_str(&format!(
+                        "\nMissing endpoint: GET {} (this Immich version uses a different API path)",
+                        endpoint
+                    ));
+                }
+                text
+            });
+            if let Err(e) = albums {
+                anyhow::bail!("Authentication check failed: {}", e);
+            }
         }
 
-        Commands::Sync => {
+        Commands::Albums => {
+            let tracked: HashMap<String, Album> = Album::get_all(&pool)
+                .await?
+                .into_iter()
+                .map(|album| (album.id.clone(), album))
+                .collect();
+            let mut albums: Vec<AlbumListing> = client
+                .get_albums()
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to list albums: {}", explain_error(&e)))?
+                .into_iter()
+                .map(|album| {
+                    let local = tracked.get(&album.id);
+                    AlbumListing {
+                        tracked: local.is_some(),
+                        last_sync: local.and_then(|a| a.last_sync),
+                        id: album.id,
+                        name: album.album_name,
+                        asset_count: album.asset_count,
+                    }
+                })
+                .collect();
+            // Untracked first, as those are what this listing is usually for
+            albums.sort_by(|a, b| a.tracked.cmp(&b.tracked).then(a.name.cmp(&b.name)));
+            report(format, &albums, |albums| {
+                let mut text = format!(
+                    "{:<9} {:>7}  {:<20} {:<36}  NAME",
+                    "STATUS", "ASSETS", "LAST SYNC", "ID"
+                );
+                for album in albums {
+                    let last_sync = album
+                        .last_sync
+                        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| "-".to_string());
+                    text.push_str(&format!(
+                        "\n{:<9} {:>7}  {:<20} {:<36}  {}",
+                        if album.tracked {
+                            "tracked"
+                        } else {
+                            "untracked"
+                        },
+                        album.asset_count,
+                        last_sync,
+                        album.id,
+                        album.name
+                    ));
+                }
+                let untracked = albums.iter().filter(|a| !a.tracked).count();
+                text.push_str(&format!(
+                    "\n{} albums on the server, {} not synced yet",
+                    albums.len(),
+                    untracked
+                ));
+                text
+            });
+        }
+
+        Commands::Status => {
+            let status = Status {
+                albums: Album::get_all(&pool).await?.len(),
+                images: SyncedImage::counts(&pool).await?,
+            };
+            report(format, &status, |s| {
+                format!(
+                    "{} albums, {} images: {} downloaded, {} converted, {} pending, {} failed, {} unsupported",
+                    s.albums,
+                    s.images.images,
+                    s.images.downloaded,
+                    s.images.converted,
+                    s.images.pending,
+                    s.images.failed,
+                    s.images.unsupported
+                )
+            });
+        }
+
+        Commands::Sync {
+            metadata_only: true,
+            ..
+        } => {
+            client.ensure_supported_api().await?;
             let sync_service = SyncService::new(client, pool, config);
-            let result = sync_service.sync_all().await?;
-            println!(
-                "Sync complete: {} downloaded, {} skipped, {} failed",
-                result.downloaded, result.skipped, result.failed
-            );
+            let result = sync_service.sync_metadata().await?;
+            report(format, &result, |r| {
+                format!(
+                    "Metadata refresh complete: {} updated, {} unchanged",
+                    r.updated, r.unchanged
+                )
+            });
         }
 
-        Commands::Convert => {
-            let converter = AvifConverter::new(pool, config);
-            let result = converter.convert_all().await?;
-            println!(
-                "Conversion complete: {} converted, {} skipped, {} failed",
-                result.converted, result.skipped, result.failed
-            );
+        Commands::Sync {
+            metadata_only: false,
+            since,
+            album,
+        } => {
+            client.ensure_supported_api().await?;
+            let started_at = chrono::Utc::now();
+            let sync_service = SyncService::new(client, pool, config)
+                .with_progress_bar(show_progress)
+                .with_since(since);
+            let result = sync_service.sync_all(album.as_deref()).await?;
+            report(format, &result, |r| {
+                format!(
+                    "Sync complete: {} downloaded, {} skipped, {} unsupported, {} failed",
+                    r.downloaded, r.skipped, r.unsupported, r.failed
+                )
+            });
+            let _ = notifier
+                .completed(&CompletionEvent::finished(
+                    "sync",
+                    started_at,
+                    Some(&result),
+                    None,
+                ))
+                .await;
+        }
+
+        Commands::Convert {
+            dry_run: true,
+            force,
+            album,
+            ..
+        } => {
+            let converter = AvifConverter::new(pool, config).with_force(force);
+            let result = converter.estimate_all(album.as_deref()).await?;
+            report(format, &result, |r| {
+                format!(
+                    "Dry run: {} images ({} failed), {:.1} MB originals -> {:.1} MB AVIF + {:.1} MB thumbnails ({:.0}% of original size)",
+                    r.images,
+                    r.failed,
+                    r.source_bytes as f64 / 1_048_576.0,
+                    r.avif_bytes as f64 / 1_048_576.0,
+                    r.thumbnail_bytes as f64 / 1_048_576.0,
+                    r.compression_ratio() * 100.0
+                )
+            });
+        }
+
+        Commands::Convert {
+            dry_run: false,
+            force,
+            album,
+            fetch_missing,
+        } => {
+            let started_at = chrono::Utc::now();
+            let mut converter = AvifConverter::new(pool, config)
+                .with_progress_bar(show_progress)
+                .with_force(force);
+            if fetch_missing {
+                client.ensure_supported_api().await?;
+                converter = converter.with_fetch_missing(client);
+            }
+            let result = match &album {
+                Some(album_id) => converter.convert_album(album_id).await?,
+                None => converter.convert_all().await?,
+            };
+            report(format, &result, |r| {
+                format!(
+                    "Conversion complete: {} converted, {} skipped, {} unsupported, {} failed",
+                    r.converted, r.skipped, r.unsupported, r.failed
+                )
+            });
+            let _ = notifier
+                .completed(&CompletionEvent::finished(
+                    "convert",
+                    started_at,
+                    None,
+                    Some(&result),
+                ))
+                .await;
         }
 
         Commands::Serve => {
-            serve(pool, config).await?;
+            let converter = Arc::new(AvifConverter::new(pool, config.clone()));
+            serve_watching(config, converter).await?;
         }
 
         Commands::Run => {
+            client.ensure_supported_api().await?;
             info!("Starting sync...");
-            let sync_service = SyncService::new(client, pool.clone(), config.clone());
-            let sync_result = sync_service.sync_all().await?;
+            let started_at = chrono::Utc::now();
+            let sync_service = SyncService::new(client, pool.clone(), config.clone())
+                .with_progress_bar(show_progress);
+            let sync_result = sync_service.sync_all(None).await?;
             info!(
                 "Sync complete: {} downloaded, {} skipped",
                 sync_result.downloaded, sync_result.skipped
             );
 
             info!("Starting conversion...");
-            let converter = AvifConverter::new(pool.clone(), config.clone());
+            let converter =
+                AvifConverter::new(pool.clone(), config.clone()).with_progress_bar(show_progress);
             let convert_result = converter.convert_all().await?;
             info!(
                 "Conversion complete: {} converted, {} skipped",
                 convert_result.converted, convert_result.skipped
             );
+            // Delivered in the background while the server starts
+            notifier.completed(&CompletionEvent::finished(
+                "run",
+                started_at,
+                Some(&sync_result),
+                Some(&convert_result),
+            ));
 
             info!("Starting server...");
-            serve(pool, config).await?;
+            serve_watching(config, Arc::new(converter.with_progress_bar(false))).await?;
+        }
+
+        Commands::Daemon => {
+            let schedule = config.sync.schedule.clone().ok_or_else(|| {
+                anyhow::anyhow!("The daemon needs a sync schedule (sync.schedule or SYNC_SCHEDULE)")
+            })?;
+            client.ensure_supported_api().await?;
+
+            let sync_service = SyncService::new(client, pool.clone(), config.clone());
+            // Shared with the server and the file watcher, so none of them converts an
+            // image another is already converting
+            let converter = Arc::new(AvifConverter::new(pool.clone(), config.clone()));
+            // Polled alongside the server rather than spawned: the sync and convert
+            // futures are not provably `Send` to the compiler
+            tokio::select! {
+                result = serve_watching(config, converter.clone()) => result?,
+                () = run_schedule(schedule, sync_service, converter, notifier) => {}
+            }
         }
 
-        Commands::Reindex => {
+        Commands::Clean => {
+            let result = storage::collect_garbage(&pool, &config).await?;
+            report(format, &result, |r| {
+                format!(
+                    "Clean complete: {} unreferenced blobs removed, {:.1} MB reclaimed",
+                    r.blobs,
+                    r.bytes as f64 / 1_048_576.0
+                )
+            });
+        }
+
+        Commands::EmptyTrash { expired } => {
+            let result = if expired {
+                storage::expire_trash(&pool, &config).await?
+            } else {
+                storage::empty_trash(&pool, &config, None).await?
+            };
+            report(format, &result, |r| {
+                format!(
+                    "Trash emptied: {} files deleted, {:.1} MB reclaimed",
+                    r.files,
+                    r.bytes as f64 / 1_048_576.0
+                )
+            });
+        }
+
+        Commands::Manifest { album } => {
+            let converter = AvifConverter::new(pool, config);
+            let result = converter.write_manifests(album.as_deref()).await?;
+            report(format, &result, |r| {
+                format!(
+                    "Wrote manifests for {} albums listing {} images",
+                    r.albums, r.images
+                )
+            });
+        }
+
+        Commands::Export { output } => {
+            let backup = db::backup::export(&pool).await?;
+            tokio::fs::write(&output, serde_json::to_vec(&backup)?)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to write {:?}: {}", output, e))?;
+            report(format, &backup.counts(), |r| {
+                format!(
+                    "Exported {} albums, {} images and {} album memberships to {}",
+                    r.albums,
+                    r.images,
+                    r.album_members,
+                    output.display()
+                )
+            });
+        }
+
+        Commands::Import { input } => {
+            let data = tokio::fs::read(&input)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", input, e))?;
+            let backup: db::backup::Backup = serde_json::from_slice(&data)
+                .map_err(|e| anyhow::anyhow!("Invalid export file {:?}: {}", input, e))?;
+            db::backup::import(&pool, &backup).await?;
+            report(format, &backup.counts(), |r| {
+                format!(
+                    "Imported {} albums, {} images and {} album memberships",
+                    r.albums, r.images, r.album_members
+                )
+            });
+        }
+
+        Commands::Verify => {
+            let converter = AvifConverter::new(pool, config);
+            let result = converter.verify_all().await?;
+            report(format, &result, |r| {
+                format!(
+                    "Verification complete: {} checked, {} invalid (will be reconverted on next convert)",
+                    r.checked, r.invalid
+                )
+            });
+        }
+
+        Commands::Reindex {
+            album: Some(album_id),
+        } => {
+            let album = Album::get_by_id(&pool, &album_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Album {} has not been synced", album_id))?;
+            info!("Starting reindex of album '{}'...", album.name);
+
+            // Step 1: Delete the album's AVIFs, thumbnails, variants and sidecars. Files may live
+            // outside the album subdirectory depending on the path template.
+            let images = SyncedImage::get_by_album(&pool, &album_id).await?;
+            for image in &images {
+                if let Some(avif) = &image.avif_path {
+                    let sidecar =
+                        storage::sidecar_path(&config, std::path::Path::new(avif), &image.id);
+                    let _ = tokio::fs::remove_file(&sidecar).await;
+                }
+            }
+            for path in images.iter().flat_map(|image| {
+                [image.avif_path.clone(), image.thumbnail_path.clone()]
+                    .into_iter()
+                    .flatten()
+                    .chain(image.variant_paths().into_values())
+            }) {
+                if let Err(e) = storage::remove_output(&config, &path).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        warn!("Failed to delete {}: {}", path, e);
+                    }
+                }
+            }
+            // Images that moved to another album may still keep their AVIFs in here
+            storage::remove_empty_dirs(&config.avif_path().join(&album_id)).await;
+
+            // Step 2: Clear the album's conversion data in the database
+            let cleared = SyncedImage::clear_conversions_for_album(&pool, &album_id).await?;
+            info!("Cleared conversion data for {} images", cleared);
+
+            // Step 3: Reconvert the album
+            let converter = AvifConverter::new(pool, config).with_progress_bar(show_progress);
+            let result = converter.convert_album(&album_id).await?;
+            report(format, &result, |r| {
+                format!(
+                    "Reindex of '{}' complete: {} converted, {} skipped, {} failed",
+                    album.name, r.converted, r.skipped, r.failed
+                )
+            });
+        }
+
+        Commands::Reindex { album: None } => {
             info!("Starting reindex...");
 
             // Step 1: Delete all AVIF files from disk
@@ -142,32 +667,170 @@ async fn main() -> Result<()> {
 
             // Step 3: Re-run conversion
             info!("Starting conversion...");
-            let converter = AvifConverter::new(pool, config);
+            let converter = AvifConverter::new(pool, config).with_progress_bar(show_progress);
             let result = converter.convert_all().await?;
-            println!(
-                "Reindex complete: {} converted, {} skipped, {} failed",
-                result.converted, result.skipped, result.failed
-            );
+            report(format, &result, |r| {
+                format!(
+                    "Reindex complete: {} converted, {} skipped, {} failed",
+                    r.converted, r.skipped, r.failed
+                )
+            });
         }
     }
 
     Ok(())
 }
 
-async fn serve(pool: sqlx::SqlitePool, config: Config) -> Result<()> {
+/// Run sync + convert each time the schedule comes due. A failed run is logged and
+/// the next one happens at the following scheduled time. Never returns, so the
+/// server keeps running even once a schedule has no runs left.
+async fn run_schedule(
+    schedule: SyncSchedule,
+    sync_service: SyncService,
+    converter: Arc<AvifConverter>,
+    notifier: Notifier,
+) {
+    loop {
+        let Some(next) = schedule.next_after(chrono::Local::now()) else {
+            warn!("Sync schedule '{}' has no upcoming runs", schedule);
+            return std::future::pending().await;
+        };
+        info!(
+            "Next scheduled sync at {}",
+            next.format("%Y-%m-%d %H:%M:%S %:z")
+        );
+        let wait = (next - chrono::Local::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let started_at = chrono::Utc::now();
+        let sync_result = match sync_service.sync_all(None).await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("Scheduled sync failed: {}", e);
+                continue;
+            }
+        };
+        let convert_result = match converter.convert_all().await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("Scheduled conversion failed: {}", e);
+                continue;
+            }
+        };
+        info!(
+            "Scheduled run complete: {} downloaded, {} converted, {} failed",
+            sync_result.downloaded,
+            convert_result.converted,
+            sync_result.failed + convert_result.failed
+        );
+        notifier.completed(&CompletionEvent::finished(
+            "scheduled",
+            started_at,
+            Some(&sync_result),
+            Some(&convert_result),
+        ));
+    }
+}
+
+/// `serve`, also converting new originals as they land when `sync.watch_originals`
+/// is set. The server and the watcher both go through `converter` and its pool.
+async fn serve_watching(config: Config, converter: Arc<AvifConverter>) -> Result<()> {
+    if !config.sync.watch_originals {
+        return serve(config, converter).await;
+    }
+
+    let dir = config.original_path();
+    tokio::select! {
+        result = serve(config, converter.clone()) => result,
+        () = watch::run(&converter, dir) => Ok(()),
+    }
+}
+
+async fn serve(config: Config, converter: Arc<AvifConverter>) -> Result<()> {
+    // The converter's, so a reconnect also reaches conversions the server starts
+    let shared_pool = converter.shared_pool();
+    if config.server.db_health_check_interval_secs > 0 {
+        health::spawn_health_check(
+            shared_pool.clone(),
+            &config,
+            Duration::from_secs(config.server.db_health_check_interval_secs),
+        );
+    }
+
+    let integrity_cache = IntegrityCache::default();
+    if config.server.integrity_check_interval_secs > 0 {
+        integrity::spawn_refresh_task(
+            shared_pool.clone(),
+            integrity_cache.clone(),
+            Duration::from_secs(config.server.integrity_check_interval_secs),
+        );
+    }
+
+    let tls = match (&config.server.tls_cert_path, &config.server.tls_key_path) {
+        (Some(cert), Some(key)) => Some(server::tls::load_config(cert, key, config.server.http2)?),
+        (None, None) => None,
+        _ => anyhow::bail!("TLS needs both a certificate and a key path"),
+    };
+
+    let not_found_image = config
+        .server
+        .not_found_image
+        .as_deref()
+        .map(|path| NotFoundImage::load(path, config.server.not_found_status))
+        .transpose()?;
+    let response_headers = ResponseHeaders::load(&config.server.response_headers)?;
+
     let state = AppState {
-        pool,
+        pool: shared_pool,
         avif_path: config.avif_path(),
+        integrity: integrity_cache,
+        serve_originals: config.server.serve_originals,
+        api_key: config.server.api_key.clone(),
+        converter,
+        not_found_image,
+        pending_placeholder: config.server.pending_placeholder,
+        allowed_origins: config.server.allowed_origins.clone(),
+        response_headers,
+        album_separator: config.storage.split_album_name_on.clone(),
+        rate_limiter: (config.server.rate_limit_per_second > 0.0).then(|| {
+            Arc::new(RateLimiter::new(
+                config.server.rate_limit_per_second,
+                config.server.rate_limit_burst,
+                config.server.trust_forwarded_for,
+            ))
+        }),
     };
 
     let app = create_router(state);
+    let app = if config.server.http2 {
+        app
+    } else {
+        app.layer(axum::middleware::from_fn(server::http::reject_http2))
+    };
     let addr = format!("{}:{}", config.server.host, config.server.port);
 
-    info!("Starting server on http://{}", addr);
-    println!("Server running at http://{}", addr);
+    let socket_addr = tokio::net::lookup_host(&addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Cannot resolve {}", addr))?;
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    let Some(tls) = tls else {
+        info!("Starting server on http://{}", addr);
+        println!("Server running at http://{}", addr);
+
+        let mut server = axum_server::bind(socket_addr);
+        server::http::configure(server.http_builder(), &config.server);
+        server.serve(service).await?;
+        return Ok(());
+    };
+
+    info!("Starting server on https://{}", addr);
+    println!("Server running at https://{}", addr);
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    let mut server = axum_server::bind_rustls(socket_addr, tls);
+    server::http::configure(server.http_builder(), &config.server);
+    server.serve(service).await?;
 
     Ok(())
 }
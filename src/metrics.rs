@@ -0,0 +1,84 @@
+//! Push-based metrics. With the `statsd` feature, counters and timers are sent over
+//! UDP to `metrics.statsd_endpoint`; otherwise every call is a no-op.
+
+use crate::config::MetricsConfig;
+use std::time::Duration;
+
+#[cfg(feature = "statsd")]
+mod statsd {
+    use crate::config::MetricsConfig;
+    use anyhow::{Context, Result};
+    use cadence::{Counted, QueuingMetricSink, StatsdClient, Timed, UdpMetricSink};
+    use std::net::UdpSocket;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+    use tracing::{debug, info};
+
+    static CLIENT: OnceLock<StatsdClient> = OnceLock::new();
+
+    pub fn init(config: &MetricsConfig) -> Result<()> {
+        let Some(endpoint) = &config.statsd_endpoint else {
+            return Ok(());
+        };
+
+        let socket = UdpSocket::bind("0.0.0.0:0").context("Failed to bind StatsD socket")?;
+        socket.set_nonblocking(true)?;
+        let sink = UdpMetricSink::from(endpoint.as_str(), socket)
+            .context(format!("Invalid StatsD endpoint: {}", endpoint))?;
+        let client = StatsdClient::from_sink(&config.prefix, QueuingMetricSink::from(sink));
+
+        let _ = CLIENT.set(client);
+        info!("Sending StatsD metrics to {} ({})", endpoint, config.prefix);
+        Ok(())
+    }
+
+    pub fn count(key: &str, value: u64) {
+        if let Some(client) = CLIENT.get() {
+            if let Err(e) = client.count(key, value) {
+                debug!("Failed to send metric {}: {}", key, e);
+            }
+        }
+    }
+
+    pub fn timing(key: &str, duration: Duration) {
+        if let Some(client) = CLIENT.get() {
+            if let Err(e) = client.time(key, duration) {
+                debug!("Failed to send metric {}: {}", key, e);
+            }
+        }
+    }
+}
+
+/// Set up the exporter. Must be called once at startup, before any metric is sent.
+pub fn init(config: &MetricsConfig) -> anyhow::Result<()> {
+    #[cfg(feature = "statsd")]
+    {
+        statsd::init(config)
+    }
+
+    #[cfg(not(feature = "statsd"))]
+    {
+        if config.statsd_endpoint.is_some() {
+            tracing::warn!(
+                "metrics.statsd_endpoint is set but metrics support is not compiled in (rebuild with `--features statsd`)"
+            );
+        }
+        Ok(())
+    }
+}
+
+pub fn incr(key: &str) {
+    count(key, 1);
+}
+
+#[allow(unused_variables)]
+pub fn count(key: &str, value: u64) {
+    #[cfg(feature = "statsd")]
+    statsd::count(key, value);
+}
+
+#[allow(unused_variables)]
+pub fn timing(key: &str, duration: Duration) {
+    #[cfg(feature = "statsd")]
+    statsd::timing(key, duration);
+}
@@ -0,0 +1,131 @@
+//! Completion webhook, so external systems can react when new images are available.
+
+use crate::config::NotificationsConfig;
+use crate::converter::ConversionResult;
+use crate::sync::SyncResult;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Delivery attempts before giving up on a webhook
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Per-attempt timeout, kept short so a dead endpoint never holds up a run
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Header carrying `sha256=<hex HMAC of the body>` when a secret is configured
+const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+#[derive(Debug, Serialize)]
+pub struct CompletionEvent<'a> {
+    /// `sync`, `convert` or `run`
+    pub event: &'static str,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync: Option<&'a SyncResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<&'a ConversionResult>,
+}
+
+impl<'a> CompletionEvent<'a> {
+    /// An event for a run that started at `started_at` and finished now
+    pub fn finished(
+        event: &'static str,
+        started_at: DateTime<Utc>,
+        sync: Option<&'a SyncResult>,
+        conversion: Option<&'a ConversionResult>,
+    ) -> Self {
+        let finished_at = Utc::now();
+        Self {
+            event,
+            started_at,
+            finished_at,
+            duration_secs: (finished_at - started_at).num_milliseconds() as f64 / 1000.0,
+            sync,
+            conversion,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Notifier {
+    client: reqwest::Client,
+    url: Option<String>,
+    secret: Option<String>,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationsConfig) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            url: config.completion_webhook_url.clone(),
+            secret: config.webhook_secret.clone(),
+        }
+    }
+
+    /// POST the event to the completion webhook, if configured, in the background.
+    /// Failures are retried with a short backoff and then logged; they never fail
+    /// the caller. One-shot commands await the handle so the process does not exit
+    /// before delivery.
+    pub fn completed(&self, event: &CompletionEvent<'_>) -> JoinHandle<()> {
+        let Some(url) = self.url.clone() else {
+            return tokio::spawn(async {});
+        };
+
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to serialize webhook payload: {}", e);
+                return tokio::spawn(async {});
+            }
+        };
+        let signature = self.secret.as_deref().map(|secret| sign(secret, &body));
+        let client = self.client.clone();
+        let name = event.event;
+
+        tokio::spawn(async move {
+            for attempt in 1..=MAX_ATTEMPTS {
+                let mut request = client
+                    .post(&url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone());
+                if let Some(signature) = &signature {
+                    request = request.header(SIGNATURE_HEADER, signature);
+                }
+
+                match request.send().await.and_then(|r| r.error_for_status()) {
+                    Ok(_) => {
+                        info!("Sent {} completion webhook", name);
+                        return;
+                    }
+                    Err(e) if attempt < MAX_ATTEMPTS => {
+                        debug!("Webhook attempt {} failed: {}", attempt, e);
+                        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Giving up on {} completion webhook after {} attempts: {}",
+                            name, MAX_ATTEMPTS, e
+                        );
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,82 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Aim for roughly this many progress lines per run, whatever its size
+const LOG_STEPS: usize = 20;
+
+/// Never log less often than every this many items
+const MAX_LOG_INTERVAL: usize = 500;
+
+/// Progress of a long batch, logged every few percent so it shows up in non-TTY
+/// logs, or drawn as a bar when one was requested and stderr is a terminal
+pub struct Progress {
+    label: &'static str,
+    total: usize,
+    done: AtomicUsize,
+    log_interval: usize,
+    started: Instant,
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// `label` completes the sentence "X/Y <label>", e.g. "converted"
+    pub fn new(label: &'static str, total: usize, show_bar: bool) -> Self {
+        let bar = (show_bar && std::io::stderr().is_terminal()).then(|| {
+            let bar = ProgressBar::new(total as u64);
+            bar.set_style(
+                ProgressStyle::with_template("{bar:40} {pos}/{len} {msg} ({percent}%, ETA {eta})")
+                    .expect("valid progress template"),
+            );
+            bar.set_message(label);
+            bar
+        });
+
+        Self {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+            log_interval: (total / LOG_STEPS).clamp(1, MAX_LOG_INTERVAL),
+            started: Instant::now(),
+            bar,
+        }
+    }
+
+    /// Record one finished item, whatever its outcome
+    pub fn inc(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+            if done == self.total {
+                bar.finish_and_clear();
+            }
+            return;
+        }
+
+        if done.is_multiple_of(self.log_interval) || done == self.total {
+            let elapsed = self.started.elapsed();
+            let eta = elapsed.mul_f64((self.total - done) as f64 / done as f64);
+            info!(
+                "{}/{} {} ({:.0}%), elapsed {}, ETA {}",
+                done,
+                self.total,
+                self.label,
+                done as f64 * 100.0 / self.total as f64,
+                format_duration(elapsed),
+                format_duration(eta)
+            );
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
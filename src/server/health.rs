@@ -0,0 +1,57 @@
+use crate::config::Config;
+use crate::db;
+use arc_swap::ArcSwap;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Consecutive failed checks before the pool is rebuilt
+const FAILURES_BEFORE_RECONNECT: u32 = 3;
+
+/// Database pool shared by the handlers, replaced when the health check reconnects
+pub type SharedPool = Arc<ArcSwap<SqlitePool>>;
+
+/// Probe the database every `interval` and recreate the pool after repeated failures,
+/// e.g. once the SQLite file has been replaced by a restore
+pub fn spawn_health_check(pool: SharedPool, config: &Config, interval: Duration) {
+    let db_path = config.db_path();
+    let journal_mode = config.storage.db_journal_mode;
+    let busy_timeout = Duration::from_millis(config.storage.db_busy_timeout_ms);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut failures = 0;
+        loop {
+            ticker.tick().await;
+            // Reads a page, so an unreadable file fails and not only a dead connection
+            let result = sqlx::query("SELECT 1 FROM sqlite_master LIMIT 1")
+                .execute(&*pool.load_full())
+                .await;
+            let Err(e) = result else {
+                failures = 0;
+                continue;
+            };
+
+            failures += 1;
+            warn!(
+                "Database health check failed ({}/{}): {}",
+                failures, FAILURES_BEFORE_RECONNECT, e
+            );
+            if failures < FAILURES_BEFORE_RECONNECT {
+                continue;
+            }
+
+            match db::create_pool(&db_path, journal_mode, busy_timeout).await {
+                Ok(new_pool) => {
+                    let old = pool.swap(Arc::new(new_pool));
+                    failures = 0;
+                    info!("Reconnected to the database at {:?}", db_path);
+                    // Requests still holding the old pool finish before it closes
+                    tokio::spawn(async move { old.close().await });
+                }
+                Err(e) => warn!("Failed to reconnect to the database: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,39 @@
+use crate::config::ServerConfig;
+use axum::extract::Request;
+use axum::http::{StatusCode, Version};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use std::time::Duration;
+
+/// Apply the connection settings from `[server]` to the HTTP/1.1 and HTTP/2
+/// protocol builder. Over TLS, HTTP/2 is offered through ALPN, see
+/// `tls::load_config`; with `http2` off, plain-text HTTP/2 is refused as well.
+pub fn configure(builder: &mut Builder<TokioExecutor>, config: &ServerConfig) {
+    if !config.http2 {
+        // axum-server serves through the upgrade-capable path, which still sniffs
+        // the protocol; `reject_http2` turns away what gets past this
+        *builder = builder.clone().http1_only();
+    }
+    builder.http1().keep_alive(config.keep_alive);
+
+    let mut http2 = builder.http2();
+    http2.max_concurrent_streams(config.http2_max_concurrent_streams);
+    if config.http2_keep_alive_interval_secs > 0 {
+        // Pings are scheduled on the timer, which hyper has no default for
+        http2
+            .timer(TokioTimer::new())
+            .keep_alive_interval(Duration::from_secs(config.http2_keep_alive_interval_secs))
+            .keep_alive_timeout(Duration::from_secs(config.http2_keep_alive_timeout_secs));
+    }
+}
+
+/// Middleware answering `505 HTTP Version Not Supported` to HTTP/2 requests, for
+/// `http2 = false`
+pub async fn reject_http2(request: Request, next: Next) -> Response {
+    if request.version() == Version::HTTP_2 {
+        return (StatusCode::HTTP_VERSION_NOT_SUPPORTED, "HTTP/2 is disabled").into_response();
+    }
+    next.run(request).await
+}
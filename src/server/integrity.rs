@@ -0,0 +1,96 @@
+use super::health::SharedPool;
+use crate::db::models::SyncedImage;
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// Maximum number of offending image IDs listed per category in a report
+const MAX_REPORTED_IDS: usize = 100;
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct IntegrityReport {
+    pub checked_at: String,
+    pub images_checked: usize,
+    pub missing_originals: usize,
+    pub missing_avifs: usize,
+    pub missing_original_ids: Vec<String>,
+    pub missing_avif_ids: Vec<String>,
+}
+
+/// Last integrity report, shared between the HTTP handlers and the refresh task
+pub type IntegrityCache = Arc<RwLock<Option<IntegrityReport>>>;
+
+/// Check that every original and AVIF referenced by the database exists on disk
+pub async fn check(pool: &SqlitePool) -> Result<IntegrityReport> {
+    let images = SyncedImage::get_with_files(pool).await?;
+
+    let mut report = IntegrityReport {
+        checked_at: chrono::Utc::now().to_rfc3339(),
+        images_checked: images.len(),
+        missing_originals: 0,
+        missing_avifs: 0,
+        missing_original_ids: Vec::new(),
+        missing_avif_ids: Vec::new(),
+    };
+
+    for image in images {
+        if let Some(path) = &image.original_path {
+            if !file_exists(path).await {
+                report.missing_originals += 1;
+                if report.missing_original_ids.len() < MAX_REPORTED_IDS {
+                    report.missing_original_ids.push(image.id.clone());
+                }
+            }
+        }
+
+        let mut avif_missing = false;
+        for path in [&image.avif_path, &image.thumbnail_path]
+            .into_iter()
+            .flatten()
+        {
+            if !file_exists(path).await {
+                avif_missing = true;
+            }
+        }
+        if avif_missing {
+            report.missing_avifs += 1;
+            if report.missing_avif_ids.len() < MAX_REPORTED_IDS {
+                report.missing_avif_ids.push(image.id);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Run a check and store the result in the cache
+pub async fn refresh(pool: &SqlitePool, cache: &IntegrityCache) -> Result<IntegrityReport> {
+    let report = check(pool).await?;
+    info!(
+        "Integrity check: {} images, {} missing originals, {} missing AVIFs",
+        report.images_checked, report.missing_originals, report.missing_avifs
+    );
+    *cache.write().await = Some(report.clone());
+    Ok(report)
+}
+
+/// Refresh the cached report every `interval` in the background
+pub fn spawn_refresh_task(pool: SharedPool, cache: IntegrityCache, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = refresh(&pool.load_full(), &cache).await {
+                warn!("Integrity check failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn file_exists(path: &str) -> bool {
+    tokio::fs::try_exists(path).await.unwrap_or(false)
+}
@@ -1,3 +1,8 @@
+pub mod health;
+pub mod http;
+pub mod integrity;
+pub mod rate_limit;
 pub mod routes;
+pub mod tls;
 
 pub use routes::{create_router, AppState};
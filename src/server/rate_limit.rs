@@ -0,0 +1,138 @@
+use super::routes::AppError;
+use crate::metrics;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often full buckets, which are indistinguishable from unseen clients, are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Most clients tracked at once; past it the least recently seen one is dropped
+const MAX_CLIENTS: usize = 10_000;
+
+/// Per-client token buckets: each request takes a token, and tokens come back at
+/// `per_second` up to `burst`
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    trust_forwarded_for: bool,
+    clients: Mutex<Clients>,
+}
+
+struct Clients {
+    buckets: HashMap<IpAddr, Bucket>,
+    pruned: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64, burst: u32, trust_forwarded_for: bool) -> Self {
+        Self {
+            per_second,
+            burst: f64::from(burst.max(1)),
+            trust_forwarded_for,
+            clients: Mutex::new(Clients {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token for `client`, or return the whole seconds until one is available
+    fn acquire(&self, client: IpAddr) -> Result<(), u64> {
+        let client = bucket_key(client);
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        let Clients { buckets, pruned } = &mut *clients;
+        if now.duration_since(*pruned) >= PRUNE_INTERVAL {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+            *pruned = now;
+        }
+        if buckets.len() >= MAX_CLIENTS && !buckets.contains_key(&client) {
+            let oldest = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.updated)
+                .map(|(ip, _)| *ip);
+            if let Some(oldest) = oldest {
+                buckets.remove(&oldest);
+            }
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(((1.0 - bucket.tokens) / self.per_second).ceil() as u64)
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+
+    /// The peer address, or with `trust_forwarded_for` the last `X-Forwarded-For`
+    /// entry, which is the one added by the reverse proxy in front of this server
+    fn client_ip(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+        if self.trust_forwarded_for {
+            let forwarded = headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .filter_map(|ip| ip.trim().parse().ok())
+                .next_back();
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+        peer
+    }
+}
+
+/// IPv4 addresses as they are, IPv6 ones by their /64, which a single host
+/// usually has all of
+fn bucket_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & (u128::MAX << 64))),
+        },
+    }
+}
+
+/// Middleware answering `429 Too Many Requests` once a client is over its limit
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(client) = limiter.client_ip(request.headers(), peer) {
+        if let Err(retry_after) = limiter.acquire(client) {
+            metrics::incr("requests.rate_limited");
+            return AppError::TooManyRequests(retry_after).into_response();
+        }
+    }
+    next.run(request).await
+}
@@ -1,48 +1,354 @@
-use crate::db::models::{Album, SyncedImage};
+use super::health::SharedPool;
+use super::integrity::{self, IntegrityCache, IntegrityReport};
+use super::rate_limit::{self, RateLimiter};
+use crate::config::split_album_name;
+use crate::converter::mosaic::{self, MosaicFormat};
+use crate::converter::AvifConverter;
+use crate::db::models::{
+    Album, AlbumProgress, AlbumSizeStats, AlbumSort, ConversionLogEntry, ConversionStats,
+    ImageCounts, ImageSort, SizeStats, SyncedImage,
+};
+use crate::encryption;
+use crate::metrics;
 use axum::{
-    extract::{Path, Query, State},
-    http::{header, StatusCode},
+    body::{Body, Bytes},
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post},
     Json, Router,
 };
+use chrono::{DateTime, NaiveDateTime, SubsecRound, Utc};
 use serde::Deserialize;
 use serde::Serialize;
 use sqlx::SqlitePool;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
+use tower::ServiceExt;
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::error;
+use tower_http::services::ServeFile;
+use tracing::{error, warn};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
 
 #[derive(Clone)]
 pub struct AppState {
-    pub pool: SqlitePool,
+    pub pool: SharedPool,
     pub avif_path: PathBuf,
+    pub integrity: IntegrityCache,
+    pub serve_originals: bool,
+    pub api_key: Option<String>,
+    pub converter: Arc<AvifConverter>,
+    pub not_found_image: Option<NotFoundImage>,
+    /// Default for `?placeholder=`: generated stand-ins for unconverted images
+    pub pending_placeholder: bool,
+    pub allowed_origins: Vec<String>,
+    pub response_headers: ResponseHeaders,
+    /// `split_album_name_on`, for album paths in listings
+    pub album_separator: Option<String>,
+    /// Applied to the image-serving routes when `rate_limit_per_second` is set
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl AppState {
+    /// Current database pool; held for one query so a reconnect takes effect promptly
+    fn pool(&self) -> Arc<SqlitePool> {
+        self.pool.load_full()
+    }
+}
+
+/// Placeholder served in place of missing or not-yet-converted images
+#[derive(Clone)]
+pub struct NotFoundImage {
+    bytes: Bytes,
+    content_type: &'static str,
+    status: StatusCode,
+}
+
+impl NotFoundImage {
+    pub fn load(path: &std::path::Path, status: u16) -> anyhow::Result<Self> {
+        let status = match StatusCode::from_u16(status)? {
+            s @ (StatusCode::NOT_FOUND | StatusCode::ACCEPTED) => s,
+            other => anyhow::bail!("not_found_status must be 404 or 202, got {}", other),
+        };
+        let bytes = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read not_found_image {:?}: {}", path, e))?;
+
+        Ok(Self {
+            bytes: Bytes::from(bytes),
+            content_type: content_type_for(path),
+            status,
+        })
+    }
+}
+
+/// Headers that only concern a single connection, which a proxy would drop or
+/// act on itself; `content-length` is set from the body
+const RESERVED_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// `server.response_headers`, validated once at startup
+#[derive(Clone, Default)]
+pub struct ResponseHeaders(Vec<(HeaderName, String)>);
+
+impl ResponseHeaders {
+    pub fn load(headers: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let mut parsed = Vec::with_capacity(headers.len());
+        for (name, value) in headers {
+            let header = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| anyhow::anyhow!("Invalid response header name '{}': {}", name, e))?;
+            if RESERVED_HEADERS.contains(&header.as_str()) {
+                anyhow::bail!("Response header '{}' cannot be overridden", name);
+            }
+            // Image IDs are plain ASCII, so a value valid with a sample ID stays valid
+            HeaderValue::from_str(&value.replace("{id}", "id")).map_err(|e| {
+                anyhow::anyhow!("Invalid value for response header '{}': {}", name, e)
+            })?;
+            parsed.push((header, value.clone()));
+        }
+        Ok(Self(parsed))
+    }
+
+    /// Add the headers to a successful image response, replacing any of the same name
+    fn apply(
+        &self,
+        image_id: &str,
+        result: Result<Response, AppError>,
+    ) -> Result<Response, AppError> {
+        let mut response = result?;
+        for (name, template) in &self.0 {
+            if let Ok(value) = HeaderValue::from_str(&template.replace("{id}", image_id)) {
+                response.headers_mut().insert(name.clone(), value);
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// OpenAPI document for the routes below, served at `/openapi.json`
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "AVIF Generator API"),
+    paths(
+        root,
+        list_albums,
+        album_tree,
+        album_status,
+        get_album,
+        serve_album_cover,
+        serve_album_mosaic,
+        list_failed_images,
+        random_image,
+        serve_image,
+        serve_thumbnail,
+        get_image_metadata,
+        get_image_history,
+        reconvert_image,
+        serve_original,
+        search_images,
+        get_stats,
+        get_integrity,
+    ),
+    components(schemas(
+        AlbumListResponse,
+        AlbumInfo,
+        AlbumFolder,
+        AlbumStatusResponse,
+        AlbumProgress,
+        ImageCounts,
+        ImageListResponse,
+        ImageInfo,
+        PaginationInfo,
+        SearchResponse,
+        SearchResult,
+        FailedImageListResponse,
+        FailedImageInfo,
+        ImageMetadata,
+        ImageHistoryResponse,
+        ConversionLogEntry,
+        ReconvertResponse,
+        ConversionStats,
+        SizeStats,
+        AlbumSizeStats,
+        IntegrityReport,
+        ErrorResponse,
+    )),
+    modifiers(&ApiKeyScheme)
+)]
+struct ApiDoc;
+
+/// Documents the `x-api-key` header checked by `require_api_key`
+struct ApiKeyScheme;
+
+impl Modify for ApiKeyScheme {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+            );
+        }
+    }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Serialize, ToSchema)]
 struct AlbumListResponse {
     albums: Vec<AlbumInfo>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+struct AlbumStatusResponse {
+    albums: Vec<AlbumProgress>,
+}
+
+#[derive(Serialize, ToSchema)]
 struct AlbumInfo {
     id: String,
     name: String,
+    /// Name split on `split_album_name_on`; just the name when that is unset
+    path: Vec<String>,
     image_count: i64,
+    last_sync: Option<String>,
+}
+
+impl AlbumInfo {
+    fn new(album: Album, separator: Option<&str>) -> Self {
+        AlbumInfo {
+            path: split_album_name(&album.name, separator)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            id: album.id,
+            name: album.name,
+            image_count: album.asset_count.unwrap_or(0),
+            last_sync: album.last_sync.map(|d| d.to_rfc3339()),
+        }
+    }
+}
+
+/// A level of the album hierarchy: the albums whose path ends here and the
+/// folders below it
+#[derive(Serialize, ToSchema)]
+struct AlbumFolder {
+    name: String,
+    albums: Vec<AlbumInfo>,
+    folders: Vec<AlbumFolder>,
+}
+
+impl AlbumFolder {
+    fn new(name: &str) -> Self {
+        AlbumFolder {
+            name: name.to_string(),
+            albums: Vec::new(),
+            folders: Vec::new(),
+        }
+    }
+
+    /// Place `album` under the folders named by all but the last part of its path
+    fn insert(&mut self, album: AlbumInfo) {
+        let mut folder = self;
+        for name in &album.path[..album.path.len().saturating_sub(1)] {
+            let index = match folder.folders.iter().position(|f| &f.name == name) {
+                Some(index) => index,
+                None => {
+                    folder.folders.push(AlbumFolder::new(name));
+                    folder.folders.len() - 1
+                }
+            };
+            folder = &mut folder.folders[index];
+        }
+        folder.albums.push(album);
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AlbumListParams {
+    /// `name`, `count` or `last_sync`
+    sort: Option<String>,
+    /// `asc` or `desc`
+    order: Option<String>,
+    /// Case-insensitive album name filter
+    q: Option<String>,
 }
 
 const DEFAULT_PAGE_SIZE: i64 = 20;
 
-#[derive(Deserialize)]
+/// ISO 8601 without an offset, since EXIF capture times carry no time zone
+const TAKEN_AT_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct PaginationParams {
     offset: Option<i64>,
     limit: Option<i64>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ResizeParams {
+    /// Width to resize to, rounded up to a standard width and capped at the
+    /// configured maximum width
+    w: Option<u32>,
+    /// Quality for the resized image, 1-100, rounded to a multiple of 10
+    q: Option<f32>,
+    /// Configured quality variant such as `low`; `full` is the full-size AVIF
+    variant: Option<String>,
+    /// `1` to get a generated placeholder while the image is not converted yet,
+    /// `0` for a 404; defaults to `server.pending_placeholder`
+    placeholder: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PendingParams {
+    /// `1` to get a generated placeholder while the image is not converted yet,
+    /// `0` for a 404; defaults to `server.pending_placeholder`
+    placeholder: Option<String>,
+}
+
+const DEFAULT_MOSAIC_COLS: u32 = 4;
+const DEFAULT_MOSAIC_TILE_SIZE: u32 = 256;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct MosaicParams {
+    /// Tiles per row: 2, 3, 4, 6 or 8 (default 4)
+    cols: Option<u32>,
+    /// Tile width and height in pixels: 128 or 256 (default 256)
+    size: Option<u32>,
+    /// `avif` (default) or `jpeg`
+    format: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RandomParams {
+    /// Only pick from this album
+    album_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
 struct PaginationInfo {
     total: i64,
     offset: i64,
@@ -50,7 +356,7 @@ struct PaginationInfo {
     has_more: bool,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct ImageListResponse {
     album_id: String,
     album_name: String,
@@ -58,15 +364,87 @@ struct ImageListResponse {
     pagination: PaginationInfo,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct ImageInfo {
     id: String,
     filename: String,
     url: String,
     thumbnail_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    placeholder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dominant_color: Option<String>,
+    /// Tiny JPEG `data:` URI for `<img src>` while the thumbnail loads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lqip: Option<String>,
+    /// Capture time from EXIF, without a time zone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    taken_at: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AlbumImageParams {
+    offset: Option<i64>,
+    /// Page size, 1-100 (default 20)
+    limit: Option<i64>,
+    /// `filename` or `taken_at`
+    sort: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchParams {
+    /// Substring of the filename
+    q: String,
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SearchResponse {
+    query: String,
+    images: Vec<SearchResult>,
+    pagination: PaginationInfo,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SearchResult {
+    album_id: String,
+    #[serde(flatten)]
+    image: ImageInfo,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FailedImageListResponse {
+    images: Vec<FailedImageInfo>,
+    pagination: PaginationInfo,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FailedImageInfo {
+    id: String,
+    filename: String,
+    album_id: String,
+    error: Option<String>,
+    attempts: i64,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+struct ReconvertResponse {
+    id: String,
+    avif_file_size: Option<u64>,
+    converted_at: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct IntegrityParams {
+    /// Recheck the disk instead of returning the cached report
+    refresh: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
 struct ImageMetadata {
     id: String,
     filename: String,
@@ -74,41 +452,139 @@ struct ImageMetadata {
     file_size: Option<i64>,
     synced_at: Option<String>,
     converted_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    placeholder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dominant_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lqip: Option<String>,
+}
+
+/// Conversion attempts of one image, newest first (at most the last 100)
+#[derive(Serialize, ToSchema)]
+struct ImageHistoryResponse {
+    id: String,
+    history: Vec<ConversionLogEntry>,
 }
 
 pub fn create_router(state: AppState) -> Router {
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    let cors = cors_layer(&state.allowed_origins);
+
+    // Only the routes that read image files are rate limited
+    let mut images = Router::new()
+        .route("/albums/:album_id/cover", get(serve_album_cover))
+        .route("/albums/:album_id/mosaic", get(serve_album_mosaic))
+        .route("/images/:image_id", get(serve_image))
+        .route("/images/:image_id/thumbnail", get(serve_thumbnail));
+    if state.serve_originals {
+        images = images.route("/images/:image_id/original", get(serve_original));
+    }
+    if let Some(limiter) = state.rate_limiter.clone() {
+        images = images.route_layer(middleware::from_fn_with_state(limiter, rate_limit::limit));
+    }
 
-    Router::new()
+    // JSON responses are compressed when the client accepts br or gzip; image
+    // routes are merged in after the layer, AVIF would not shrink any further
+    let router = Router::new()
         .route("/", get(root))
+        .route("/openapi.json", get(openapi_spec))
         .route("/albums", get(list_albums))
+        .route("/albums/status", get(album_status))
+        .route("/tree", get(album_tree))
         .route("/albums/:album_id", get(get_album))
-        .route("/images/:image_id", get(serve_image))
-        .route("/images/:image_id/thumbnail", get(serve_thumbnail))
+        .route("/images/failed", get(list_failed_images))
+        .route("/images/random", get(random_image))
         .route("/images/:image_id/metadata", get(get_image_metadata))
-        .layer(cors)
-        .with_state(Arc::new(state))
+        .route("/images/:image_id/history", get(get_image_history))
+        .route("/images/:image_id/reconvert", post(reconvert_image))
+        .route("/search", get(search_images))
+        .route("/stats", get(get_stats))
+        .route("/admin/integrity", get(get_integrity))
+        .layer(CompressionLayer::new())
+        .merge(images);
+
+    router.layer(cors).with_state(Arc::new(state))
+}
+
+/// Wide open when no origins are configured, otherwise exactly those origins
+fn cors_layer(allowed_origins: &[String]) -> CorsLayer {
+    if allowed_origins.is_empty() {
+        return CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(Any)
+            .allow_headers(Any);
+    }
+
+    let origins: Vec<HeaderValue> = allowed_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("Ignoring invalid CORS origin: {}", origin);
+                None
+            }
+        })
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::HEAD, Method::POST, Method::OPTIONS])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::RANGE,
+            HeaderName::from_static("x-api-key"),
+        ])
 }
 
+/// Health check
+#[utoipa::path(get, path = "/", responses((status = 200, body = String)))]
 async fn root() -> &'static str {
     "AVIF Generator API"
 }
 
+async fn openapi_spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// List synced albums
+#[utoipa::path(
+    get,
+    path = "/albums",
+    params(AlbumListParams),
+    responses(
+        (status = 200, body = AlbumListResponse),
+        (status = 400, body = ErrorResponse),
+    )
+)]
 async fn list_albums(
     State(state): State<Arc<AppState>>,
+    Query(params): Query<AlbumListParams>,
 ) -> Result<Json<AlbumListResponse>, AppError> {
-    let albums = Album::get_all(&state.pool).await?;
+    let sort: AlbumSort = match params.sort.as_deref() {
+        Some(sort) => sort
+            .parse()
+            .map_err(|e: anyhow::Error| AppError::BadRequest(e.to_string()))?,
+        None => AlbumSort::default(),
+    };
+    let descending = match params.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => {
+            return Err(AppError::BadRequest(format!(
+                "Unknown order '{}' (expected asc or desc)",
+                other
+            )))
+        }
+    };
+    let name_filter = params.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
 
+    let albums = Album::get_all_sorted(&state.pool(), sort, descending, name_filter).await?;
+
+    let separator = state.album_separator.as_deref();
     let album_infos: Vec<AlbumInfo> = albums
         .into_iter()
-        .map(|a| AlbumInfo {
-            id: a.id,
-            name: a.name,
-            image_count: a.asset_count.unwrap_or(0),
-        })
+        .map(|a| AlbumInfo::new(a, separator))
         .collect();
 
     Ok(Json(AlbumListResponse {
@@ -116,20 +592,69 @@ async fn list_albums(
     }))
 }
 
+/// Albums as a folder tree, split on `split_album_name_on`. The root folder has an
+/// empty name; without a separator every album sits directly in it.
+#[utoipa::path(
+    get,
+    path = "/tree",
+    responses((status = 200, body = AlbumFolder))
+)]
+async fn album_tree(State(state): State<Arc<AppState>>) -> Result<Json<AlbumFolder>, AppError> {
+    let albums = Album::get_all_sorted(&state.pool(), AlbumSort::Name, false, None).await?;
+
+    let mut root = AlbumFolder::new("");
+    for album in albums {
+        root.insert(AlbumInfo::new(album, state.album_separator.as_deref()));
+    }
+    Ok(Json(root))
+}
+
+/// Sync time and image counts of every album
+#[utoipa::path(
+    get,
+    path = "/albums/status",
+    responses((status = 200, body = AlbumStatusResponse))
+)]
+async fn album_status(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<AlbumStatusResponse>, AppError> {
+    Ok(Json(AlbumStatusResponse {
+        albums: Album::get_progress(&state.pool()).await?,
+    }))
+}
+
+/// Images in an album, paginated
+#[utoipa::path(
+    get,
+    path = "/albums/{album_id}",
+    params(("album_id" = String, Path, description = "Immich album ID"), AlbumImageParams),
+    responses(
+        (status = 200, body = ImageListResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
 async fn get_album(
     State(state): State<Arc<AppState>>,
     Path(album_id): Path<String>,
-    Query(params): Query<PaginationParams>,
+    Query(params): Query<AlbumImageParams>,
 ) -> Result<Json<ImageListResponse>, AppError> {
-    let album = Album::get_by_id(&state.pool, &album_id)
+    let album = Album::get_by_id(&state.pool(), &album_id)
         .await?
         .ok_or_else(|| AppError::NotFound("Album not found".to_string()))?;
+    let sort: ImageSort = match params.sort.as_deref() {
+        Some(sort) => sort
+            .parse()
+            .map_err(|e: anyhow::Error| AppError::BadRequest(e.to_string()))?,
+        None => ImageSort::default(),
+    };
 
     let offset = params.offset.unwrap_or(0).max(0);
     let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, 100);
 
-    let total = SyncedImage::count_by_album(&state.pool, &album_id).await?;
-    let images = SyncedImage::get_by_album_paginated(&state.pool, &album_id, offset, limit).await?;
+    let total = SyncedImage::count_by_album(&state.pool(), &album_id).await?;
+    let images =
+        SyncedImage::get_by_album_paginated(&state.pool(), &album_id, sort, offset, limit).await?;
 
     let image_infos: Vec<ImageInfo> = images
         .into_iter()
@@ -138,6 +663,10 @@ async fn get_album(
             thumbnail_url: format!("/images/{}/thumbnail", img.id),
             id: img.id,
             filename: img.filename,
+            placeholder: img.placeholder,
+            dominant_color: img.dominant_color,
+            lqip: img.lqip,
+            taken_at: img.taken_at.map(|t| t.format(TAKEN_AT_FORMAT).to_string()),
         })
         .collect();
 
@@ -156,37 +685,418 @@ async fn get_album(
     }))
 }
 
+/// Converted images whose filename contains `q`, paginated
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(SearchParams),
+    responses((status = 200, body = SearchResponse))
+)]
+async fn search_images(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResponse>, AppError> {
+    let offset = params.offset.unwrap_or(0).max(0);
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, 100);
+    let query = params.q.trim().to_string();
+
+    let total = SyncedImage::count_search(&state.pool(), &query).await?;
+    let images = SyncedImage::search(&state.pool(), &query, limit, offset).await?;
+
+    let results: Vec<SearchResult> = images
+        .into_iter()
+        .map(|img| SearchResult {
+            album_id: img.album_id,
+            image: ImageInfo {
+                url: format!("/images/{}", img.id),
+                thumbnail_url: format!("/images/{}/thumbnail", img.id),
+                id: img.id,
+                filename: img.filename,
+                placeholder: img.placeholder,
+                dominant_color: img.dominant_color,
+                lqip: img.lqip,
+                taken_at: img.taken_at.map(|t| t.format(TAKEN_AT_FORMAT).to_string()),
+            },
+        })
+        .collect();
+
+    let has_more = offset + (results.len() as i64) < total;
+
+    Ok(Json(SearchResponse {
+        query,
+        images: results,
+        pagination: PaginationInfo {
+            total,
+            offset,
+            limit,
+            has_more,
+        },
+    }))
+}
+
+/// Images whose last conversion failed, paginated
+#[utoipa::path(
+    get,
+    path = "/images/failed",
+    params(PaginationParams),
+    responses((status = 200, body = FailedImageListResponse))
+)]
+async fn list_failed_images(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PaginationParams>,
+) -> Result<Json<FailedImageListResponse>, AppError> {
+    let offset = params.offset.unwrap_or(0).max(0);
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, 100);
+
+    let total = SyncedImage::count_failed(&state.pool()).await?;
+    let images = SyncedImage::get_failed(&state.pool(), offset, limit).await?;
+
+    let image_infos: Vec<FailedImageInfo> = images
+        .into_iter()
+        .map(|img| FailedImageInfo {
+            id: img.id,
+            filename: img.filename,
+            album_id: img.album_id,
+            error: img.conversion_error,
+            attempts: img.conversion_attempts,
+        })
+        .collect();
+
+    let has_more = offset + (image_infos.len() as i64) < total;
+
+    Ok(Json(FailedImageListResponse {
+        images: image_infos,
+        pagination: PaginationInfo {
+            total,
+            offset,
+            limit,
+            has_more,
+        },
+    }))
+}
+
+/// Redirect to a random converted image, so each image URL stays cacheable
+#[utoipa::path(
+    get,
+    path = "/images/random",
+    params(RandomParams),
+    responses(
+        (status = 302, description = "Redirect to `/images/{image_id}`"),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn random_image(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RandomParams>,
+) -> Result<Response, AppError> {
+    let image = SyncedImage::get_random(&state.pool(), params.album_id.as_deref())
+        .await?
+        .ok_or_else(|| AppError::NotFound("No converted images".to_string()))?;
+
+    Ok((
+        StatusCode::FOUND,
+        [
+            (header::LOCATION, format!("/images/{}", image.id)),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    )
+        .into_response())
+}
+
+/// The full AVIF, a resized one with `w`, or a configured quality `variant`
+#[utoipa::path(
+    get,
+    path = "/images/{image_id}",
+    params(("image_id" = String, Path, description = "Immich asset ID"), ResizeParams),
+    responses(
+        (status = 200, content_type = "image/avif", body = Vec<u8>),
+        (status = 202, content_type = "image/png", body = Vec<u8>, description = "Placeholder while not converted yet"),
+        (status = 304, description = "Not modified since `If-Modified-Since`"),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+    )
+)]
 async fn serve_image(
     State(state): State<Arc<AppState>>,
     Path(image_id): Path<String>,
+    Query(params): Query<ResizeParams>,
+    headers: HeaderMap,
 ) -> Result<Response, AppError> {
-    let image = SyncedImage::get_by_id(&state.pool, &image_id)
-        .await?
-        .ok_or_else(|| AppError::NotFound("Image not found".to_string()))?;
+    if params.w == Some(0) {
+        return Err(AppError::BadRequest("w must be at least 1".to_string()));
+    }
+    if params.q.is_some_and(|q| !(1.0..=100.0).contains(&q)) {
+        return Err(AppError::BadRequest(
+            "q must be between 1 and 100".to_string(),
+        ));
+    }
+    if params.variant.is_some() && (params.w.is_some() || params.q.is_some()) {
+        return Err(AppError::BadRequest(
+            "variant cannot be combined with w or q".to_string(),
+        ));
+    }
+
+    let result = async {
+        let image = SyncedImage::get_by_id(&state.pool(), &image_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Image not found".to_string()))?;
 
-    let avif_path = image
-        .avif_path
-        .ok_or_else(|| AppError::NotFound("AVIF not yet converted".to_string()))?;
+        let Some(avif_path) = image.avif_path.clone() else {
+            if wants_placeholder(&state, &image, params.placeholder.as_deref()) {
+                return pending_placeholder(&state, &image).await;
+            }
+            return Err(AppError::NotFound("AVIF not yet converted".to_string()));
+        };
+
+        if let Some(name) = &params.variant {
+            let variant = state
+                .converter
+                .variant(&image, name)
+                .map_err(|e| AppError::BadRequest(e.to_string()))?;
+            if let Some(path) = variant {
+                return serve_avif_file(&path.to_string_lossy(), IMAGE_CACHE, &headers).await;
+            }
+        }
+
+        if let Some(width) = params.w {
+            if let Some(resized) = state.converter.resized(&image, width, params.q).await? {
+                return serve_avif_file(&resized.to_string_lossy(), IMAGE_CACHE, &headers).await;
+            }
+        }
+
+        serve_avif_file(&avif_path, IMAGE_CACHE, &headers).await
+    }
+    .await;
 
-    serve_avif_file(&avif_path).await
+    let result = or_not_found_image(&state, result);
+    state.response_headers.apply(&image_id, result)
 }
 
+/// The thumbnail AVIF
+#[utoipa::path(
+    get,
+    path = "/images/{image_id}/thumbnail",
+    params(("image_id" = String, Path, description = "Immich asset ID"), PendingParams),
+    responses(
+        (status = 200, content_type = "image/avif", body = Vec<u8>),
+        (status = 202, content_type = "image/png", body = Vec<u8>, description = "Placeholder while not converted yet"),
+        (status = 304, description = "Not modified since `If-Modified-Since`"),
+        (status = 404, body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+    )
+)]
 async fn serve_thumbnail(
     State(state): State<Arc<AppState>>,
     Path(image_id): Path<String>,
+    Query(params): Query<PendingParams>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let result = async {
+        let image = SyncedImage::get_by_id(&state.pool(), &image_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Image not found".to_string()))?;
+
+        let Some(thumbnail_path) = image.thumbnail_path.clone() else {
+            if wants_placeholder(&state, &image, params.placeholder.as_deref()) {
+                return pending_placeholder(&state, &image).await;
+            }
+            return Err(AppError::NotFound(
+                "Thumbnail not yet converted".to_string(),
+            ));
+        };
+
+        serve_avif_file(&thumbnail_path, IMAGE_CACHE, &headers).await
+    }
+    .await;
+
+    let result = or_not_found_image(&state, result);
+    state.response_headers.apply(&image_id, result)
+}
+
+/// Thumbnail of the album's cover image
+#[utoipa::path(
+    get,
+    path = "/albums/{album_id}/cover",
+    params(("album_id" = String, Path, description = "Immich album ID")),
+    responses(
+        (status = 200, content_type = "image/avif", body = Vec<u8>),
+        (status = 304, description = "`If-None-Match` matches the current cover's `ETag`"),
+        (status = 404, body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+    )
+)]
+async fn serve_album_cover(
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<String>,
+    headers: HeaderMap,
 ) -> Result<Response, AppError> {
-    let image = SyncedImage::get_by_id(&state.pool, &image_id)
+    let image = SyncedImage::get_album_cover(&state.pool(), &album_id)
         .await?
-        .ok_or_else(|| AppError::NotFound("Image not found".to_string()))?;
+        .ok_or_else(|| AppError::NotFound("Album has no converted images".to_string()))?;
 
     let thumbnail_path = image
         .thumbnail_path
         .ok_or_else(|| AppError::NotFound("Thumbnail not yet converted".to_string()))?;
 
-    serve_avif_file(&thumbnail_path).await
+    // The cover can switch to another image between syncs, so it is revalidated sooner,
+    // and by an ETag naming the image: an older file must not pass `If-Modified-Since`
+    let modified = tokio::fs::metadata(&thumbnail_path)
+        .await
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let etag = format!("\"{}-{}\"", image.id, modified);
+    let result = if if_none_match(&headers, &etag) {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static(COVER_CACHE));
+        Ok(response)
+    } else {
+        serve_avif_file(&thumbnail_path, COVER_CACHE, &HeaderMap::new())
+            .await
+            .map(|mut response| {
+                response.headers_mut().remove(header::LAST_MODIFIED);
+                response
+            })
+    };
+    let result = result.map(|mut response| {
+        if let Ok(value) = HeaderValue::from_str(&etag) {
+            response.headers_mut().insert(header::ETAG, value);
+        }
+        response
+    });
+    state.response_headers.apply(&image.id, result)
+}
+
+/// Whether `If-None-Match` lists `etag`, or is `*`
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Contact sheet of the album: its converted images as a grid of square tiles
+#[utoipa::path(
+    get,
+    path = "/albums/{album_id}/mosaic",
+    params(("album_id" = String, Path, description = "Immich album ID"), MosaicParams),
+    responses(
+        (status = 200, content_type = "image/avif", body = Vec<u8>),
+        (status = 304, description = "Not modified since `If-Modified-Since`"),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+    )
+)]
+async fn serve_album_mosaic(
+    State(state): State<Arc<AppState>>,
+    Path(album_id): Path<String>,
+    Query(params): Query<MosaicParams>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let cols = params.cols.unwrap_or(DEFAULT_MOSAIC_COLS);
+    if !mosaic::COLS.contains(&cols) {
+        return Err(AppError::BadRequest(format!(
+            "cols must be one of {:?}",
+            mosaic::COLS
+        )));
+    }
+    let size = params.size.unwrap_or(DEFAULT_MOSAIC_TILE_SIZE);
+    if !mosaic::TILE_SIZES.contains(&size) {
+        return Err(AppError::BadRequest(format!(
+            "size must be one of {:?}",
+            mosaic::TILE_SIZES
+        )));
+    }
+    let format: MosaicFormat = match params.format.as_deref() {
+        Some(format) => format
+            .parse()
+            .map_err(|e: anyhow::Error| AppError::BadRequest(e.to_string()))?,
+        None => MosaicFormat::Avif,
+    };
+
+    let path = state
+        .converter
+        .mosaic(&album_id, cols, size, format)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound("Album has no converted images with Immich thumbnails".to_string())
+        })?;
+
+    // Like the cover, the mosaic changes as the album does
+    serve_avif_file(&path.to_string_lossy(), COVER_CACHE, &headers).await
+}
+
+/// Whether `?placeholder=`, or else `server.pending_placeholder`, asks for a
+/// generated placeholder. Never for unsupported images, which will not be
+/// converted and so are not pending.
+fn wants_placeholder(state: &AppState, image: &SyncedImage, param: Option<&str>) -> bool {
+    if image.unsupported.is_some() {
+        return false;
+    }
+    match param {
+        Some(value) => matches!(value, "1" | "true"),
+        None => state.pending_placeholder,
+    }
+}
+
+/// Generated stand-in for an image that is not converted yet. `no-store` makes
+/// clients ask again, and get the real image once it exists.
+async fn pending_placeholder(state: &AppState, image: &SyncedImage) -> Result<Response, AppError> {
+    let png = state.converter.pending_placeholder(image).await?;
+    Ok((
+        StatusCode::ACCEPTED,
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        png,
+    )
+        .into_response())
+}
+
+/// Swap a 404 for the configured placeholder image, if any
+fn or_not_found_image(
+    state: &AppState,
+    result: Result<Response, AppError>,
+) -> Result<Response, AppError> {
+    match (result, &state.not_found_image) {
+        (Err(AppError::NotFound(_)), Some(placeholder)) => Ok((
+            placeholder.status,
+            [
+                (header::CONTENT_TYPE, placeholder.content_type),
+                (header::CACHE_CONTROL, "public, max-age=60"),
+            ],
+            placeholder.bytes.clone(),
+        )
+            .into_response()),
+        (result, _) => result,
+    }
 }
 
-async fn serve_avif_file(file_path: &str) -> Result<Response, AppError> {
+/// Cache policy for converted image files. Reconverting rewrites the file at the
+/// same URL, so clients revalidate daily against `Last-Modified`. `no-transform`
+/// keeps proxies from recompressing bodies that are already compressed.
+const IMAGE_CACHE: &str = "public, max-age=86400, must-revalidate, no-transform";
+
+/// Cache policy for album covers
+const COVER_CACHE: &str = "public, max-age=3600, no-transform";
+
+/// HTTP-date format (RFC 7231 IMF-fixdate) for `Last-Modified`/`If-Modified-Since`
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+async fn serve_avif_file(
+    file_path: &str,
+    cache_control: &'static str,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
     let path = PathBuf::from(file_path);
     if !path.exists() {
         return Err(AppError::NotFound(
@@ -194,6 +1104,34 @@ async fn serve_avif_file(file_path: &str) -> Result<Response, AppError> {
         ));
     }
 
+    // HTTP dates have whole-second precision
+    let modified = tokio::fs::metadata(&path)
+        .await
+        .and_then(|m| m.modified())
+        .ok()
+        .map(|t| DateTime::<Utc>::from(t).trunc_subsecs(0));
+    let last_modified = modified.map(|t| t.format(HTTP_DATE).to_string());
+
+    let if_modified_since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| NaiveDateTime::parse_from_str(v, HTTP_DATE).ok())
+        .map(|t| t.and_utc());
+    if let (Some(modified), Some(since)) = (modified, if_modified_since) {
+        if modified <= since {
+            let mut response = StatusCode::NOT_MODIFIED.into_response();
+            let response_headers = response.headers_mut();
+            response_headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(cache_control),
+            );
+            if let Some(value) = last_modified.and_then(|v| HeaderValue::from_str(&v).ok()) {
+                response_headers.insert(header::LAST_MODIFIED, value);
+            }
+            return Ok(response);
+        }
+    }
+
     let mut file = File::open(&path).await.map_err(|e| {
         error!("Failed to open file {:?}: {}", path, e);
         AppError::Internal("Failed to read image".to_string())
@@ -205,21 +1143,160 @@ async fn serve_avif_file(file_path: &str) -> Result<Response, AppError> {
         AppError::Internal("Failed to read image".to_string())
     })?;
 
-    Ok((
+    metrics::incr("images.served");
+    metrics::count("images.bytes_served", contents.len() as u64);
+
+    // An explicit encoding also makes tower-http's compression layer pass the
+    // body through, while JSON responses stay compressible
+    let mut response = (
         [
-            (header::CONTENT_TYPE, "image/avif"),
-            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            (header::CONTENT_TYPE, content_type_for(&path)),
+            (header::CACHE_CONTROL, cache_control),
+            (header::CONTENT_ENCODING, "identity"),
         ],
         contents,
     )
-        .into_response())
+        .into_response();
+    if let Some(value) = last_modified.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        response.headers_mut().insert(header::LAST_MODIFIED, value);
+    }
+    Ok(response)
+}
+
+/// Serve the untouched original, with range request support from `ServeFile`.
+/// Only routed when `server.serve_originals` is enabled.
+#[utoipa::path(
+    get,
+    path = "/images/{image_id}/original",
+    params(("image_id" = String, Path, description = "Immich asset ID")),
+    responses(
+        (status = 200, content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 206, description = "Requested byte range"),
+        (status = 404, body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+    )
+)]
+async fn serve_original(
+    State(state): State<Arc<AppState>>,
+    Path(image_id): Path<String>,
+    request: Request,
+) -> Result<Response, AppError> {
+    let image = SyncedImage::get_by_id(&state.pool(), &image_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Image not found".to_string()))?;
+
+    let original_path = image
+        .original_path
+        .map(PathBuf::from)
+        .ok_or_else(|| AppError::NotFound("Original not available".to_string()))?;
+
+    if !original_path.exists() {
+        return Err(AppError::NotFound(
+            "Original file not found on disk".to_string(),
+        ));
+    }
+
+    // Encrypted originals are decrypted in memory and sent whole, without ranges
+    let path = original_path.clone();
+    let decrypted = tokio::task::spawn_blocking(move || encryption::read_if_encrypted(&path))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .map_err(|e| {
+            error!("Failed to decrypt original {:?}: {:#}", original_path, e);
+            AppError::Internal("Failed to read original".to_string())
+        })?;
+
+    let mut response = match decrypted {
+        Some(plaintext) => {
+            let content_type = image
+                .mime_type
+                .as_deref()
+                .unwrap_or("application/octet-stream");
+            (
+                [(header::CONTENT_TYPE, content_type.to_string())],
+                plaintext,
+            )
+                .into_response()
+        }
+        None => ServeFile::new(&original_path)
+            .oneshot(request)
+            .await
+            .map_err(|e| {
+                error!("Failed to serve original {:?}: {}", original_path, e);
+                AppError::Internal("Failed to read original".to_string())
+            })?
+            .map(Body::new),
+    };
+    if let Ok(value) = HeaderValue::from_str(&content_disposition(&image.filename)) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_DISPOSITION, value);
+    }
+
+    Ok(response)
+}
+
+/// `inline` disposition with an ASCII fallback name and an RFC 5987 UTF-8 name
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let encoded: String = filename
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+
+    format!(
+        "inline; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
 }
 
+/// Content type by extension; originals linked by `skip_if_smaller` keep their format
+fn content_type_for(path: &std::path::Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .as_deref()
+    {
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        _ => "image/avif",
+    }
+}
+
+/// Database record of one image
+#[utoipa::path(
+    get,
+    path = "/images/{image_id}/metadata",
+    params(("image_id" = String, Path, description = "Immich asset ID")),
+    responses(
+        (status = 200, body = ImageMetadata),
+        (status = 404, body = ErrorResponse),
+    )
+)]
 async fn get_image_metadata(
     State(state): State<Arc<AppState>>,
     Path(image_id): Path<String>,
 ) -> Result<Json<ImageMetadata>, AppError> {
-    let image = SyncedImage::get_by_id(&state.pool, &image_id)
+    let image = SyncedImage::get_by_id(&state.pool(), &image_id)
         .await?
         .ok_or_else(|| AppError::NotFound("Image not found".to_string()))?;
 
@@ -230,12 +1307,147 @@ async fn get_image_metadata(
         file_size: image.file_size,
         synced_at: image.synced_at.map(|d| d.to_rfc3339()),
         converted_at: image.converted_at.map(|d| d.to_rfc3339()),
+        placeholder: image.placeholder,
+        dominant_color: image.dominant_color,
+        lqip: image.lqip,
     }))
 }
 
+/// Every recorded conversion of one image, with sizes, quality and outcome
+#[utoipa::path(
+    get,
+    path = "/images/{image_id}/history",
+    params(("image_id" = String, Path, description = "Immich asset ID")),
+    responses(
+        (status = 200, body = ImageHistoryResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn get_image_history(
+    State(state): State<Arc<AppState>>,
+    Path(image_id): Path<String>,
+) -> Result<Json<ImageHistoryResponse>, AppError> {
+    let pool = state.pool();
+    let image = SyncedImage::get_by_id(&pool, &image_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Image not found".to_string()))?;
+    let history = ConversionLogEntry::get_by_image(&pool, &image.id).await?;
+
+    Ok(Json(ImageHistoryResponse {
+        id: image.id,
+        history,
+    }))
+}
+
+/// Reconvert one image with the current settings
+#[utoipa::path(
+    post,
+    path = "/images/{image_id}/reconvert",
+    params(("image_id" = String, Path, description = "Immich asset ID")),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = ReconvertResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn reconvert_image(
+    State(state): State<Arc<AppState>>,
+    Path(image_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<ReconvertResponse>, AppError> {
+    require_api_key(&state, &headers)?;
+
+    let image = SyncedImage::get_by_id(&state.pool(), &image_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Image not found".to_string()))?;
+
+    let image = state.converter.reconvert(&image).await?;
+
+    let avif_file_size = match &image.avif_path {
+        Some(path) => tokio::fs::metadata(path).await.ok().map(|m| m.len()),
+        None => None,
+    };
+
+    Ok(Json(ReconvertResponse {
+        id: image.id,
+        avif_file_size,
+        converted_at: image.converted_at.map(|d| d.to_rfc3339()),
+    }))
+}
+
+/// Check the `x-api-key` header or bearer token against `server.api_key`
+fn require_api_key(state: &AppState, headers: &HeaderMap) -> Result<(), AppError> {
+    let expected = state.api_key.as_deref().ok_or_else(|| {
+        AppError::Forbidden("This endpoint requires server.api_key to be configured".to_string())
+    })?;
+
+    let provided = headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+        })
+        .ok_or_else(|| AppError::Unauthorized("Missing API key".to_string()))?;
+
+    if !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        return Err(AppError::Unauthorized("Invalid API key".to_string()));
+    }
+
+    Ok(())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Size totals and compression ratio, overall and per album
+#[utoipa::path(get, path = "/stats", responses((status = 200, body = ConversionStats)))]
+async fn get_stats(State(state): State<Arc<AppState>>) -> Result<Json<ConversionStats>, AppError> {
+    Ok(Json(SyncedImage::stats(&state.pool()).await?))
+}
+
+/// Originals and AVIFs missing from disk
+#[utoipa::path(
+    get,
+    path = "/admin/integrity",
+    params(IntegrityParams),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = IntegrityReport),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse),
+    )
+)]
+async fn get_integrity(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<IntegrityParams>,
+    headers: HeaderMap,
+) -> Result<Json<IntegrityReport>, AppError> {
+    require_api_key(&state, &headers)?;
+
+    if !params.refresh.unwrap_or(false) {
+        if let Some(report) = state.integrity.read().await.clone() {
+            return Ok(Json(report));
+        }
+    }
+
+    let report = integrity::refresh(&state.pool(), &state.integrity).await?;
+    Ok(Json(report))
+}
+
 #[derive(Debug)]
 pub enum AppError {
     NotFound(String),
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    /// Over the rate limit; seconds until the next request is accepted
+    TooManyRequests(u64),
     Internal(String),
     Database(sqlx::Error),
 }
@@ -244,6 +1456,19 @@ impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status, message) = match self {
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            AppError::TooManyRequests(retry_after) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    Json(ErrorResponse {
+                        error: "Too many requests".to_string(),
+                    }),
+                )
+                    .into_response();
+            }
             AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::Database(e) => {
                 error!("Database error: {}", e);
@@ -254,7 +1479,7 @@ impl IntoResponse for AppError {
             }
         };
 
-        (status, Json(serde_json::json!({ "error": message }))).into_response()
+        (status, Json(ErrorResponse { error: message })).into_response()
     }
 }
 
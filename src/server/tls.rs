@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Load and validate a PEM certificate chain and private key.
+///
+/// Everything is checked up front, including that the key belongs to the
+/// certificate, so a bad pair fails at startup rather than on the first handshake.
+/// Clients are offered HTTP/2 when `http2` is set, and HTTP/1.1 either way.
+pub fn load_config(cert_path: &Path, key_path: &Path, http2: bool) -> Result<RustlsConfig> {
+    let cert_pem = std::fs::read(cert_path)
+        .with_context(|| format!("Failed to read TLS certificate {:?}", cert_path))?;
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Malformed TLS certificate {:?}", cert_path))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {:?}", cert_path);
+    }
+
+    let key_pem = std::fs::read(key_path)
+        .with_context(|| format!("Failed to read TLS key {:?}", key_path))?;
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .with_context(|| format!("Malformed TLS key {:?}", key_path))?
+        .with_context(|| format!("No private key found in {:?}", key_path))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS protocol versions")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("TLS certificate and key do not form a valid pair")?;
+    config.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+
+    Ok(RustlsConfig::from_config(Arc::new(config)))
+}
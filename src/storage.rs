@@ -0,0 +1,632 @@
+use crate::config::{Config, EvictionOrder, RemovalMode};
+use crate::db::models::{Album, SyncedImage, TrashedFile};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::OwnedMutexGuard;
+use tracing::{debug, info, warn};
+
+/// How long a blob or sidecar is safe from `collect_garbage` after it was written
+/// or reused, so a conversion has time to record it
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Default)]
+pub struct EvictionResult {
+    pub images: usize,
+    pub bytes: u64,
+    /// Albums that lost images, whose manifests are out of date
+    pub albums: BTreeSet<String>,
+}
+
+/// Result of the `clean` command
+#[derive(Debug, Default, Serialize)]
+pub struct CleanResult {
+    pub blobs: usize,
+    pub bytes: u64,
+}
+
+/// Files deleted from the trash, by `empty-trash` or once their retention ends
+#[derive(Debug, Default, Serialize)]
+pub struct TrashResult {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// One async lock per key, such as a checksum or a cache file, so concurrent
+/// downloads or conversions of the same content wait for each other and the second
+/// one can reuse the first one's files
+#[derive(Default)]
+pub struct KeyLocks(Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>);
+
+impl KeyLocks {
+    pub async fn lock(&self, key: &str) -> OwnedMutexGuard<()> {
+        let lock = self
+            .0
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+}
+
+/// A storage directory that cannot be written to, typically an unmounted or
+/// unreachable network volume. Reported once for the run instead of as a failure
+/// of every image.
+#[derive(Debug)]
+pub struct VolumeUnavailable {
+    path: PathBuf,
+    error: std::io::Error,
+}
+
+impl std::fmt::Display for VolumeUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Storage volume {:?} is not writable ({}); check that it is mounted and reachable",
+            self.path, self.error
+        )
+    }
+}
+
+impl std::error::Error for VolumeUnavailable {}
+
+/// Device and inode of each directory when first probed, so a mount point that
+/// lost its volume is noticed even though it is still a writable directory
+static PROBED_DIRS: Mutex<Option<HashMap<PathBuf, (u64, u64)>>> = Mutex::new(None);
+
+/// Write and delete a probe file in `dir`, and check that it is still the directory
+/// first probed. The directory is never created here: on an unmounted volume that
+/// would put files on the disk under the mount point.
+pub async fn check_writable(dir: &Path) -> Result<(), VolumeUnavailable> {
+    let probe = dir.join(format!(".write-probe-{}", std::process::id()));
+    let written = async {
+        tokio::fs::write(&probe, b"probe").await?;
+        tokio::fs::remove_file(&probe).await?;
+        let Some(id) = file_id(&tokio::fs::metadata(dir).await?) else {
+            return Ok(());
+        };
+        let mut probed = PROBED_DIRS.lock().unwrap();
+        let first = *probed
+            .get_or_insert_with(HashMap::new)
+            .entry(dir.to_path_buf())
+            .or_insert(id);
+        if first != id {
+            return Err(std::io::Error::other(
+                "the directory was replaced since startup",
+            ));
+        }
+        Ok(())
+    }
+    .await;
+    written.map_err(|error| VolumeUnavailable {
+        path: dir.to_path_buf(),
+        error,
+    })
+}
+
+/// Probe the originals and AVIF directories, which may sit on different volumes
+pub async fn check_volumes(config: &Config) -> Result<(), VolumeUnavailable> {
+    check_writable(&config.original_path()).await?;
+    check_writable(&config.avif_path()).await
+}
+
+/// Whether a failure came from the filesystem rather than from the image itself
+pub fn is_io_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.downcast_ref::<std::io::Error>().is_some())
+}
+
+/// Fail when writing `needed` more bytes would leave less than `storage.min_free_bytes`
+/// free on the storage disk, so a large sync cannot fill it up and corrupt the database
+pub fn ensure_free_space(config: &Config, needed: u64) -> Result<()> {
+    let min_free = config.storage.min_free_bytes;
+    if min_free == 0 {
+        return Ok(());
+    }
+
+    let base_path = &config.storage.base_path;
+    let available = fs2::available_space(base_path)
+        .with_context(|| format!("Failed to query free space on {:?}", base_path))?;
+    if available.saturating_sub(needed) < min_free {
+        anyhow::bail!(
+            "Not enough disk space on {:?}: {:.1} MB free, the download needs {:.1} MB and {:.1} MB must stay free",
+            base_path,
+            available as f64 / 1_048_576.0,
+            needed as f64 / 1_048_576.0,
+            min_free as f64 / 1_048_576.0
+        );
+    }
+    Ok(())
+}
+
+/// Bring originals plus AVIFs back under `storage.max_total_bytes` by deleting
+/// files in `storage.eviction_order`. Evicted images keep their database row with
+/// the file paths cleared.
+pub async fn enforce_limit(pool: &SqlitePool, config: &Config) -> Result<EvictionResult> {
+    let limit = config.storage.max_total_bytes;
+    let mut result = EvictionResult::default();
+    if limit == 0 {
+        return Ok(result);
+    }
+
+    let mut images = Vec::new();
+    let mut total = 0;
+    let mut counted = HashSet::new();
+    for image in SyncedImage::get_with_files(pool).await? {
+        let size = files_size(config, &image, &mut counted).await;
+        total += size;
+        images.push((image, size));
+    }
+    if total <= limit {
+        debug!("Storage use {} of {} bytes", total, limit);
+        return Ok(result);
+    }
+    info!(
+        "Storage use {:.1} MB exceeds the {:.1} MB limit, evicting",
+        total as f64 / 1_048_576.0,
+        limit as f64 / 1_048_576.0
+    );
+
+    match config.storage.eviction_order {
+        EvictionOrder::OldestImage => {
+            // Never-synced rows (None) sort first
+            images.sort_by_key(|(image, _)| image.synced_at);
+            for (image, size) in &images {
+                if total <= limit {
+                    break;
+                }
+                result.albums.extend(evict(pool, config, image).await?);
+                debug!("Evicted {} ({} bytes)", image.filename, size);
+                total -= size;
+                result.images += 1;
+                result.bytes += size;
+            }
+        }
+        EvictionOrder::OldestAlbum => {
+            let mut by_album: HashMap<String, Vec<(SyncedImage, u64)>> = HashMap::new();
+            for (image, size) in images {
+                by_album
+                    .entry(image.album_id.clone())
+                    .or_default()
+                    .push((image, size));
+            }
+
+            let mut albums = Album::get_all(pool).await?;
+            albums.sort_by(|a, b| a.last_sync.cmp(&b.last_sync).then(a.name.cmp(&b.name)));
+            for album in albums {
+                if total <= limit {
+                    break;
+                }
+                let Some(images) = by_album.remove(&album.id) else {
+                    continue;
+                };
+
+                let mut album_bytes = 0;
+                for (image, size) in &images {
+                    result.albums.extend(evict(pool, config, image).await?);
+                    album_bytes += size;
+                }
+                info!(
+                    "Evicted album '{}': {} images, {:.1} MB",
+                    album.name,
+                    images.len(),
+                    album_bytes as f64 / 1_048_576.0
+                );
+                total -= album_bytes;
+                result.images += images.len();
+                result.bytes += album_bytes;
+            }
+        }
+    }
+
+    // Evicted images may have been the last users of their blobs
+    if config.storage.content_addressed {
+        collect_garbage(pool, config).await?;
+    }
+
+    info!(
+        "Evicted {} images, reclaimed {:.1} MB (now using {:.1} MB)",
+        result.images,
+        result.bytes as f64 / 1_048_576.0,
+        total as f64 / 1_048_576.0
+    );
+    if total > limit {
+        warn!("Still over the storage limit after evicting everything eligible");
+    }
+
+    Ok(result)
+}
+
+/// Delete content-addressed blobs, and their sidecars, that no image refers to.
+/// Files younger than `GC_GRACE_PERIOD` are left alone, as they may come from a
+/// conversion that has not been recorded yet.
+pub async fn collect_garbage(pool: &SqlitePool, config: &Config) -> Result<CleanResult> {
+    let mut result = CleanResult::default();
+    let blobs_path = config.blobs_path();
+    if !blobs_path.exists() {
+        return Ok(result);
+    }
+
+    let images = SyncedImage::get_with_files(pool).await?;
+    let sidecars: HashSet<PathBuf> = images
+        .iter()
+        .filter_map(|image| {
+            let avif = image.avif_path.as_deref()?;
+            Some(sidecar_path(config, Path::new(avif), &image.id))
+        })
+        .collect();
+    let referenced: HashSet<PathBuf> = images
+        .into_iter()
+        .flat_map(|image| {
+            let variants = image.variant_paths().into_values();
+            [image.avif_path, image.thumbnail_path]
+                .into_iter()
+                .flatten()
+                .chain(variants)
+        })
+        .map(PathBuf::from)
+        .collect();
+    let grace_cutoff = std::time::SystemTime::now() - GC_GRACE_PERIOD;
+
+    let mut shards = tokio::fs::read_dir(&blobs_path).await?;
+    while let Some(shard) = shards.next_entry().await? {
+        if !shard.file_type().await?.is_dir() {
+            continue;
+        }
+        let mut blobs = tokio::fs::read_dir(shard.path()).await?;
+        while let Some(blob) = blobs.next_entry().await? {
+            let path = blob.path();
+            let kept = match path.to_str().and_then(|p| p.strip_suffix(".json")) {
+                // Sidecars written before they were kept per image go along with their blob
+                Some(owner) => sidecars.contains(&path) || referenced.contains(Path::new(owner)),
+                None => referenced.contains(&path),
+            };
+            if kept {
+                continue;
+            }
+
+            let Ok(metadata) = blob.metadata().await else {
+                continue;
+            };
+            if metadata
+                .modified()
+                .is_ok_and(|modified| modified > grace_cutoff)
+            {
+                continue;
+            }
+            let size = metadata.len();
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {
+                    debug!("Removed unreferenced blob {:?}", path);
+                    result.blobs += 1;
+                    result.bytes += size;
+                }
+                Err(e) => warn!("Failed to delete {:?}: {}", path, e),
+            }
+        }
+    }
+
+    info!(
+        "Removed {} unreferenced blobs, reclaimed {:.1} MB",
+        result.blobs,
+        result.bytes as f64 / 1_048_576.0
+    );
+    Ok(result)
+}
+
+/// Provenance sidecar of an image's AVIF: `{avif}.json`, or `{avif}.{image_id}.json`
+/// for content-addressed blobs, which several images may share
+pub fn sidecar_path(config: &Config, avif_path: &Path, image_id: &str) -> PathBuf {
+    if avif_path.starts_with(config.blobs_path()) {
+        avif_path.with_extension(format!("avif.{}.json", image_id))
+    } else {
+        avif_path.with_extension("avif.json")
+    }
+}
+
+/// Delete one of an image's files. Blobs may be shared with other images, so they
+/// are left for `collect_garbage` instead.
+pub async fn remove_output(config: &Config, path: &str) -> std::io::Result<()> {
+    if config.storage.content_addressed && Path::new(path).starts_with(config.blobs_path()) {
+        return Ok(());
+    }
+    tokio::fs::remove_file(path).await
+}
+
+/// Get rid of a file of an image removed from Immich: delete it, or with
+/// `removal_mode = "trash"` move it under `trash_path()` and record when. Missing
+/// files are skipped, and failures only logged, like the plain deletes before.
+pub async fn discard(pool: &SqlitePool, config: &Config, image_id: &str, path: &str) {
+    let result = match config.sync.removal_mode {
+        RemovalMode::Delete => remove_output(config, path).await.map_err(Into::into),
+        RemovalMode::Trash => move_to_trash(pool, config, image_id, path).await,
+    };
+    if let Err(e) = result {
+        let missing = e
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound);
+        if !missing {
+            warn!("Failed to remove {}: {}", path, e);
+        }
+    }
+}
+
+async fn move_to_trash(
+    pool: &SqlitePool,
+    config: &Config,
+    image_id: &str,
+    path: &str,
+) -> Result<()> {
+    let source = Path::new(path);
+    // Blobs may be shared with other images; `collect_garbage` deletes them
+    if config.storage.content_addressed && source.starts_with(config.blobs_path()) {
+        return Ok(());
+    }
+    tokio::fs::symlink_metadata(source).await?;
+
+    // Keep the layout under base_path, so a file can be moved back by hand
+    let dest = match source.strip_prefix(&config.storage.base_path) {
+        Ok(relative) => config.trash_path().join(relative),
+        Err(_) => config
+            .trash_path()
+            .join("other")
+            .join(source.file_name().unwrap_or_default()),
+    };
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // The trash sits next to the originals, but a custom base_path layout may put
+    // it on another filesystem
+    if tokio::fs::rename(source, &dest).await.is_err() {
+        tokio::fs::copy(source, &dest).await?;
+        tokio::fs::remove_file(source).await?;
+    }
+    debug!("Moved {} to the trash", path);
+    TrashedFile::insert(pool, &dest.to_string_lossy(), path, image_id).await
+}
+
+/// Delete trashed files older than `trash_retention_days`, if it is set
+pub async fn expire_trash(pool: &SqlitePool, config: &Config) -> Result<TrashResult> {
+    let days = config.sync.trash_retention_days;
+    if days == 0 {
+        return Ok(TrashResult::default());
+    }
+    // Capped so an absurd setting cannot overflow the date arithmetic
+    let cutoff = Utc::now() - chrono::Duration::days(days.min(36_500) as i64);
+    empty_trash(pool, config, Some(cutoff)).await
+}
+
+/// Delete the files trashed before `cutoff`, or all of them without one
+pub async fn empty_trash(
+    pool: &SqlitePool,
+    config: &Config,
+    cutoff: Option<DateTime<Utc>>,
+) -> Result<TrashResult> {
+    let mut result = TrashResult::default();
+    let trash_path = config.trash_path();
+
+    for file in TrashedFile::get_before(pool, cutoff).await? {
+        let path = Path::new(&file.path);
+        let size = tokio::fs::symlink_metadata(path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        debug!(
+            "Deleting {} of image {} from the trash (trashed {})",
+            file.original_location, file.image_id, file.trashed_at
+        );
+        match tokio::fs::remove_file(path).await {
+            Ok(()) => {
+                result.files += 1;
+                result.bytes += size;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!("Failed to delete {:?}: {}", path, e);
+                continue;
+            }
+        }
+        TrashedFile::delete(pool, &file.path).await?;
+
+        // Directories left empty go too; removing one that is not empty fails
+        let mut dir = path.parent();
+        while let Some(d) = dir.filter(|d| d.starts_with(&trash_path) && *d != trash_path) {
+            if tokio::fs::remove_dir(d).await.is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+
+    if result.files > 0 {
+        info!(
+            "Deleted {} files from the trash, reclaimed {:.1} MB",
+            result.files,
+            result.bytes as f64 / 1_048_576.0
+        );
+    }
+    Ok(result)
+}
+
+/// Suffix of files still being written; they are renamed to their final name once
+/// complete, so a file under its final name is never truncated
+const PART_SUFFIX: &str = ".part";
+
+/// Temporary name to write `dest` under before renaming it into place
+pub fn part_path(dest: &Path) -> PathBuf {
+    let mut path = dest.as_os_str().to_owned();
+    path.push(PART_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// How long a `.part` file must have gone unwritten before it counts as left
+/// behind, so a download still running in another process is not pulled out
+/// from under it
+const PART_STALE_AFTER: Duration = Duration::from_secs(3600);
+
+/// Delete `.part` files under `dir` left behind by an interrupted sync, returning
+/// how many there were
+pub async fn remove_partial_downloads(dir: &Path) -> Result<usize> {
+    let stale_cutoff = std::time::SystemTime::now() - PART_STALE_AFTER;
+    let mut removed = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", dir)),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                pending.push(path);
+            } else if path.to_string_lossy().ends_with(PART_SUFFIX) {
+                // Gone already: finished and renamed by the sync that wrote it
+                let Ok(metadata) = entry.metadata().await else {
+                    continue;
+                };
+                if metadata
+                    .modified()
+                    .is_ok_and(|modified| modified > stale_cutoff)
+                {
+                    continue;
+                }
+                debug!("Removing partial download {:?}", path);
+                tokio::fs::remove_file(&path).await?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove `dir` and the directories under it that hold no files. Directories
+/// with files left, such as those of images that moved to another album, stay.
+pub async fn remove_empty_dirs(dir: &Path) {
+    let mut dirs = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+                pending.push(entry.path());
+            }
+        }
+        dirs.push(dir);
+    }
+    // Children were found after their parents, so they go first; removing a
+    // directory that is not empty fails
+    for dir in dirs.iter().rev() {
+        let _ = tokio::fs::remove_dir(dir).await;
+    }
+}
+
+/// Where sync stores an asset's original: `{album_id}/{stem}-{asset_id}.{ext}` under
+/// `original_path()`. Immich allows several assets with the same name in one album,
+/// so the ID keeps their originals from overwriting each other.
+pub fn original_dest(config: &Config, album_id: &str, asset_id: &str, filename: &str) -> PathBuf {
+    let name = Path::new(filename);
+    let stem = name
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(filename);
+    let file_name = match name.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}-{}.{}", stem, asset_id, ext),
+        None => format!("{}-{}", stem, asset_id),
+    };
+    config.original_path().join(album_id).join(file_name)
+}
+
+/// Suffix of Immich's own thumbnails, kept next to their originals
+const IMMICH_THUMBNAIL_SUFFIX: &str = ".immich-thumb";
+
+/// Where Immich's own thumbnail of an original is kept, next to the original
+pub fn immich_thumbnail_path(original: &Path) -> PathBuf {
+    let mut path = original.as_os_str().to_owned();
+    path.push(IMMICH_THUMBNAIL_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Whether a file under `original_path()` may be an original, rather than a
+/// partial download or an Immich thumbnail
+pub fn is_original(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    !name.ends_with(PART_SUFFIX) && !name.ends_with(IMMICH_THUMBNAIL_SUFFIX)
+}
+
+/// Bytes on disk for an image's original, AVIF, thumbnail, variants and sidecar.
+/// Files already in `counted`, such as hardlinked duplicates and shared blobs, add
+/// nothing.
+async fn files_size(
+    config: &Config,
+    image: &SyncedImage,
+    counted: &mut HashSet<(u64, u64)>,
+) -> u64 {
+    let mut size = 0;
+    for path in image_files(config, image) {
+        if let Ok(metadata) = tokio::fs::metadata(&path).await {
+            if file_id(&metadata).is_none_or(|id| counted.insert(id)) {
+                size += metadata.len();
+            }
+        }
+    }
+    size
+}
+
+/// Device and inode of a file, which its hardlinks share
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Delete an image's files, returning the albums it is in
+async fn evict(pool: &SqlitePool, config: &Config, image: &SyncedImage) -> Result<Vec<String>> {
+    for path in image_files(config, image) {
+        if let Err(e) = remove_output(config, &path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to delete {}: {}", path, e);
+            }
+        }
+    }
+    SyncedImage::clear_files(pool, &image.id).await?;
+    SyncedImage::album_ids(pool, &image.id).await
+}
+
+fn image_files(config: &Config, image: &SyncedImage) -> Vec<String> {
+    let mut files: Vec<String> = [
+        &image.original_path,
+        &image.avif_path,
+        &image.thumbnail_path,
+    ]
+    .into_iter()
+    .flatten()
+    .cloned()
+    .chain(image.variant_paths().into_values())
+    .collect();
+    if let Some(original) = &image.original_path {
+        let thumbnail = immich_thumbnail_path(Path::new(original));
+        files.push(thumbnail.to_string_lossy().into_owned());
+    }
+    if let Some(avif) = &image.avif_path {
+        let sidecar = sidecar_path(config, Path::new(avif), &image.id);
+        files.push(sidecar.to_string_lossy().into_owned());
+    }
+    files
+}
@@ -1,24 +1,57 @@
-use crate::config::Config;
-use crate::db::models::{Album, SyncedImage};
-use crate::immich::{AssetResponse, ImmichClient};
+use crate::config::{Config, LinkMode};
+use crate::converter::formats::{self, UnsupportedTally};
+use crate::converter::manifest;
+use crate::db::models::{Album, NewSyncedImage, SyncedImage};
+use crate::encryption;
+use crate::failures::FailureList;
+use crate::immich::{AlbumResponse, AssetResponse, ImmichClient};
+use crate::metrics;
+use crate::progress::Progress;
+use crate::storage::{self, KeyLocks, VolumeUnavailable};
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use futures::stream::{self, StreamExt};
+use serde::Serialize;
 use sqlx::SqlitePool;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Component, Path, PathBuf};
 use tracing::{debug, info, warn};
 
+/// Re-list assets changed shortly before the last sync, to absorb clock skew
+/// between this host and the Immich server
+const INCREMENTAL_OVERLAP: chrono::Duration = chrono::Duration::minutes(5);
+
+/// Downloaded originals written to the database per transaction
+const UPSERT_BATCH_SIZE: usize = 50;
+
+/// Width of Immich's small `thumbnail` size; wider thumbnails start from `preview`
+const IMMICH_THUMBNAIL_WIDTH: u32 = 250;
+
 pub struct SyncService {
     client: ImmichClient,
     pool: SqlitePool,
     config: Config,
+    progress_bar: bool,
+    checksum_locks: KeyLocks,
+    since: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct SyncResult {
     pub downloaded: usize,
     pub skipped: usize,
     pub failed: usize,
+    /// Assets not downloaded because their type is not converted
+    pub unsupported: usize,
     pub removed: usize,
+    #[serde(flatten)]
+    pub failures: FailureList,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetadataRefreshResult {
+    pub updated: usize,
+    pub unchanged: usize,
 }
 
 impl SyncService {
@@ -27,20 +60,51 @@ impl SyncService {
             client,
             pool,
             config,
+            progress_bar: false,
+            checksum_locks: KeyLocks::default(),
+            since: None,
         }
     }
 
-    pub async fn sync_all(&self) -> Result<SyncResult> {
+    /// Draw a download progress bar instead of periodic progress logs when on a terminal
+    pub fn with_progress_bar(mut self, enabled: bool) -> Self {
+        self.progress_bar = enabled;
+        self
+    }
+
+    /// Only download assets dated after `since` (see [`AssetResponse::date`]);
+    /// undated assets are left out
+    pub fn with_since(mut self, since: Option<DateTime<Utc>>) -> Self {
+        self.since = since;
+        self
+    }
+
+    /// Sync every accessible album, or only `album_id`
+    pub async fn sync_all(&self, album_id: Option<&str>) -> Result<SyncResult> {
         let mut total_result = SyncResult {
             downloaded: 0,
             skipped: 0,
             failed: 0,
+            unsupported: 0,
             removed: 0,
+            failures: FailureList::default(),
         };
 
-        let albums = self.client.get_albums().await?;
+        storage::check_writable(&self.config.original_path()).await?;
+
+        let mut albums = self.client.get_albums().await?;
         info!("Found {} accessible albums", albums.len());
+        // Listed in full before filtering, so other albums are not taken as removed
+        let remote_album_ids: HashSet<String> = albums.iter().map(|a| a.id.clone()).collect();
+        if let Some(album_id) = album_id {
+            albums.retain(|a| a.id == album_id);
+            if albums.is_empty() {
+                anyhow::bail!("Album {} is not accessible on the Immich server", album_id);
+            }
+        }
 
+        // Album progress is always logged; the bar, if any, tracks each album's downloads
+        let progress = Progress::new("albums synced", albums.len(), false);
         for album in &albums {
             info!("Syncing album: {} ({})", album.album_name, album.id);
             match self.sync_album(&album.id).await {
@@ -48,25 +112,40 @@ impl SyncService {
                     total_result.downloaded += result.downloaded;
                     total_result.skipped += result.skipped;
                     total_result.failed += result.failed;
+                    total_result.unsupported += result.unsupported;
                     total_result.removed += result.removed;
+                    total_result.failures.extend(result.failures);
+                }
+                // Every later album would fail the same way
+                Err(e) if e.is::<VolumeUnavailable>() => {
+                    return Err(e.context("Sync stopped"));
                 }
                 Err(e) => {
-                    warn!("Failed to sync album {} ({}): {}", album.album_name, album.id, e);
+                    warn!(
+                        "Failed to sync album {} ({}): {}",
+                        album.album_name, album.id, e
+                    );
                     total_result.failed += 1;
+                    total_result.failures.push(&album.id, &album.album_name, &e);
                 }
             }
+            progress.inc();
         }
 
-        if self.config.sync.delete_removed {
-            let remote_album_ids: HashSet<String> = albums.iter().map(|a| a.id.clone()).collect();
+        if self.config.sync.prune_removed_albums() {
             let removed = self.remove_stale_albums(&remote_album_ids).await?;
             total_result.removed += removed;
         }
 
+        if let Err(e) = storage::expire_trash(&self.pool, &self.config).await {
+            warn!("Failed to delete expired files from the trash: {}", e);
+        }
+
         info!(
-            "Sync complete: {} downloaded, {} skipped, {} failed, {} removed",
+            "Sync complete: {} downloaded, {} skipped, {} unsupported, {} failed, {} removed",
             total_result.downloaded,
             total_result.skipped,
+            total_result.unsupported,
             total_result.failed,
             total_result.removed
         );
@@ -74,14 +153,90 @@ impl SyncService {
         Ok(total_result)
     }
 
+    /// Refresh album names and asset filenames/checksums for already synced images,
+    /// without downloading anything or touching files on disk.
+    pub async fn sync_metadata(&self) -> Result<MetadataRefreshResult> {
+        let mut result = MetadataRefreshResult {
+            updated: 0,
+            unchanged: 0,
+        };
+
+        let existing_ids: HashSet<String> = SyncedImage::get_all_synced_ids(&self.pool)
+            .await?
+            .into_iter()
+            .collect();
+
+        let albums = self.client.get_albums().await?;
+        for summary in &albums {
+            let album = match self.fetch_album(&summary.id).await {
+                Ok(album) => album,
+                Err(e) => {
+                    warn!(
+                        "Failed to fetch album {} ({}): {}",
+                        summary.album_name, summary.id, e
+                    );
+                    continue;
+                }
+            };
+
+            Album::upsert(
+                &self.pool,
+                &album.id,
+                &album.album_name,
+                album.asset_count,
+                album.album_thumbnail_asset_id.as_deref(),
+            )
+            .await?;
+
+            for asset in album.assets.iter().filter(|a| existing_ids.contains(&a.id)) {
+                if SyncedImage::update_metadata(
+                    &self.pool,
+                    &asset.id,
+                    &asset.original_file_name,
+                    &asset.checksum,
+                )
+                .await?
+                {
+                    debug!(
+                        "Updated metadata for {}: {}",
+                        asset.id, asset.original_file_name
+                    );
+                    result.updated += 1;
+                } else {
+                    result.unchanged += 1;
+                }
+            }
+        }
+
+        info!(
+            "Metadata refresh complete: {} updated, {} unchanged",
+            result.updated, result.unchanged
+        );
+
+        Ok(result)
+    }
+
     pub async fn sync_album(&self, album_id: &str) -> Result<SyncResult> {
-        let album = self.client.get_album(album_id).await?;
+        let started_at = Utc::now();
+        let stored = Album::get_by_id(&self.pool, album_id).await?;
+        let album = match self.list_changed_assets(album_id, stored.as_ref()).await? {
+            Some(album) => {
+                info!(
+                    "Album '{}': incremental listing returned {} changed assets",
+                    album.album_name,
+                    album.assets.len()
+                );
+                album
+            }
+            None => self.fetch_album(album_id).await?,
+        };
 
         crate::db::models::Album::upsert(
             &self.pool,
             &album.id,
             &album.album_name,
             album.asset_count,
+            album.album_thumbnail_asset_id.as_deref(),
         )
         .await?;
 
@@ -90,24 +245,88 @@ impl SyncService {
             .into_iter()
             .collect();
 
-        let images: Vec<&AssetResponse> = album.assets.iter().filter(|a| a.is_image()).collect();
+        let owner_ids = &self.config.sync.owner_ids;
+        let mut other_owners = 0;
+        let images: Vec<&AssetResponse> = album
+            .assets
+            .iter()
+            .filter(|a| a.is_image())
+            .filter(|a| {
+                let wanted = owner_ids.is_empty()
+                    || a.owner_id.as_ref().is_some_and(|id| owner_ids.contains(id));
+                if !wanted {
+                    other_owners += 1;
+                }
+                wanted
+            })
+            .collect();
+        if other_owners > 0 {
+            info!(
+                "Album '{}': left out {} images of other owners",
+                album.album_name, other_owners
+            );
+        }
 
         let remote_ids: HashSet<String> = images.iter().map(|a| a.id.clone()).collect();
 
+        // Images already synced through another album are not downloaded again, but
+        // still show up in this one
+        let members: Vec<String> = remote_ids.intersection(&existing_ids).cloned().collect();
+        SyncedImage::add_album_members(&self.pool, album_id, &members).await?;
+
         let mut result = SyncResult {
             downloaded: 0,
             skipped: 0,
             failed: 0,
+            unsupported: 0,
             removed: 0,
+            failures: FailureList::default(),
         };
 
+        // Unsupported assets still count as present in the album, they are just
+        // never downloaded
+        let mut unsupported = UnsupportedTally::default();
+        let mut out_of_range = 0;
         let assets_to_sync: Vec<&AssetResponse> = images
             .into_iter()
+            .filter(|asset| {
+                let mime_type = asset.original_mime_type.as_deref();
+                let reason = formats::unsupported_reason(
+                    &asset.original_file_name,
+                    mime_type,
+                    &self.config.sync.skip_mime_types,
+                );
+                match reason {
+                    Some(reason) => {
+                        let label = formats::type_label(&asset.original_file_name, mime_type);
+                        unsupported.add(label, reason);
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .filter(|asset| match self.since {
+                Some(since) if asset.date().is_none_or(|date| date <= since) => {
+                    out_of_range += 1;
+                    false
+                }
+                _ => true,
+            })
             .filter(|asset| {
                 !existing_ids.contains(&asset.id)
                     || self.needs_update(&asset.id, &asset.checksum, &existing_ids)
             })
             .collect();
+        unsupported.log("Not downloading");
+        result.unsupported = unsupported.total();
+        if let Some(since) = self.since {
+            info!(
+                "Album '{}': left out {} images dated before {} or undated",
+                album.album_name,
+                out_of_range,
+                since.to_rfc3339()
+            );
+        }
 
         info!(
             "Album '{}': {} images to sync out of {}",
@@ -116,47 +335,153 @@ impl SyncService {
             album.asset_count
         );
 
+        let progress = &Progress::new("downloaded", assets_to_sync.len(), self.progress_bar);
+        let pending = &PendingImages::default();
         let results: Vec<_> = stream::iter(assets_to_sync)
-            .map(|asset| async move { self.download_asset(album_id, asset).await })
+            .map(|asset| async move {
+                let result = self.download_asset(album_id, asset, pending).await;
+                progress.inc();
+                (asset, result)
+            })
             .buffer_unordered(self.config.sync.parallel_downloads)
             .collect()
             .await;
+        pending.flush(&self.pool).await?;
+
+        // Failed writes are not the assets' fault when the volume itself is gone
+        if results
+            .iter()
+            .any(|(_, r)| r.as_ref().is_err_and(storage::is_io_error))
+        {
+            storage::check_writable(&self.config.original_path()).await?;
+        }
 
-        for download_result in results {
+        for (asset, download_result) in results {
             match download_result {
                 Ok(true) => result.downloaded += 1,
                 Ok(false) => result.skipped += 1,
                 Err(e) => {
                     warn!("Download failed: {}", e);
+                    metrics::incr("downloads.failed");
                     result.failed += 1;
+                    result
+                        .failures
+                        .push(&asset.id, &asset.original_file_name, &e);
                 }
             }
         }
 
         if self.config.sync.delete_removed {
-            for id in existing_ids.difference(&remote_ids) {
-                if let Ok(Some(image)) = SyncedImage::get_by_id(&self.pool, id).await {
-                    if image.album_id == album_id {
-                        debug!("Removing deleted image: {}", id);
-                        if let Some(path) = &image.original_path {
-                            let _ = tokio::fs::remove_file(path).await;
-                        }
-                        if let Some(path) = &image.avif_path {
-                            let _ = tokio::fs::remove_file(path).await;
-                        }
-                        SyncedImage::delete_by_id(&self.pool, id).await?;
-                        result.removed += 1;
-                    }
+            let mut left = false;
+            for id in SyncedImage::member_ids(&self.pool, album_id).await? {
+                if remote_ids.contains(&id) {
+                    continue;
+                }
+                left = true;
+                if self.remove_from_album(&id, album_id).await? {
+                    result.removed += 1;
                 }
             }
+            if left {
+                let albums = BTreeSet::from([album_id.to_string()]);
+                manifest::refresh(&self.pool, &self.config, &albums).await;
+            }
+        }
+
+        // Failed downloads must show up in the next incremental listing again, and
+        // so must the assets a --since run left out
+        if result.failed == 0 && self.since.is_none() {
+            Album::set_last_sync(&self.pool, album_id, started_at).await?;
         }
 
         Ok(result)
     }
 
+    /// The album with its complete asset list. Large albums may come back with only
+    /// part of their assets inline; the rest are then paged in through the search.
+    async fn fetch_album(&self, album_id: &str) -> Result<AlbumResponse> {
+        let mut album = self.client.get_album(album_id).await?;
+        if album.assets.len() as i64 >= album.asset_count {
+            return Ok(album);
+        }
+
+        debug!(
+            "Album '{}' listed {} of {} assets inline, paging through the rest",
+            album.album_name,
+            album.assets.len(),
+            album.asset_count
+        );
+        match self.client.get_album_assets(album_id).await? {
+            Some(assets) if assets.len() > album.assets.len() => album.assets = assets,
+            Some(_) => {}
+            None => warn!(
+                "Album '{}' lists {} of {} assets and the server cannot page through the rest",
+                album.album_name,
+                album.assets.len(),
+                album.asset_count
+            ),
+        }
+        Ok(album)
+    }
+
+    /// List only the assets changed since the album's last complete sync, or `None`
+    /// when a full listing is needed.
+    ///
+    /// Adding an existing asset to an album does not bump its `updatedAt`, so a change
+    /// in the album's asset count also forces a full listing. Removal detection needs
+    /// the full list too, so incremental listing is off with `delete_removed`.
+    async fn list_changed_assets(
+        &self,
+        album_id: &str,
+        stored: Option<&Album>,
+    ) -> Result<Option<AlbumResponse>> {
+        if self.config.sync.delete_removed {
+            return Ok(None);
+        }
+        let Some((last_sync, stored_count)) = stored.and_then(|a| a.last_sync.zip(a.asset_count))
+        else {
+            return Ok(None);
+        };
+
+        let mut album = self.client.get_album_info(album_id).await?;
+        if album.asset_count != stored_count {
+            debug!(
+                "Album '{}' went from {} to {} assets, listing in full",
+                album.album_name, stored_count, album.asset_count
+            );
+            return Ok(None);
+        }
+
+        let since = last_sync - INCREMENTAL_OVERLAP;
+        match self
+            .client
+            .get_album_assets_updated_after(album_id, since)
+            .await?
+        {
+            Some(assets) => {
+                album.assets = assets;
+                Ok(Some(album))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Delete local albums missing from `remote_album_ids`, returning the number of
+    /// images removed with them
     async fn remove_stale_albums(&self, remote_album_ids: &HashSet<String>) -> Result<usize> {
         let local_albums = Album::get_all(&self.pool).await?;
         let mut removed = 0;
+        let mut pruned = BTreeSet::new();
+
+        // An empty listing more likely means a wrong key or user than that every
+        // album was deleted
+        if remote_album_ids.is_empty() && !local_albums.is_empty() {
+            warn!(
+                "Immich listed no albums, not pruning the {} local ones",
+                local_albums.len()
+            );
+            return Ok(0);
+        }
 
         for album in &local_albums {
             if remote_album_ids.contains(&album.id) {
@@ -165,59 +490,342 @@ impl SyncService {
 
             info!("Removing stale album: {} ({})", album.name, album.id);
 
-            let images = SyncedImage::get_by_album(&self.pool, &album.id).await?;
-            for image in &images {
-                if let Some(path) = &image.original_path {
-                    let _ = tokio::fs::remove_file(path).await;
-                }
-                if let Some(path) = &image.avif_path {
-                    let _ = tokio::fs::remove_file(path).await;
+            for id in SyncedImage::member_ids(&self.pool, &album.id).await? {
+                if self.remove_from_album(&id, &album.id).await? {
+                    removed += 1;
                 }
-                if let Some(path) = &image.thumbnail_path {
-                    let _ = tokio::fs::remove_file(path).await;
-                }
-                removed += 1;
             }
 
-            let original_dir = self.config.original_path().join(&album.id);
-            let avif_dir = self.config.avif_path().join(&album.id);
-            let _ = tokio::fs::remove_dir_all(&original_dir).await;
-            let _ = tokio::fs::remove_dir_all(&avif_dir).await;
-
-            SyncedImage::delete_by_album(&self.pool, &album.id).await?;
             Album::delete_by_id(&self.pool, &album.id).await?;
+            pruned.insert(album.id.clone());
+        }
+        manifest::refresh(&self.pool, &self.config, &pruned).await;
+
+        // Files went through their recorded paths above. Images that moved to another
+        // album still keep theirs in these directories, so only empty ones go.
+        for album_id in &pruned {
+            storage::remove_empty_dirs(&self.config.original_path().join(album_id)).await;
+            storage::remove_empty_dirs(&self.config.avif_path().join(album_id)).await;
         }
 
         Ok(removed)
     }
 
+    /// Take an image out of an album it was removed from. Its files and row go only
+    /// once it is in no album at all; returns whether that happened.
+    async fn remove_from_album(&self, id: &str, album_id: &str) -> Result<bool> {
+        let remaining = SyncedImage::remove_album_member(&self.pool, id, album_id).await?;
+        if !remaining.is_empty() {
+            debug!(
+                "Image {} left album {}, still in {} others",
+                id,
+                album_id,
+                remaining.len()
+            );
+            return Ok(false);
+        }
+        let Some(image) = SyncedImage::get_by_id(&self.pool, id).await? else {
+            return Ok(false);
+        };
+        debug!("Removing deleted image: {}", id);
+        self.discard_files(&image).await;
+        SyncedImage::delete_by_id(&self.pool, id).await?;
+        Ok(true)
+    }
+
+    /// Delete or trash a removed image's original, Immich thumbnail, AVIF, sidecar,
+    /// thumbnail and variants
+    async fn discard_files(&self, image: &SyncedImage) {
+        let mut paths: Vec<String> = image.variant_paths().into_values().collect();
+        if let Some(path) = &image.original_path {
+            let thumbnail = storage::immich_thumbnail_path(Path::new(path));
+            paths.push(path.clone());
+            paths.push(thumbnail.to_string_lossy().into_owned());
+        }
+        if let Some(avif) = &image.avif_path {
+            let sidecar = storage::sidecar_path(&self.config, Path::new(avif), &image.id);
+            paths.push(avif.clone());
+            paths.push(sidecar.to_string_lossy().into_owned());
+        }
+        paths.extend(image.thumbnail_path.clone());
+        for path in &paths {
+            storage::discard(&self.pool, &self.config, &image.id, path).await;
+        }
+    }
+
     fn needs_update(&self, _asset_id: &str, _checksum: &str, _existing: &HashSet<String>) -> bool {
         false
     }
 
-    async fn download_asset(&self, album_id: &str, asset: &AssetResponse) -> Result<bool> {
-        let original_dir = self.config.original_path();
-        let dest_path = original_dir.join(album_id).join(&asset.original_file_name);
+    /// Link the original from `source_library_path` into `dest`, returning its size,
+    /// or `None` when it is not available locally and has to be downloaded
+    async fn link_from_library(&self, asset: &AssetResponse, dest: &Path) -> Option<u64> {
+        let library = self.config.sync.source_library_path.as_deref()?;
+        let source = Self::locate_in_library(library, asset).await?;
+
+        // A link would leave the original readable, so it is copied in encrypted
+        let result = if encryption::enabled() {
+            Self::copy_encrypted(&source, dest).await
+        } else {
+            Self::link_file(&source, dest, self.config.sync.source_link_mode).await
+        };
+        match result {
+            Ok(size) => {
+                info!(
+                    "Linked from library: {} ({:?})",
+                    asset.original_file_name, source
+                );
+                Some(size)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to link {:?} from library, downloading instead: {}",
+                    source, e
+                );
+                None
+            }
+        }
+    }
 
-        if dest_path.exists() {
-            debug!("Skipping existing file: {:?}", dest_path);
-            return Ok(false);
+    /// Write an encrypted copy of `source` to `dest`, returning the plaintext size
+    async fn copy_encrypted(source: &Path, dest: &Path) -> std::io::Result<u64> {
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
         }
+        let plaintext = tokio::fs::read(source).await?;
+        let sealed = encryption::seal(&plaintext).map_err(std::io::Error::other)?;
+        let part_path = storage::part_path(dest);
+        tokio::fs::write(&part_path, &sealed).await?;
+        tokio::fs::rename(&part_path, dest).await?;
+        Ok(plaintext.len() as u64)
+    }
 
-        info!("Downloading: {}", asset.original_file_name);
-        let size = self.client.download_asset(&asset.id, &dest_path).await?;
+    /// Link `source` to `dest`, replacing whatever is there. Hardlinks fall back to a
+    /// copy across filesystems. Returns the size of the linked file.
+    async fn link_file(source: &Path, dest: &Path, mode: LinkMode) -> std::io::Result<u64> {
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        if tokio::fs::symlink_metadata(dest).await.is_ok() {
+            tokio::fs::remove_file(dest).await?;
+        }
+        match mode {
+            LinkMode::Symlink => {
+                // Relative targets would resolve against the link's own directory
+                let target = tokio::fs::canonicalize(source).await?;
+                tokio::fs::symlink(target, dest).await?
+            }
+            LinkMode::Hardlink => {
+                if tokio::fs::hard_link(source, dest).await.is_err() {
+                    let part_path = storage::part_path(dest);
+                    tokio::fs::copy(source, &part_path).await?;
+                    tokio::fs::rename(&part_path, dest).await?;
+                }
+            }
+        }
+        tokio::fs::metadata(dest).await.map(|m| m.len())
+    }
 
-        SyncedImage::upsert(
-            &self.pool,
-            &asset.id,
-            album_id,
-            &asset.original_file_name,
-            Some(&asset.checksum),
-            Some(dest_path.to_str().unwrap_or("")),
-            Some(size as i64),
-        )
-        .await?;
+    /// Hardlink the original of an already downloaded asset with the same checksum,
+    /// e.g. the same photo uploaded twice, instead of downloading it again
+    async fn link_duplicate(
+        &self,
+        asset: &AssetResponse,
+        dest: &Path,
+        pending: &PendingImages,
+    ) -> Result<Option<u64>> {
+        let stored = SyncedImage::get_by_checksum(&self.pool, &asset.checksum, &asset.id).await?;
+        let candidates = pending
+            .with_checksum(&asset.checksum)
+            .await
+            .into_iter()
+            .chain(stored.into_iter().map(|d| (d.id, d.original_path)));
+        for (duplicate_id, original_path) in candidates {
+            let Some(source) = original_path.as_deref().map(Path::new) else {
+                continue;
+            };
+            if !source.exists() {
+                continue;
+            }
+            match Self::link_file(source, dest, LinkMode::Hardlink).await {
+                Ok(size) => {
+                    info!(
+                        "Reusing original of duplicate {} for {}",
+                        duplicate_id, asset.original_file_name
+                    );
+                    return Ok(Some(size));
+                }
+                Err(e) => warn!("Failed to link duplicate {:?}: {}", source, e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find an asset's original under the library mount. Immich reports the path
+    /// inside its own container (e.g. `/usr/src/app/upload/library/...`), so ever
+    /// shorter suffixes of it are tried until one exists with the expected size.
+    async fn locate_in_library(library: &Path, asset: &AssetResponse) -> Option<PathBuf> {
+        let original = asset.original_path.as_deref()?;
+        let components: Vec<_> = Path::new(original)
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+
+        // Keep at least one directory, so a bare filename never matches the wrong file
+        for start in 0..components.len().saturating_sub(1) {
+            let candidate: PathBuf = library.join(components[start..].iter().collect::<PathBuf>());
+            let Ok(metadata) = tokio::fs::metadata(&candidate).await else {
+                continue;
+            };
+            if metadata.is_file()
+                && asset
+                    .file_size
+                    .is_none_or(|size| size as u64 == metadata.len())
+            {
+                return Some(candidate);
+            }
+        }
+
+        debug!(
+            "Original of {} not found in library: {}",
+            asset.id, original
+        );
+        None
+    }
+
+    async fn download_asset(
+        &self,
+        album_id: &str,
+        asset: &AssetResponse,
+        pending: &PendingImages,
+    ) -> Result<bool> {
+        if let Some(existing) = SyncedImage::get_by_id(&self.pool, &asset.id).await? {
+            if let Some(path) = existing.original_path.filter(|p| Path::new(p).exists()) {
+                debug!("Skipping already downloaded asset {}: {}", asset.id, path);
+                SyncedImage::add_album_members(
+                    &self.pool,
+                    album_id,
+                    std::slice::from_ref(&asset.id),
+                )
+                .await?;
+                return Ok(false);
+            }
+        }
+
+        let dest_path =
+            storage::original_dest(&self.config, album_id, &asset.id, &asset.original_file_name);
+
+        // Held until the row is queued, so a parallel download of the same content
+        // finds this one instead of fetching it again
+        let _guard = self.checksum_locks.lock(&asset.checksum).await;
+        let reused = match self.link_duplicate(asset, &dest_path, pending).await? {
+            Some(size) => Some(size),
+            None => self.link_from_library(asset, &dest_path).await,
+        };
+        let size = match reused {
+            Some(size) => {
+                metrics::incr("downloads.linked");
+                size
+            }
+            None => {
+                let needed = asset.file_size.unwrap_or(0).max(0) as u64;
+                storage::ensure_free_space(&self.config, needed)?;
+                info!("Downloading: {}", asset.original_file_name);
+                let size = self.client.download_asset(&asset.id, &dest_path).await?;
+                metrics::incr("downloads.completed");
+                metrics::count("downloads.bytes", size);
+                size
+            }
+        };
+
+        let image = NewSyncedImage {
+            id: asset.id.clone(),
+            album_id: album_id.to_string(),
+            filename: asset.original_file_name.clone(),
+            checksum: Some(asset.checksum.clone()),
+            original_path: Some(dest_path.to_str().unwrap_or("").to_string()),
+            file_size: Some(size as i64),
+            mime_type: asset.original_mime_type.clone(),
+            synced_at: Utc::now(),
+        };
+        pending.push(&self.pool, image).await;
+        if self.config.sync.immich_thumbnails {
+            self.download_immich_thumbnail(album_id, asset, &dest_path)
+                .await;
+        }
 
         Ok(true)
     }
+
+    /// Fetch Immich's thumbnail of the asset for conversions to start from. Only
+    /// logged on failure: the thumbnail is then made from the original.
+    async fn download_immich_thumbnail(
+        &self,
+        album_id: &str,
+        asset: &AssetResponse,
+        original: &Path,
+    ) {
+        let thumbnail_width = self.config.image.for_album(album_id).thumbnail_width;
+        let size = if thumbnail_width <= IMMICH_THUMBNAIL_WIDTH {
+            "thumbnail"
+        } else {
+            "preview"
+        };
+        let dest = storage::immich_thumbnail_path(original);
+        match self.client.download_thumbnail(&asset.id, size, &dest).await {
+            Ok(bytes) => metrics::count("downloads.thumbnail_bytes", bytes),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&dest).await;
+                warn!(
+                    "Failed to download Immich thumbnail of {}: {:#}",
+                    asset.original_file_name, e
+                );
+            }
+        }
+    }
+}
+
+/// Downloaded originals not yet in the database. Rows are written
+/// `UPSERT_BATCH_SIZE` at a time, so parallel downloads do not each wait for the
+/// SQLite write lock.
+#[derive(Default)]
+struct PendingImages(tokio::sync::Mutex<Vec<NewSyncedImage>>);
+
+impl PendingImages {
+    /// Queue a row, writing the batch once it is full. A failed write is retried with
+    /// the next batch or the final `flush`.
+    async fn push(&self, pool: &SqlitePool, image: NewSyncedImage) {
+        let mut rows = self.0.lock().await;
+        rows.push(image);
+        if rows.len() < UPSERT_BATCH_SIZE {
+            return;
+        }
+        match SyncedImage::upsert_many(pool, &rows).await {
+            Ok(()) => rows.clear(),
+            Err(e) => warn!(
+                "Failed to record {} downloads, will retry: {}",
+                rows.len(),
+                e
+            ),
+        }
+    }
+
+    /// Write whatever is queued
+    async fn flush(&self, pool: &SqlitePool) -> Result<()> {
+        let mut rows = self.0.lock().await;
+        if !rows.is_empty() {
+            SyncedImage::upsert_many(pool, &rows).await?;
+            rows.clear();
+        }
+        Ok(())
+    }
+
+    /// IDs and originals of queued rows with this checksum
+    async fn with_checksum(&self, checksum: &str) -> Vec<(String, Option<String>)> {
+        let rows = self.0.lock().await;
+        rows.iter()
+            .filter(|row| row.checksum.as_deref() == Some(checksum))
+            .map(|row| (row.id.clone(), row.original_path.clone()))
+            .collect()
+    }
 }